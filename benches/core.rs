@@ -0,0 +1,73 @@
+use cpu::block_cache::BlockCache;
+use cpu::instruction::Instruction;
+use cpu::{Cpu, PROGRAM_START};
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+
+/// Draws a 15 row sprite across the screen with some arithmetic in between,
+/// forever
+const BUSY_ROM: [u8; 31] = [
+    0xA2, 0x10, // LD I, 210
+    0xD0, 0x1F, // DRW V0, V1, F
+    0x70, 0x08, // ADD V0, 08
+    0x82, 0x34, // ADD V2, V3
+    0x73, 0x01, // ADD V3, 01
+    0x83, 0x25, // SUB V3, V2
+    0x12, 0x02, // JP 202
+    0x00, 0x00, //
+    0xFF, 0x81, 0xBD, 0xA5, 0xA5, 0xBD, 0x81, 0xFF, // sprite
+    0x18, 0x3C, 0x7E, 0xFF, 0x7E, 0x3C, 0x18,
+];
+
+/// Runs `opcode` over and over at the start of the program
+fn bench_opcode(c: &mut Criterion, name: &str, opcode: u16, setup: impl Fn(&mut Cpu)) {
+    let mut cpu = Cpu::new(&opcode.to_be_bytes(), 0);
+    setup(&mut cpu);
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            cpu.position_in_memory = PROGRAM_START;
+            cpu.step().unwrap();
+        })
+    });
+}
+
+fn decode(c: &mut Criterion) {
+    c.bench_function("decode all opcodes", |b| {
+        b.iter(|| {
+            for opcode in 0..=u16::MAX {
+                black_box(Instruction::decode(black_box(opcode)));
+            }
+        })
+    });
+}
+
+fn alu(c: &mut Criterion) {
+    bench_opcode(c, "ADD Vx, kk", 0x7A01, |_| {});
+    bench_opcode(c, "ADD Vx, Vy", 0x8AB4, |_| {});
+    bench_opcode(c, "SUB Vx, Vy", 0x8AB5, |_| {});
+    bench_opcode(c, "XOR Vx, Vy", 0x8AB3, |_| {});
+    bench_opcode(c, "SHL Vx", 0x8ABE, |_| {});
+}
+
+fn draw(c: &mut Criterion) {
+    let sprite = |cpu: &mut Cpu| {
+        cpu.register_i = 0x300;
+        cpu.memory[0x300..0x30F].fill(0xAA);
+    };
+    bench_opcode(c, "DRW 1 row", 0xD011, sprite);
+    bench_opcode(c, "DRW 15 rows", 0xD01F, sprite);
+}
+
+fn frame(c: &mut Criterion) {
+    let mut cpu = Cpu::new(&BUSY_ROM, 0);
+    c.bench_function("frame of a busy ROM", |b| b.iter(|| cpu.run_frame()));
+
+    let mut cpu = Cpu::new(&BUSY_ROM, 0);
+    let mut cache = BlockCache::default();
+    c.bench_function("frame of a busy ROM, block cache", |b| {
+        b.iter(|| cache.run_frame(&mut cpu))
+    });
+}
+
+criterion_group!(benches, decode, alu, draw, frame);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+//! Runs arbitrary bytes as a ROM. Whatever the program does, the core has
+//! to report it as a `Fault` instead of panicking.
+
+#![no_main]
+
+use cpu::{Cpu, MAX_PROGRAM_SIZE, Quirks};
+use libfuzzer_sys::fuzz_target;
+
+/// Frames run per input
+const FRAMES: usize = 1000;
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte picks the quirks and the next two the keys held down
+    let [quirks, keys_high, keys_low, rom @ ..] = data else {
+        return;
+    };
+    let mut cpu = Cpu::new(&rom[..rom.len().min(MAX_PROGRAM_SIZE)], 0);
+    cpu.quirks = Quirks {
+        shift: quirks & 1 != 0,
+        jump: quirks & 2 != 0,
+        vf_reset: quirks & 4 != 0,
+    };
+    let keys = u16::from_be_bytes([*keys_high, *keys_low]);
+    cpu.keypad = std::array::from_fn(|key| keys & 1 << key != 0);
+
+    for _ in 0..FRAMES {
+        if cpu.halted || cpu.run_frame().is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,59 @@
+/* The CHIP8 machine, without a window, for frontends that draw the screen
+ * and read the keys themselves. Build the library with `cargo build
+ * --release` and link target/release/libcpu.so (libcpu.dylib on macOS,
+ * cpu.dll on Windows). */
+
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CHIP8_WIDTH 64
+#define CHIP8_HEIGHT 32
+
+#define CHIP8_OK 0
+/* The program halted, or ran out of instructions to execute */
+#define CHIP8_HALTED 1
+/* The program did something the machine can't carry out */
+#define CHIP8_FAULT (-1)
+#define CHIP8_ROM_TOO_LARGE (-2)
+
+typedef struct Chip8 Chip8;
+
+/* A machine with empty memory, its random numbers decided by `seed` */
+Chip8 *chip8_create(uint64_t seed);
+void chip8_destroy(Chip8 *chip8);
+
+/* Starts the machine over with `len` bytes of program, at most 3584,
+ * keeping the speed */
+int32_t chip8_load_rom(Chip8 *chip8, const uint8_t *rom, size_t len);
+
+/* Executes one instruction, without ticking the timers */
+int32_t chip8_step(Chip8 *chip8);
+/* Executes a frame's worth of instructions and ticks the timers, to be
+ * called 60 times a second */
+int32_t chip8_run_frame(Chip8 *chip8);
+/* Instructions per frame, 10 by default */
+void chip8_set_speed(Chip8 *chip8, size_t speed);
+
+/* Writes CHIP8_WIDTH * CHIP8_HEIGHT bytes to `pixels`, 1 for on and 0 for
+ * off, row by row from the top left */
+void chip8_framebuffer(const Chip8 *chip8, uint8_t *pixels);
+/* Holds key `key` (0 to 15) down or lets go of it */
+void chip8_set_key(Chip8 *chip8, uint8_t key, bool down);
+/* Sets the whole keypad at once, key n in bit n */
+void chip8_set_keys(Chip8 *chip8, uint16_t keys);
+/* Whether the buzzer sounds */
+bool chip8_sound(const Chip8 *chip8);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
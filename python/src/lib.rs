@@ -0,0 +1,200 @@
+//! The `chip8` Python module: the machine without a window, stepped by the
+//! caller and its screen read as a numpy array, to train agents on games.
+//!
+//! ```python
+//! import chip8
+//!
+//! machine = chip8.Chip8(seed=1)
+//! machine.set_platform("chip8")
+//! machine.load(open("pong.ch8", "rb").read())
+//! machine.set_keys(0b10)
+//! machine.run_frame()
+//! machine.framebuffer()  # uint8 array of shape (32, 64)
+//! ```
+
+use cpu::{Cpu, MAX_PROGRAM_SIZE, Quirks};
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray2};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use std::collections::BTreeMap;
+
+/// A CHIP8 machine with empty memory until `load`
+#[pyclass(name = "Chip8")]
+struct Chip8 {
+    cpu: Cpu,
+    seed: u64,
+}
+
+fn run(result: Result<(), cpu::Fault>, cpu: &Cpu) -> PyResult<bool> {
+    result.map_err(|fault| PyRuntimeError::new_err(fault.to_string()))?;
+    Ok(cpu.can_run())
+}
+
+#[pymethods]
+impl Chip8 {
+    /// `seed` decides the random numbers, so that an episode can be replayed
+    #[new]
+    #[pyo3(signature = (seed = 0))]
+    fn new(seed: u64) -> Self {
+        Self {
+            cpu: Cpu::new(&[], seed),
+            seed,
+        }
+    }
+
+    /// Starts over with `rom` as the program, keeping the speed and quirks
+    fn load(&mut self, rom: &[u8]) -> PyResult<()> {
+        if rom.len() > MAX_PROGRAM_SIZE {
+            return Err(PyValueError::new_err(format!(
+                "the ROM is {} bytes, at most {} fit in memory",
+                rom.len(),
+                MAX_PROGRAM_SIZE
+            )));
+        }
+        let mut cpu = Cpu::new(rom, self.seed);
+        cpu.speed = self.cpu.speed;
+        cpu.quirks = self.cpu.quirks;
+        self.cpu = cpu;
+        Ok(())
+    }
+
+    /// Executes one instruction, returning whether the program can go on.
+    /// Raises `RuntimeError` if the program does something the machine
+    /// can't carry out.
+    fn step(&mut self) -> PyResult<bool> {
+        if !self.cpu.can_run() {
+            return Ok(false);
+        }
+        let result = self.cpu.step();
+        run(result, &self.cpu)
+    }
+
+    /// Executes a frame's worth of instructions and ticks the timers, as
+    /// `step` does
+    fn run_frame(&mut self) -> PyResult<bool> {
+        let result = self.cpu.run_frame();
+        run(result, &self.cpu)
+    }
+
+    /// The screen as a `(32, 64)` array of 0 and 1
+    fn framebuffer<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<u8>> {
+        let display = &self.cpu.display;
+        Array2::from_shape_fn((32, 64), |(row, col)| display.pixel(row, col) as u8).into_pyarray(py)
+    }
+
+    /// Holds key `key` (0 to 15) down or lets go of it
+    fn set_key(&mut self, key: usize, down: bool) -> PyResult<()> {
+        let held = self
+            .cpu
+            .keypad
+            .get_mut(key)
+            .ok_or_else(|| PyValueError::new_err(format!("there's no key {}", key)))?;
+        *held = down;
+        Ok(())
+    }
+
+    /// Sets the whole keypad at once, key `n` in bit `n`
+    fn set_keys(&mut self, keys: u16) {
+        self.cpu.keypad = std::array::from_fn(|key| keys & 1 << key != 0);
+    }
+
+    /// Instructions per frame, 10 by default
+    #[getter]
+    fn speed(&self) -> usize {
+        self.cpu.speed
+    }
+
+    #[setter]
+    fn set_speed(&mut self, speed: usize) {
+        self.cpu.speed = speed;
+    }
+
+    /// The quirks as a dict of `shift`, `jump` and `vf_reset`, SCHIP's by
+    /// default. Setting it changes the quirks the dict has.
+    #[getter]
+    fn quirks(&self) -> BTreeMap<&'static str, bool> {
+        let quirks = self.cpu.quirks;
+        BTreeMap::from([
+            ("shift", quirks.shift),
+            ("jump", quirks.jump),
+            ("vf_reset", quirks.vf_reset),
+        ])
+    }
+
+    #[setter]
+    fn set_quirks(&mut self, changes: BTreeMap<String, bool>) -> PyResult<()> {
+        let mut quirks = self.cpu.quirks;
+        for (name, on) in changes {
+            let quirk = match name.as_str() {
+                "shift" => &mut quirks.shift,
+                "jump" => &mut quirks.jump,
+                "vf_reset" => &mut quirks.vf_reset,
+                _ => return Err(PyValueError::new_err(format!("there's no quirk {}", name))),
+            };
+            *quirk = on;
+        }
+        self.cpu.quirks = quirks;
+        Ok(())
+    }
+
+    /// Uses the quirks programs for `platform` expect: chip8, schip or
+    /// xochip
+    fn set_platform(&mut self, platform: &str) -> PyResult<()> {
+        self.cpu.quirks = match platform {
+            "chip8" => Quirks::CHIP8,
+            "schip" => Quirks::SCHIP,
+            "xochip" => Quirks::XOCHIP,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "there's no platform {}",
+                    platform
+                )));
+            }
+        };
+        Ok(())
+    }
+
+    #[getter]
+    fn registers(&self) -> [u8; 16] {
+        self.cpu.registers
+    }
+
+    #[getter]
+    fn pc(&self) -> usize {
+        self.cpu.position_in_memory
+    }
+
+    #[getter]
+    fn halted(&self) -> bool {
+        !self.cpu.can_run()
+    }
+
+    /// Whether the buzzer sounds
+    #[getter]
+    fn sound(&self) -> bool {
+        self.cpu.sound_timer > 0
+    }
+
+    /// Memory from `addr`, to read scores and lives for rewards
+    fn peek(&self, addr: usize, len: usize) -> PyResult<Vec<u8>> {
+        self.cpu
+            .memory
+            .get(addr..addr.saturating_add(len))
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| PyValueError::new_err("the range is outside of memory"))
+    }
+
+    /// A copy of the machine, to go back to
+    fn __copy__(&self) -> Self {
+        Self {
+            cpu: self.cpu.clone(),
+            seed: self.seed,
+        }
+    }
+}
+
+#[pymodule]
+fn chip8(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Chip8>()
+}
@@ -0,0 +1,96 @@
+use anyhow::{Context, Result, anyhow};
+use cpu::MAX_PROGRAM_SIZE;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// Entries picked from an archive when `--entry` doesn't name one
+const ROM_EXTENSIONS: [&str; 3] = ["ch8", "sc8", "xo8"];
+
+pub fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+}
+
+/// Whether the archive entry `name` is `entry`, by its full path in the
+/// archive or just its file name
+fn matches(name: &str, entry: &str) -> bool {
+    name == entry || Path::new(name).file_name().is_some_and(|n| n == entry)
+}
+
+fn is_rom(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .is_some_and(|e| ROM_EXTENSIONS.iter().any(|r| e.eq_ignore_ascii_case(r)))
+}
+
+/// The name and contents of `entry` in the zip archive `bytes`, or of its
+/// first ROM. Only as much is read as fits in memory, plus a byte for the
+/// size check to fail on.
+pub fn extract(bytes: Vec<u8>, entry: Option<&str>) -> Result<(String, Vec<u8>)> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).context("Not a zip archive")?;
+    // in the order they're stored, skipping names that aren't valid text
+    let name = archive
+        .file_names()
+        .filter_map(|name| name.ok())
+        .find(|name| match entry {
+            Some(entry) => matches(name, entry),
+            None => is_rom(name),
+        })
+        .map(String::from)
+        .ok_or_else(|| match entry {
+            Some(entry) => anyhow!("No `{}` in the archive", entry),
+            None => anyhow!("No .ch8, .sc8 or .xo8 file in the archive"),
+        })?;
+
+    let mut program = Vec::new();
+    archive
+        .by_name(&name)?
+        .take(MAX_PROGRAM_SIZE as u64 + 1)
+        .read_to_end(&mut program)
+        .with_context(|| format!("Couldn't unpack `{}`", name))?;
+    Ok((name, program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::{SimpleFileOptions, ZipWriter};
+
+    fn archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in entries {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn picks_the_first_rom_or_the_named_entry() {
+        let bytes = archive(&[
+            ("readme.txt", b"hello"),
+            ("games/pong.ch8", &[0x12, 0x00]),
+            ("games/tetris.sc8", &[0x00, 0xE0]),
+        ]);
+        let (name, program) = extract(bytes.clone(), None).unwrap();
+        assert_eq!(
+            (name.as_str(), program),
+            ("games/pong.ch8", vec![0x12, 0x00])
+        );
+
+        let (name, _) = extract(bytes.clone(), Some("tetris.sc8")).unwrap();
+        assert_eq!(name, "games/tetris.sc8");
+        let (name, _) = extract(bytes.clone(), Some("readme.txt")).unwrap();
+        assert_eq!(name, "readme.txt");
+
+        let error = extract(bytes, Some("missing.ch8")).unwrap_err();
+        assert_eq!(error.to_string(), "No `missing.ch8` in the archive");
+        let error = extract(archive(&[("readme.txt", b"hello")]), None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No .ch8, .sc8 or .xo8 file in the archive"
+        );
+    }
+}
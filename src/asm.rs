@@ -0,0 +1,919 @@
+use crate::PROGRAM_START;
+use crate::symbols::Symbols;
+use anyhow::{Result, anyhow, bail};
+use std::collections::HashMap;
+
+/// A word of the source and the line it is on
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+/// Splits the source into words, dropping `#` comments
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    source
+        .lines()
+        .enumerate()
+        .flat_map(|(n, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_whitespace()
+                .map(move |text| Token { text, line: n + 1 })
+        })
+        .collect()
+}
+
+/// A comparison in `if` and `while`
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+/// An open `if ... begin`, `else` or `loop` block
+enum Block {
+    /// The jump over the `if` body to patch at `else` or `end`
+    If { jump: usize },
+    /// The jump over the `else` body to patch at `end`
+    Else { jump: usize },
+    /// Where `again` jumps back to, and the jumps out of the loop each
+    /// `while` emitted
+    Loop { start: u16, exits: Vec<usize> },
+}
+
+/// A `:macro`, expanded in place of its name with the parameters replaced
+/// by the words following it
+#[derive(Clone)]
+struct Macro<'a> {
+    params: Vec<&'a str>,
+    body: Vec<Token<'a>>,
+}
+
+/// Expansions of macros and `:repeat` after which the source is assumed to
+/// expand forever
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// Assembles Octo source into a ROM loaded at `PROGRAM_START`, and the
+/// labels as symbols. The ROM starts with a jump to the `main` label.
+pub fn assemble(source: &str) -> Result<(Vec<u8>, Symbols)> {
+    let mut asm = Assembler {
+        tokens: tokenize(source),
+        pos: 0,
+        rom: Vec::new(),
+        here: PROGRAM_START,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        macros: HashMap::new(),
+        expansions: 0,
+        conditionals: 0,
+    };
+    // the jump to main
+    asm.emit_address(0x1000, "main", 0)?;
+    while asm.pos < asm.tokens.len() {
+        asm.statement()?;
+    }
+    asm.finish()
+}
+
+struct Assembler<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    /// The ROM from `PROGRAM_START` on
+    rom: Vec<u8>,
+    /// Address the next byte goes to
+    here: usize,
+    labels: HashMap<&'a str, u16>,
+    constants: HashMap<&'a str, i64>,
+    aliases: HashMap<&'a str, u8>,
+    /// Opcodes whose address is a label defined later: the ROM offset of
+    /// the opcode, the label and the line it was used on
+    fixups: Vec<(usize, &'a str, usize)>,
+    blocks: Vec<Block>,
+    macros: HashMap<&'a str, Macro<'a>>,
+    expansions: usize,
+    /// `:if` and `:else` branches being assembled
+    conditionals: usize,
+}
+
+impl<'a> Assembler<'a> {
+    fn next(&mut self) -> Result<Token<'a>> {
+        let token = self.tokens.get(self.pos).copied().ok_or_else(|| {
+            let line = self.tokens.last().map_or(1, |t| t.line);
+            anyhow!("line {}: unexpected end of the source", line)
+        })?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|t| t.text)
+    }
+
+    /// Consumes the next token, which has to be `text`
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let token = self.next()?;
+        if token.text != text {
+            bail!(
+                "line {}: expected `{}`, found `{}`",
+                token.line,
+                text,
+                token.text
+            );
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<()> {
+        let token = self.next()?;
+        let line = token.line;
+        match token.text {
+            ":" => {
+                let name = self.name()?;
+                if self.labels.insert(name, self.here as u16).is_some() {
+                    bail!("line {}: `{}` is defined twice", line, name);
+                }
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.next()?;
+                let value = self.value(value)?;
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut params = Vec::new();
+                while self.peek() != Some("{") {
+                    params.push(self.name()?);
+                }
+                let body = self.braces()?;
+                self.macros.insert(name, Macro { params, body });
+            }
+            ":repeat" => {
+                let (count, _) = self.number()?;
+                let body = self.braces()?;
+                if count < 0 {
+                    bail!("line {}: can't repeat {} times", line, count);
+                }
+                let body = body.repeat(count as usize);
+                self.expand(body, line)?;
+            }
+            ":if" | ":unless" => {
+                let (value, _) = self.number()?;
+                if (value != 0) == (token.text == ":if") || self.skip_branch(line, true)? {
+                    self.conditionals += 1;
+                }
+            }
+            ":else" => {
+                if self.conditionals == 0 {
+                    bail!("line {}: `:else` without `:if`", line);
+                }
+                self.conditionals -= 1;
+                self.skip_branch(line, false)?;
+            }
+            ":end" => {
+                if self.conditionals == 0 {
+                    bail!("line {}: `:end` without `:if`", line);
+                }
+                self.conditionals -= 1;
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":byte" => {
+                let value = self.byte()?;
+                self.emit_bytes(&[value]);
+            }
+            ":org" => {
+                let addr = self.address()?;
+                if (addr as usize) < PROGRAM_START {
+                    bail!("line {}: :org below {:03X}", line, PROGRAM_START);
+                }
+                self.here = addr as usize;
+            }
+            ":call" => self.address_operand(0x2000)?,
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n)
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n)
+            }
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "jump" => self.address_operand(0x1000)?,
+            "jump0" => self.address_operand(0xB000)?,
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n)
+            }
+            "bcd" => self.register_op(0xF033)?,
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    // XO-CHIP ranges
+                    self.pos += 1;
+                    let y = self.register()?;
+                    let op = if token.text == "save" { 0x5002 } else { 0x5003 };
+                    self.emit(op | (x as u16) << 8 | (y as u16) << 4);
+                } else {
+                    let op = if token.text == "save" { 0xF055 } else { 0xF065 };
+                    self.emit(op | (x as u16) << 8);
+                }
+            }
+            "saveflags" => self.register_op(0xF075)?,
+            "loadflags" => self.register_op(0xF085)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8)
+            }
+            "audio" => self.emit(0xF002),
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let op = match token.text {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.register_op(op)?
+            }
+            "i" => self.i_statement()?,
+            "if" => self.if_statement()?,
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump }) => {
+                    let exit = self.emit_jump();
+                    self.patch(jump);
+                    self.blocks.push(Block::Else { jump: exit });
+                }
+                _ => bail!("line {}: `else` without `if ... begin`", line),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump } | Block::Else { jump }) => self.patch(jump),
+                _ => bail!("line {}: `end` without `if ... begin`", line),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here as u16,
+                exits: Vec::new(),
+            }),
+            "while" => {
+                let condition = self.condition()?;
+                let skip = self.skip(condition, false);
+                self.emit(skip);
+                let exit = self.emit_jump();
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|b| matches!(b, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { exits, .. }) => exits.push(exit),
+                    _ => bail!("line {}: `while` outside of a loop", line),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits }) => {
+                    self.emit(0x1000 | start);
+                    for exit in exits {
+                        self.patch(exit);
+                    }
+                }
+                _ => bail!("line {}: `again` without `loop`", line),
+            },
+            text if self.is_register(text) => {
+                self.pos -= 1;
+                self.register_statement()?;
+            }
+            text if text.starts_with(|c: char| c.is_ascii_digit() || c == '-') => {
+                self.pos -= 1;
+                let value = self.byte()?;
+                self.emit_bytes(&[value]);
+            }
+            name if self.macros.contains_key(name) => {
+                let Macro { params, body } = self.macros[name].clone();
+                let mut args = HashMap::new();
+                for param in params {
+                    args.insert(param, self.next()?);
+                }
+                let body = body
+                    .into_iter()
+                    .map(|token| args.get(token.text).copied().unwrap_or(token))
+                    .collect();
+                self.expand(body, line)?;
+            }
+            // a bare label calls it
+            name => self.emit_address(0x2000, name, line)?,
+        }
+        Ok(())
+    }
+
+    /// `i := nnn`, `i := hex vx`, `i := bighex vx`, `i := long nnnn` and
+    /// `i += vx`
+    fn i_statement(&mut self) -> Result<()> {
+        let op = self.next()?;
+        match op.text {
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.pos += 1;
+                    self.register_op(0xF029)
+                }
+                Some("bighex") => {
+                    self.pos += 1;
+                    self.register_op(0xF030)
+                }
+                Some("long") => {
+                    self.pos += 1;
+                    let (value, line) = self.number()?;
+                    if !(0..=0xFFFF).contains(&value) {
+                        bail!("line {}: {} doesn't fit in 16 bits", line, value);
+                    }
+                    self.emit(0xF000);
+                    self.emit(value as u16);
+                    Ok(())
+                }
+                _ => self.address_operand(0xA000),
+            },
+            "+=" => self.register_op(0xF01E),
+            _ => bail!("line {}: unknown operator `{}` for i", op.line, op.text),
+        }
+    }
+
+    /// `vx <op> ...`
+    fn register_statement(&mut self) -> Result<()> {
+        let x = self.register()? as u16;
+        let op = self.next()?;
+        let line = op.line;
+
+        // operators only taking a register
+        let alu = match op.text {
+            "|=" => Some(0x1),
+            "&=" => Some(0x2),
+            "^=" => Some(0x3),
+            "-=" if self.peek().is_some_and(|t| self.is_register(t)) => Some(0x5),
+            ">>=" => Some(0x6),
+            "=-" => Some(0x7),
+            "<<=" => Some(0xE),
+            _ => None,
+        };
+        if let Some(n) = alu {
+            let y = self.register()? as u16;
+            self.emit(0x8000 | x << 8 | y << 4 | n);
+            return Ok(());
+        }
+
+        match op.text {
+            ":=" => match self.peek() {
+                Some("random") => {
+                    self.pos += 1;
+                    let kk = self.byte()?;
+                    self.emit(0xC000 | x << 8 | kk as u16)
+                }
+                Some("delay") => {
+                    self.pos += 1;
+                    self.emit(0xF007 | x << 8)
+                }
+                Some("key") => {
+                    self.pos += 1;
+                    self.emit(0xF00A | x << 8)
+                }
+                _ => match self.operand()? {
+                    Operand::Register(y) => self.emit(0x8000 | x << 8 | (y as u16) << 4),
+                    Operand::Byte(kk) => self.emit(0x6000 | x << 8 | kk as u16),
+                },
+            },
+            "+=" => match self.operand()? {
+                Operand::Register(y) => self.emit(0x8004 | x << 8 | (y as u16) << 4),
+                Operand::Byte(kk) => self.emit(0x7000 | x << 8 | kk as u16),
+            },
+            "-=" => {
+                let kk = self.byte()?;
+                self.emit(0x7000 | x << 8 | kk.wrapping_neg() as u16)
+            }
+            _ => bail!("line {}: unknown operator `{}`", line, op.text),
+        }
+        Ok(())
+    }
+
+    fn if_statement(&mut self) -> Result<()> {
+        let condition = self.condition()?;
+        let token = self.next()?;
+        match token.text {
+            "then" => {
+                let skip = self.skip(condition, true);
+                self.emit(skip);
+            }
+            "begin" => {
+                let skip = self.skip(condition, false);
+                self.emit(skip);
+                let jump = self.emit_jump();
+                self.blocks.push(Block::If { jump });
+            }
+            _ => bail!(
+                "line {}: expected `then` or `begin`, found `{}`",
+                token.line,
+                token.text
+            ),
+        }
+        Ok(())
+    }
+
+    /// `vx == n`, `vx != vy`, `vx key`, `vx -key`, ...
+    fn condition(&mut self) -> Result<Condition> {
+        let x = self.register()?;
+        let op = self.next()?;
+        Ok(match op.text {
+            "==" => Condition::Equal(x, self.operand()?),
+            "!=" => Condition::NotEqual(x, self.operand()?),
+            "key" => Condition::Key(x),
+            "-key" => Condition::NotKey(x),
+            _ => bail!("line {}: unknown comparison `{}`", op.line, op.text),
+        })
+    }
+
+    /// The skip taken when `condition` holds, or when it doesn't with
+    /// `negate`
+    fn skip(&self, condition: Condition, negate: bool) -> u16 {
+        let (condition, negate) = match condition {
+            Condition::NotEqual(x, operand) => (Condition::Equal(x, operand), !negate),
+            Condition::NotKey(x) => (Condition::Key(x), !negate),
+            condition => (condition, negate),
+        };
+        let x = match condition {
+            Condition::Equal(x, _) | Condition::Key(x) => (x as u16) << 8,
+            _ => unreachable!(),
+        };
+        match (condition, negate) {
+            (Condition::Equal(_, Operand::Byte(kk)), false) => 0x3000 | x | kk as u16,
+            (Condition::Equal(_, Operand::Byte(kk)), true) => 0x4000 | x | kk as u16,
+            (Condition::Equal(_, Operand::Register(y)), false) => 0x5000 | x | (y as u16) << 4,
+            (Condition::Equal(_, Operand::Register(y)), true) => 0x9000 | x | (y as u16) << 4,
+            (_, false) => 0xE09E | x,
+            (_, true) => 0xE0A1 | x,
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str> {
+        let token = self.next()?;
+        let valid = token
+            .text
+            .starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && !self.is_register(token.text);
+        if !valid {
+            bail!("line {}: `{}` isn't a valid name", token.line, token.text);
+        }
+        Ok(token.text)
+    }
+
+    fn is_register(&self, text: &str) -> bool {
+        parse_register(text).is_some() || self.aliases.contains_key(text)
+    }
+
+    fn register(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        parse_register(token.text)
+            .or_else(|| self.aliases.get(token.text).copied())
+            .ok_or_else(|| anyhow!("line {}: `{}` isn't a register", token.line, token.text))
+    }
+
+    /// A register or a byte
+    fn operand(&mut self) -> Result<Operand> {
+        if self.peek().is_some_and(|t| self.is_register(t)) {
+            return Ok(Operand::Register(self.register()?));
+        }
+        Ok(Operand::Byte(self.byte()?))
+    }
+
+    /// `opcode` with a register in x
+    fn register_op(&mut self, opcode: u16) -> Result<()> {
+        let x = self.register()? as u16;
+        self.emit(opcode | x << 8);
+        Ok(())
+    }
+
+    /// A number, a constant or a label defined earlier
+    fn value(&self, token: Token) -> Result<i64> {
+        if let Some(value) = parse_number(token.text) {
+            return Ok(value);
+        }
+        if let Some(&value) = self.constants.get(token.text) {
+            return Ok(value);
+        }
+        if let Some(&addr) = self.labels.get(token.text) {
+            return Ok(addr as i64);
+        }
+        bail!("line {}: unknown value `{}`", token.line, token.text)
+    }
+
+    /// A value, or a `:calc` expression in braces, and the line it is on
+    fn number(&mut self) -> Result<(i64, usize)> {
+        let token = self.next()?;
+        if token.text == "{" {
+            self.pos -= 1;
+            return Ok((self.calc()?, token.line));
+        }
+        Ok((self.value(token)?, token.line))
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let (value, line) = self.number()?;
+        if !(-128..=255).contains(&value) {
+            bail!("line {}: {} doesn't fit in a byte", line, value);
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u16> {
+        let (value, line) = self.number()?;
+        if !(0..=15).contains(&value) {
+            bail!("line {}: {} doesn't fit in a nibble", line, value);
+        }
+        Ok(value as u16)
+    }
+
+    /// An address known at this point
+    fn address(&mut self) -> Result<u16> {
+        let (value, line) = self.number()?;
+        if !(0..=0xFFF).contains(&value) {
+            bail!("line {}: {} isn't a 12 bit address", line, value);
+        }
+        Ok(value as u16)
+    }
+
+    /// `opcode` with the address in the next token, which may be a label
+    /// defined later
+    fn address_operand(&mut self, opcode: u16) -> Result<()> {
+        let token = self.next()?;
+        let known = token.text == "{"
+            || parse_number(token.text).is_some()
+            || self.constants.contains_key(token.text);
+        if known {
+            self.pos -= 1;
+            let addr = self.address()?;
+            self.emit(opcode | addr);
+            return Ok(());
+        }
+        self.emit_address(opcode, token.text, token.line)
+    }
+
+    /// The words between `{` and the matching `}`
+    fn braces(&mut self) -> Result<Vec<Token<'a>>> {
+        self.expect("{")?;
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match token.text {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    /// Assembles `tokens` next, in place of a macro or `:repeat`
+    fn expand(&mut self, tokens: Vec<Token<'a>>, line: usize) -> Result<()> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            bail!("line {}: macros expand forever", line);
+        }
+        self.tokens.splice(self.pos..self.pos, tokens);
+        Ok(())
+    }
+
+    /// Skips a branch of `:if` not taken, up to its `:end` or, with
+    /// `to_else`, its `:else`. Returns whether it stopped at `:else`.
+    fn skip_branch(&mut self, line: usize, to_else: bool) -> Result<bool> {
+        let mut depth = 0;
+        while let Some(text) = self.peek() {
+            self.pos += 1;
+            match text {
+                ":if" | ":unless" => depth += 1,
+                ":end" if depth == 0 => return Ok(false),
+                ":end" => depth -= 1,
+                ":else" if depth == 0 && to_else => return Ok(true),
+                _ => {}
+            }
+        }
+        bail!("line {}: `:if` without `:end`", line)
+    }
+
+    /// A `:calc` expression in braces. Like in Octo, operators have no
+    /// precedence and are applied from right to left, so `2 * 3 + 1` is 8.
+    fn calc(&mut self) -> Result<i64> {
+        self.expect("{")?;
+        let value = self.expression()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn expression(&mut self) -> Result<i64> {
+        let left = self.term()?;
+        if matches!(self.peek(), Some("}" | ")")) {
+            return Ok(left);
+        }
+        let op = self.next()?;
+        let right = self.expression()?;
+        let value = match op.text {
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" => {
+                let value = match op.text {
+                    "/" => left.checked_div(right),
+                    _ => left.checked_rem(right),
+                };
+                value.ok_or_else(|| anyhow!("line {}: division by zero", op.line))?
+            }
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "<<" => left.checked_shl(right as u32).unwrap_or(0),
+            ">>" => left.checked_shr(right as u32).unwrap_or(0),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64,
+            "<=" => (left <= right) as i64,
+            ">" => (left > right) as i64,
+            ">=" => (left >= right) as i64,
+            "==" => (left == right) as i64,
+            "!=" => (left != right) as i64,
+            _ => bail!("line {}: unknown operator `{}`", op.line, op.text),
+        };
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<i64> {
+        let token = self.next()?;
+        Ok(match token.text {
+            "(" => {
+                let value = self.expression()?;
+                self.expect(")")?;
+                value
+            }
+            "-" => self.term()?.wrapping_neg(),
+            "~" => !self.term()?,
+            "!" => (self.term()? == 0) as i64,
+            "HERE" => self.here as i64,
+            _ => self.value(token)?,
+        })
+    }
+
+    /// `opcode` with the address of the label `name`, patched in `finish`
+    fn emit_address(&mut self, opcode: u16, name: &'a str, line: usize) -> Result<()> {
+        if self.is_register(name) {
+            bail!("line {}: expected an address, found `{}`", line, name);
+        }
+        self.fixups.push((self.here - PROGRAM_START, name, line));
+        self.emit(opcode);
+        Ok(())
+    }
+
+    /// A jump to be patched later, returning its ROM offset
+    fn emit_jump(&mut self) -> usize {
+        let offset = self.here - PROGRAM_START;
+        self.emit(0x1000);
+        offset
+    }
+
+    /// Points the jump at `offset` to the current address
+    fn patch(&mut self, offset: usize) {
+        let opcode = 0x1000 | self.here as u16;
+        self.rom[offset..offset + 2].copy_from_slice(&opcode.to_be_bytes());
+    }
+
+    fn emit(&mut self, opcode: u16) {
+        self.emit_bytes(&opcode.to_be_bytes());
+    }
+
+    fn emit_bytes(&mut self, bytes: &[u8]) {
+        let offset = self.here - PROGRAM_START;
+        if self.rom.len() < offset + bytes.len() {
+            self.rom.resize(offset + bytes.len(), 0);
+        }
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.here += bytes.len();
+    }
+
+    fn finish(mut self) -> Result<(Vec<u8>, Symbols)> {
+        if let Some(block) = self.blocks.last() {
+            let open = match block {
+                Block::If { .. } | Block::Else { .. } => "if ... begin",
+                Block::Loop { .. } => "loop",
+            };
+            bail!("`{}` without `end` or `again`", open);
+        }
+        if self.conditionals > 0 {
+            bail!("`:if` without `:end`");
+        }
+        for &(offset, name, line) in &self.fixups {
+            let addr = match self.labels.get(name) {
+                Some(&addr) => addr,
+                None if name == "main" => bail!("The program has no `: main` label"),
+                None => bail!("line {}: unknown label `{}`", line, name),
+            };
+            if addr > 0xFFF {
+                bail!("line {}: `{}` is above 0xFFF", line, name);
+            }
+            self.rom[offset + 1] |= addr as u8;
+            self.rom[offset] |= (addr >> 8) as u8;
+        }
+        if PROGRAM_START + self.rom.len() > 0x1000 {
+            bail!("The program doesn't fit in memory");
+        }
+        let mut symbols = Symbols::default();
+        for (&name, &addr) in &self.labels {
+            symbols.insert(addr as usize, name);
+        }
+        Ok((self.rom, symbols))
+    }
+}
+
+/// `v0` to `vF`, in either case
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix(['v', 'V'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+/// Decimal, `0x` hex and `0b` binary numbers, possibly negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The opcodes `source` assembles to, after the jump to main
+    fn opcodes(source: &str) -> Vec<u16> {
+        let (rom, _) = assemble(&format!(": main\n{}", source)).unwrap();
+        assert_eq!(&rom[..2], [0x12, 0x02]);
+        rom[2..]
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn statements() {
+        let source = "
+            clear return ;
+            jump 0x300 jump0 0x300 :call 0x300
+            v1 := 0x2A v1 := v2 v1 += 1 v1 += v2 v1 -= v2 v1 -= 1 v1 =- v2
+            v1 |= v2 v1 &= v2 v1 ^= v2 v1 >>= v2 v1 <<= v2
+            v1 := random 0xF0 v1 := delay v1 := key delay := v1 buzzer := v1
+            i := 0x300 i += v1 i := hex v1 bcd v1 save v1 load v1
+            sprite v1 v2 5
+        ";
+        assert_eq!(
+            opcodes(source),
+            [
+                0x00E0, 0x00EE, 0x00EE, 0x1300, 0xB300, 0x2300, 0x612A, 0x8120, 0x7101, 0x8124,
+                0x8125, 0x71FF, 0x8127, 0x8121, 0x8122, 0x8123, 0x8126, 0x812E, 0xC1F0, 0xF107,
+                0xF10A, 0xF115, 0xF118, 0xA300, 0xF11E, 0xF129, 0xF133, 0xF155, 0xF165, 0xD125,
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_constants_and_data() {
+        let source = "
+            :const LIVES 3
+            :alias lives v4
+            lives := LIVES  # comment
+            i := sprite
+            draw
+            : draw
+            return
+            : sprite
+            0xFF 0b10000001 :byte -1
+        ";
+        assert_eq!(
+            assemble(&format!(": main\n{}", source)).unwrap().0,
+            [
+                0x12, 0x02, 0x64, 0x03, 0xA2, 0x0A, 0x22, 0x08, 0x00, 0xEE, 0xFF, 0x81, 0xFF
+            ]
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(
+            opcodes("if v1 == 2 then v1 := 0 if v1 != v2 then clear if v3 key then clear"),
+            [0x4102, 0x6100, 0x5120, 0x00E0, 0xE3A1, 0x00E0]
+        );
+        // 202: skip into the body, 204: jump to else, 208: jump to end
+        assert_eq!(
+            opcodes("if v1 -key begin clear else return end"),
+            [0xE1A1, 0x120A, 0x00E0, 0x120C, 0x00EE]
+        );
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(
+            opcodes("loop v1 += 1 while v1 != 10 again clear"),
+            [0x7101, 0x410A, 0x120A, 0x1202, 0x00E0]
+        );
+    }
+
+    #[test]
+    fn labels_become_symbols() {
+        let (_, symbols) = assemble(": main\nloop draw again\n: draw return").unwrap();
+        assert_eq!(symbols.to_string(), "0202 main\n0206 draw\n");
+        assert_eq!(Symbols::parse(&symbols.to_string()).unwrap(), symbols);
+    }
+
+    #[test]
+    fn macros_and_repeat() {
+        let source = "
+            :macro move register amount { register += amount }
+            move v1 2
+            :repeat 3 { move v2 -1 }
+        ";
+        assert_eq!(opcodes(source), [0x7102, 0x72FF, 0x72FF, 0x72FF]);
+    }
+
+    #[test]
+    fn calc() {
+        let source = "
+            :const WIDTH 64
+            :calc CENTER { ( WIDTH / 2 ) - 4 }
+            :calc EIGHT { 2 * 3 + 1 }
+            v1 := CENTER
+            v2 := EIGHT
+            :byte { HERE & 0xFF }
+            :byte { ( 1 << 4 ) | 1 }
+        ";
+        assert_eq!(
+            assemble(&format!(": main\n{}", source)).unwrap().0,
+            [0x12, 0x02, 0x61, 0x1C, 0x62, 0x08, 0x06, 0x11]
+        );
+    }
+
+    #[test]
+    fn conditional_assembly() {
+        let source = "
+            :const DEBUG 0
+            :if DEBUG v1 := 1 :else v1 := 2 :if DEBUG :else :end :end
+            :unless DEBUG v2 := 3 :end
+            :if { DEBUG == 0 } v3 := 4 :else v3 := 5 :end
+        ";
+        assert_eq!(opcodes(source), [0x6102, 0x6203, 0x6304]);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let error = assemble(": main\nv1 := 0x100").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: 256 doesn't fit in a byte");
+
+        let error = assemble(": main\n\njump nowhere").unwrap_err();
+        assert_eq!(error.to_string(), "line 3: unknown label `nowhere`");
+
+        let error = assemble(": main\n:calc X { 1 / 0 }").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: division by zero");
+
+        let error = assemble(": main\n:macro forever { forever }\nforever").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: macros expand forever");
+
+        let error = assemble("clear").unwrap_err();
+        assert_eq!(error.to_string(), "The program has no `: main` label");
+    }
+}
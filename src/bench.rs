@@ -0,0 +1,49 @@
+use anyhow::Result;
+use cpu::Cpu;
+use std::time::{Duration, Instant};
+
+/// Runs frames with `run_frame` back to back without a window for
+/// `duration` and prints the instructions per second and frame times
+pub fn run(
+    cpu: &mut Cpu,
+    duration: Duration,
+    mut run_frame: impl FnMut(&mut Cpu) -> Result<()>,
+) -> Result<()> {
+    let start_cycles = cpu.cycles;
+    let mut frame_times = Vec::new();
+
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        let frame_start = Instant::now();
+        run_frame(cpu)?;
+        frame_times.push(frame_start.elapsed());
+    }
+    let elapsed = start.elapsed();
+
+    let instructions = cpu.cycles - start_cycles;
+    frame_times.sort();
+    let total: Duration = frame_times.iter().sum();
+    let percentile = |p: usize| frame_times[(frame_times.len() - 1) * p / 100];
+
+    println!(
+        "Ran {} frames, {} instructions in {:.2?}",
+        frame_times.len(),
+        instructions,
+        elapsed
+    );
+    println!(
+        "Instructions per second: {:.0}",
+        instructions as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "Frame time: mean {:.2?}, median {:.2?}, 99th percentile {:.2?}, max {:.2?}",
+        total.div_f64(frame_times.len() as f64),
+        percentile(50),
+        percentile(99),
+        percentile(100)
+    );
+    if !cpu.can_run() {
+        println!("The program stopped at {:04X}", cpu.position_in_memory);
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use crate::dispatch::{self, Handler};
+use crate::instruction::Instruction;
+use crate::{Cpu, Fault};
+
+/// Most instructions in one block
+const MAX_BLOCK: usize = 64;
+
+/// Straight-line opcodes with their handlers looked up, up to and including
+/// the first one that can change the PC
+type Block = Vec<(Handler, u16)>;
+
+/// An interpreter that decodes each straight-line run of instructions once
+/// and replays it from then on. Blocks containing a written address are
+/// dropped and the address is interpreted instruction by instruction from
+/// then on, so self-modifying code still works.
+pub struct BlockCache {
+    /// Blocks by the address they start at
+    blocks: Vec<Option<Block>>,
+    written: Box<[bool; 0x1000]>,
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self {
+            blocks: (0..0x1000).map(|_| None).collect(),
+            written: Box::new([false; 0x1000]),
+        }
+    }
+}
+
+impl BlockCache {
+    /// Like `Cpu::run_frame`, running cached blocks. Falls back to the
+    /// interpreter while instructions are being recorded.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Result<(), Fault> {
+        // pokes between frames
+        for (addr, _) in cpu.writes.drain(..) {
+            self.invalidate(addr);
+        }
+        if cpu.executed.is_some() {
+            return cpu.run_frame();
+        }
+
+        let mut budget = cpu.speed;
+        while budget > 0 && cpu.can_run() {
+            let pc = cpu.position_in_memory;
+            if pc >= self.blocks.len() {
+                // let the interpreter report the PC leaving memory
+                return cpu.step();
+            }
+            if self.blocks[pc].is_none() {
+                self.blocks[pc] = Some(self.decode(cpu, pc));
+            }
+            let block = self.blocks[pc].as_ref().unwrap();
+
+            // only the last instruction of a block can halt
+            let len = block.len().min(budget).min(cpu.max_cycles - cpu.cycles);
+            for &(handler, opcode) in &block[..len] {
+                cpu.cycles += 1;
+                cpu.writes.clear();
+                cpu.position_in_memory += 2;
+                handler(cpu, opcode);
+                if let Some(fault) = cpu.fault.take() {
+                    cpu.position_in_memory -= 2;
+                    return Err(fault);
+                }
+                budget -= 1;
+                if !cpu.writes.is_empty() {
+                    // the rest of the block may have just changed
+                    break;
+                }
+            }
+            if block.is_empty() && budget > 0 && cpu.can_run() {
+                cpu.step()?;
+                budget -= 1;
+            }
+
+            for &(addr, _) in &cpu.writes {
+                self.invalidate(addr);
+            }
+        }
+
+        cpu.tick_timers();
+        Ok(())
+    }
+
+    /// Drops the blocks containing `addr` and never caches it again
+    fn invalidate(&mut self, addr: usize) {
+        self.written[addr] = true;
+        let first = (addr + 1).saturating_sub(2 * MAX_BLOCK);
+        for pc in first..=addr {
+            if self.blocks[pc]
+                .as_ref()
+                .is_some_and(|block| addr < pc + 2 * block.len())
+            {
+                self.blocks[pc] = None;
+            }
+        }
+    }
+
+    /// The block starting at `pc`, empty if `pc` was written to
+    fn decode(&self, cpu: &Cpu, pc: usize) -> Block {
+        let mut block = Vec::new();
+        let mut addr = pc;
+        while block.len() < MAX_BLOCK
+            && addr + 1 < cpu.memory.len()
+            && !self.written[addr]
+            && !self.written[addr + 1]
+        {
+            let opcode = u16::from_be_bytes([cpu.memory[addr], cpu.memory[addr + 1]]);
+            block.push((dispatch::handler(opcode), opcode));
+            if changes_pc(Instruction::decode(opcode)) {
+                break;
+            }
+            addr += 2;
+        }
+        block
+    }
+}
+
+/// Whether the instruction after this one may not be the next in memory
+fn changes_pc(instruction: Instruction) -> bool {
+    use Instruction::*;
+
+    matches!(
+        instruction,
+        Halt | Return
+            | Jump { .. }
+            | Call { .. }
+            | JumpOffset { .. }
+            | SkipEq { .. }
+            | SkipNe { .. }
+            | SkipEqRegisters { .. }
+            | SkipNeRegisters { .. }
+            | SkipKey { .. }
+            | SkipNotKey { .. }
+            | WaitKey { .. }
+            | Unknown(_)
+    )
+}
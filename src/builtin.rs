@@ -0,0 +1,96 @@
+use crate::asm;
+use anyhow::{Context, Result, anyhow};
+
+/// How a built-in ROM is stored in the binary
+enum Source {
+    Binary(&'static [u8]),
+    Octo(&'static str),
+}
+
+/// A ROM that comes with the emulator, to try it without downloading any
+pub struct Builtin {
+    pub name: &'static str,
+    source: Source,
+}
+
+pub const BUILTINS: [Builtin; 3] = [
+    Builtin {
+        name: "ibm-logo",
+        source: Source::Binary(include_bytes!("../IBM Logo.ch8")),
+    },
+    Builtin {
+        name: "pong",
+        source: Source::Octo(include_str!("builtin/pong.o8")),
+    },
+    Builtin {
+        name: "quirks",
+        source: Source::Octo(include_str!("builtin/quirks.o8")),
+    },
+];
+
+impl Builtin {
+    /// The program, assembled first if it's kept as Octo source
+    pub fn program(&self) -> Result<Vec<u8>> {
+        match self.source {
+            Source::Binary(program) => Ok(program.to_vec()),
+            Source::Octo(source) => {
+                let (program, _) = asm::assemble(source)
+                    .with_context(|| format!("In built-in ROM `{}`", self.name))?;
+                Ok(program)
+            }
+        }
+    }
+}
+
+/// Index of the built-in ROM called `name` in [`BUILTINS`]
+pub fn parse(name: &str) -> Result<usize> {
+    BUILTINS.iter().position(|b| b.name == name).ok_or_else(|| {
+        let names: Vec<_> = BUILTINS.iter().map(|b| b.name).collect();
+        anyhow!(
+            "unknown built-in ROM, expected one of: {}",
+            names.join(", ")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, Quirks};
+
+    /// The 8 pixels of the quirks test's ON/OFF word on `line`
+    fn word(cpu: &Cpu, line: usize) -> u8 {
+        (cpu.display.rows[5 + line * 8] >> (64 - 24)) as u8
+    }
+
+    #[test]
+    fn quirks_test_shows_the_quirks() {
+        let program = BUILTINS[parse("quirks").unwrap()].program().unwrap();
+        for (quirks, expected) in [
+            (Quirks::default(), [0xEC, 0xEC, 0xEE]),
+            (
+                Quirks {
+                    shift: false,
+                    jump: false,
+                    vf_reset: true,
+                },
+                [0xEE, 0xEE, 0xEC],
+            ),
+        ] {
+            let mut cpu = Cpu::new(&program, 0);
+            cpu.quirks = quirks;
+            cpu.max_cycles = 1000;
+            while cpu.can_run() {
+                cpu.step().unwrap();
+            }
+            assert_eq!([word(&cpu, 0), word(&cpu, 1), word(&cpu, 2)], expected);
+        }
+    }
+
+    #[test]
+    fn builtins_assemble() {
+        for builtin in &BUILTINS {
+            assert!(!builtin.program().unwrap().is_empty(), "{}", builtin.name);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use crate::Display;
+use crate::palette::Palette;
+use anyhow::{Context, Result, anyhow};
+use gif::{Encoder, Frame, Repeat};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+/// Default recording name: the ROM name and the frame the recording started
+/// at, in the current directory
+pub fn default_gif_path(rom: &Path, frame: u64) -> PathBuf {
+    let stem = rom.file_stem().unwrap_or_default().to_string_lossy();
+    PathBuf::from(format!("{}-{}.gif", stem, frame))
+}
+
+/// Records the display into an animated GIF at 60 frames per second.
+///
+/// GIF delays are in hundredths of a second, so identical frames are merged
+/// and each image is shown until the centisecond its last frame ends in.
+pub struct GifRecorder {
+    encoder: Encoder<BufWriter<File>>,
+    scale: usize,
+    pending: Option<Vec<u8>>,
+    pending_since: u64,
+    frames: u64,
+}
+
+impl GifRecorder {
+    pub fn create(path: &Path, colors: &Palette, scale: usize) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Couldn't create GIF `{}`", path.display()))?;
+
+        let palette: Vec<u8> = [colors.bg(), colors.fg()]
+            .iter()
+            .flat_map(|color| color.to_be_bytes()[1..].to_vec())
+            .collect();
+        let mut encoder = Encoder::new(
+            BufWriter::new(file),
+            (64 * scale) as u16,
+            (32 * scale) as u16,
+            &palette,
+        )?;
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        Ok(Self {
+            encoder,
+            scale,
+            pending: None,
+            pending_since: 0,
+            frames: 0,
+        })
+    }
+
+    pub fn add_frame(&mut self, display: &Display) -> Result<()> {
+        let (width, height) = (64 * self.scale, 32 * self.scale);
+        let pixels: Vec<u8> = (0..width * height)
+            .map(|i| display.pixel(i / width / self.scale, i % width / self.scale) as u8)
+            .collect();
+
+        if self.pending.as_ref() != Some(&pixels) {
+            self.flush()?;
+            self.pending = Some(pixels);
+            self.pending_since = self.frames;
+        }
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let Some(pixels) = self.pending.take() else {
+            return Ok(());
+        };
+
+        let centiseconds = |frames: u64| frames * 100 / 60;
+        let mut frame = Frame::from_indexed_pixels(
+            (64 * self.scale) as u16,
+            (32 * self.scale) as u16,
+            pixels,
+            None,
+        );
+        frame.delay = (centiseconds(self.frames) - centiseconds(self.pending_since)) as u16;
+        self.encoder.write_frame(&frame)?;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.flush()?;
+        self.encoder.into_inner()?;
+        Ok(())
+    }
+}
+
+const SAMPLE_RATE: u32 = 44100;
+const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+const BUZZER_HZ: u32 = 440;
+
+/// Records the display and the buzzer into a video file with ffmpeg.
+///
+/// Frames are piped to ffmpeg as they come, encoded with its default codec
+/// for the output container. The buzzer is kept in memory and muxed in when
+/// the recording is finished.
+pub struct VideoRecorder {
+    path: PathBuf,
+    video_path: PathBuf,
+    ffmpeg: Child,
+    colors: Palette,
+    scale: usize,
+    samples: Vec<i16>,
+}
+
+impl VideoRecorder {
+    pub fn create(path: &Path, colors: &Palette, scale: usize) -> Result<Self> {
+        // keep the output extension last so ffmpeg picks the same container
+        let ext = path.extension().unwrap_or_default().to_string_lossy();
+        let video_path = temp_path(path, &format!("video.{}", ext));
+        let ffmpeg = Command::new("ffmpeg")
+            .args([
+                "-y",
+                "-loglevel",
+                "error",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "rgb24",
+            ])
+            .args(["-s", &format!("{}x{}", 64 * scale, 32 * scale)])
+            .args(["-r", "60", "-i", "-", "-pix_fmt", "yuv420p"])
+            .arg(&video_path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("Couldn't start ffmpeg, is it installed?")?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            video_path,
+            ffmpeg,
+            colors: *colors,
+            scale,
+            samples: Vec::new(),
+        })
+    }
+
+    pub fn add_frame(&mut self, display: &Display, buzzer: bool) -> Result<()> {
+        let data = rgb_pixels(display, &self.colors, self.scale);
+        let stdin = self
+            .ffmpeg
+            .stdin
+            .as_mut()
+            .context("ffmpeg stdin is closed")?;
+        stdin
+            .write_all(&data)
+            .context("Couldn't send a frame to ffmpeg")?;
+
+        let start = self.samples.len();
+        self.samples
+            .extend((start..start + SAMPLES_PER_FRAME).map(|i| {
+                let high = (i * BUZZER_HZ as usize * 2 / SAMPLE_RATE as usize).is_multiple_of(2);
+                match (buzzer, high) {
+                    (false, _) => 0,
+                    (true, true) => i16::MAX / 4,
+                    (true, false) => i16::MIN / 4,
+                }
+            }));
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        drop(self.ffmpeg.stdin.take());
+        let status = self.ffmpeg.wait()?;
+        if !status.success() {
+            return Err(anyhow!("ffmpeg failed to encode the video: {}", status));
+        }
+
+        let audio_path = temp_path(&self.path, "audio.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut wav = hound::WavWriter::create(&audio_path, spec)
+            .with_context(|| format!("Couldn't create `{}`", audio_path.display()))?;
+        for &sample in &self.samples {
+            wav.write_sample(sample)?;
+        }
+        wav.finalize()?;
+
+        let status = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-i"])
+            .arg(&self.video_path)
+            .arg("-i")
+            .arg(&audio_path)
+            .args(["-c:v", "copy", "-shortest"])
+            .arg(&self.path)
+            .status()
+            .context("Couldn't start ffmpeg")?;
+
+        let _ = fs::remove_file(&self.video_path);
+        let _ = fs::remove_file(&audio_path);
+
+        if !status.success() {
+            return Err(anyhow!("ffmpeg failed to add the audio: {}", status));
+        }
+
+        Ok(())
+    }
+}
+
+/// `out/video.webm` -> `out/.video.webm.<suffix>`
+fn temp_path(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{}", name, suffix))
+}
+
+/// The display as RGB bytes, with every emulated pixel `scale` x `scale`
+/// image pixels
+pub fn rgb_pixels(display: &Display, colors: &Palette, scale: usize) -> Vec<u8> {
+    let (width, height) = (64 * scale, 32 * scale);
+    let mut data = Vec::with_capacity(width * height * 3);
+    for row in 0..height {
+        for col in 0..width {
+            let color = if display.pixel(row / scale, col / scale) {
+                colors.fg()
+            } else {
+                colors.bg()
+            };
+            data.extend_from_slice(&color.to_be_bytes()[1..]);
+        }
+    }
+    data
+}
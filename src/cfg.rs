@@ -0,0 +1,199 @@
+use crate::PROGRAM_START;
+use crate::disasm::{self, Reached};
+use crate::instruction::Instruction;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// A straight run of instructions entered only at the top and left only
+/// at the bottom
+#[derive(Debug, PartialEq, Eq)]
+struct Block {
+    start: usize,
+    /// The address after the last instruction
+    end: usize,
+    edges: Vec<Edge>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Edge {
+    to: usize,
+    kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EdgeKind {
+    /// Into the next block, including back from a call
+    Flow,
+    Jump,
+    Call,
+    /// To the instruction a skip doesn't skip
+    NoSkip,
+    /// Past the instruction
+    Skip,
+}
+
+/// The control-flow graph of the code reachable from the start, following
+/// jumps, calls and skips
+fn blocks(program: &[u8]) -> Vec<Block> {
+    use Instruction::*;
+
+    let code = disasm::reachable(program);
+    let opcode = |pc: usize| {
+        let offset = pc - PROGRAM_START;
+        u16::from_be_bytes([program[offset], program[offset + 1]])
+    };
+
+    // instructions control can reach other than from the one before
+    let mut leaders: BTreeSet<usize> = code
+        .iter()
+        .filter(|&(_, &reached)| reached != Reached::Flow)
+        .map(|(&pc, _)| pc)
+        .collect();
+    leaders.insert(PROGRAM_START);
+    for &pc in code.keys() {
+        match Instruction::decode(opcode(pc)) {
+            Call { .. } => {
+                leaders.insert(pc + 2);
+            }
+            SkipEq { .. }
+            | SkipNe { .. }
+            | SkipEqRegisters { .. }
+            | SkipNeRegisters { .. }
+            | SkipKey { .. }
+            | SkipNotKey { .. } => {
+                leaders.insert(pc + 2);
+                leaders.insert(pc + 4);
+            }
+            _ => {}
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for &start in leaders.iter().filter(|pc| code.contains_key(pc)) {
+        let mut pc = start;
+        let edges = loop {
+            let next = pc + 2;
+            let edges = match Instruction::decode(opcode(pc)) {
+                Jump { addr } => vec![(addr as usize, EdgeKind::Jump)],
+                Call { addr } => vec![(addr as usize, EdgeKind::Call), (next, EdgeKind::Flow)],
+                SkipEq { .. }
+                | SkipNe { .. }
+                | SkipEqRegisters { .. }
+                | SkipNeRegisters { .. }
+                | SkipKey { .. }
+                | SkipNotKey { .. } => vec![(next, EdgeKind::NoSkip), (pc + 4, EdgeKind::Skip)],
+                Halt | Return | JumpOffset { .. } => vec![],
+                _ if !code.contains_key(&next) => vec![],
+                _ if leaders.contains(&next) => vec![(next, EdgeKind::Flow)],
+                _ => {
+                    pc = next;
+                    continue;
+                }
+            };
+            break edges;
+        };
+        let edges = edges
+            .into_iter()
+            .filter(|(to, _)| code.contains_key(to))
+            .map(|(to, kind)| Edge { to, kind })
+            .collect();
+        blocks.push(Block {
+            start,
+            end: pc + 2,
+            edges,
+        });
+    }
+    blocks
+}
+
+/// The control-flow graph as Graphviz, a box of disassembly per block
+pub fn to_dot(program: &[u8]) -> String {
+    let labels: BTreeMap<usize, String> = disasm::reachable(program)
+        .into_iter()
+        .filter_map(|(pc, reached)| match reached {
+            Reached::Jump => Some((pc, format!("label_{:04X}", pc))),
+            Reached::Call => Some((pc, format!("sub_{:04X}", pc))),
+            Reached::Flow => None,
+        })
+        .collect();
+
+    let mut dot = String::new();
+    writeln!(dot, "digraph cfg {{").unwrap();
+    writeln!(dot, "  node [shape=box fontname=monospace];").unwrap();
+    for block in blocks(program) {
+        let mut label = String::new();
+        if let Some(name) = labels.get(&block.start) {
+            write!(label, "{}:\\l", name).unwrap();
+        }
+        for pc in (block.start..block.end).step_by(2) {
+            let offset = pc - PROGRAM_START;
+            let opcode = u16::from_be_bytes([program[offset], program[offset + 1]]);
+            write!(label, "{:04X}  {}\\l", pc, Instruction::decode(opcode)).unwrap();
+        }
+        writeln!(dot, "  b{:04X} [label=\"{}\"];", block.start, label).unwrap();
+
+        for edge in &block.edges {
+            let style = match edge.kind {
+                EdgeKind::Flow => "",
+                EdgeKind::Jump => " [label=jump]",
+                EdgeKind::Call => " [label=call style=dashed]",
+                EdgeKind::NoSkip => " [label=\"no skip\"]",
+                EdgeKind::Skip => " [label=skip]",
+            };
+            writeln!(dot, "  b{:04X} -> b{:04X}{};", block.start, edge.to, style).unwrap();
+        }
+    }
+    writeln!(dot, "}}").unwrap();
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_at_branches_and_targets() {
+        let program = [
+            0x60, 0x00, // 200: LD V0, 00
+            0x22, 0x0C, // 202: CALL 20C
+            0x30, 0x05, // 204: SE V0, 05
+            0x12, 0x02, // 206: JP 202
+            0x00, 0xE0, // 208: CLS
+            0x12, 0x0A, // 20A: JP 20A
+            0x70, 0x01, // 20C: ADD V0, 01
+            0x00, 0xEE, // 20E: RET
+        ];
+        let edges = |to: &[(usize, EdgeKind)]| -> Vec<Edge> {
+            to.iter().map(|&(to, kind)| Edge { to, kind }).collect()
+        };
+        let block = |start, end, to: &[(usize, EdgeKind)]| Block {
+            start,
+            end,
+            edges: edges(to),
+        };
+        assert_eq!(
+            blocks(&program),
+            [
+                block(0x200, 0x202, &[(0x202, EdgeKind::Flow)]),
+                block(
+                    0x202,
+                    0x204,
+                    &[(0x20C, EdgeKind::Call), (0x204, EdgeKind::Flow)]
+                ),
+                block(
+                    0x204,
+                    0x206,
+                    &[(0x206, EdgeKind::NoSkip), (0x208, EdgeKind::Skip)]
+                ),
+                block(0x206, 0x208, &[(0x202, EdgeKind::Jump)]),
+                block(0x208, 0x20A, &[(0x20A, EdgeKind::Flow)]),
+                block(0x20A, 0x20C, &[(0x20A, EdgeKind::Jump)]),
+                block(0x20C, 0x210, &[]),
+            ]
+        );
+
+        let dot = to_dot(&program);
+        assert!(dot.contains("  b020C [label=\"sub_020C:\\l020C  ADD V0, 01\\l020E  RET\\l\"];\n"));
+        assert!(dot.contains("  b0202 -> b020C [label=call style=dashed];\n"));
+    }
+}
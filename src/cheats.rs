@@ -0,0 +1,88 @@
+use crate::debugger;
+use anyhow::{Context, Result, anyhow};
+use cpu::Cpu;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A byte of memory kept at a value, written before every frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cheat {
+    pub addr: usize,
+    pub value: u8,
+}
+
+/// `3A2=05`, hex with or without `0x`
+pub fn parse(s: &str) -> Result<Cheat> {
+    let (addr, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected ADDR=VALUE, found `{}`", s))?;
+    let addr = debugger::parse_addr(addr.trim())?;
+    let value = value.trim();
+    let value = u8::from_str_radix(value.trim_start_matches("0x"), 16)
+        .map_err(|_| anyhow!("Bad value `{}`, expected a byte", value))?;
+    Ok(Cheat { addr, value })
+}
+
+/// The cheats for a ROM are kept next to it, `game.ch8` has `game.cht`
+pub fn path(rom: &Path) -> PathBuf {
+    rom.with_extension("cht")
+}
+
+/// A cheat per line as for `--cheat`, `#` starts a comment:
+/// ```text
+/// 3A2=05  # lives
+/// ```
+fn parse_file(text: &str) -> Result<Vec<Cheat>> {
+    text.lines()
+        .enumerate()
+        .map(|(n, line)| (n, line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(n, line)| parse(line).with_context(|| format!("line {}", n + 1)))
+        .collect()
+}
+
+/// The cheats in the file next to `rom`, none if there is no such file
+pub fn load(rom: &Path) -> Result<Vec<Cheat>> {
+    let path = path(rom);
+    let Ok(text) = fs::read_to_string(&path) else {
+        return Ok(Vec::new());
+    };
+    parse_file(&text).with_context(|| format!("In `{}`", path.display()))
+}
+
+pub fn apply(cheats: &[Cheat], cpu: &mut Cpu) {
+    for cheat in cheats {
+        cpu.poke(cheat.addr, cheat.value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_cheats_like_the_command_line() {
+        let cheats = parse_file("# Space Invaders\n0x3A2=0x05  # lives\n\n 3a3 = ff\n").unwrap();
+        assert_eq!(
+            cheats,
+            [
+                Cheat {
+                    addr: 0x3A2,
+                    value: 5
+                },
+                Cheat {
+                    addr: 0x3A3,
+                    value: 0xFF
+                }
+            ]
+        );
+        assert!(parse("3A2").is_err());
+        assert!(parse("1000=05").is_err());
+        assert!(parse("3A2=100").is_err());
+        let error = parse_file("3A2=05\n3A3=x\n").unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "line 2: Bad value `x`, expected a byte"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use crate::Cpu;
+use anyhow::{Result, anyhow};
+use std::fmt;
+
+/// Breakpoint condition such as `v3 == 0x1F && dt == 0`.
+///
+/// Comparisons of values joined with `&&` and `||`, `&&` binding tighter.
+/// Values are numbers (decimal or `0x` hex), registers `v0`-`vf`, `i`, `pc`,
+/// `sp`, the timers `dt` and `st`, and memory reads `[addr]`.
+#[derive(Debug)]
+pub struct Condition {
+    source: String,
+    /// Alternatives of comparisons that all have to hold
+    any: Vec<Vec<Comparison>>,
+}
+
+#[derive(Debug)]
+struct Comparison {
+    left: Value,
+    op: Op,
+    right: Value,
+}
+
+#[derive(Debug)]
+enum Value {
+    Number(usize),
+    Register(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    Memory(Box<Value>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+
+        let mut any = vec![vec![parser.comparison()?]];
+        while let Some(token) = parser.next() {
+            match token.as_str() {
+                "&&" => any.last_mut().unwrap().push(parser.comparison()?),
+                "||" => any.push(vec![parser.comparison()?]),
+                _ => return Err(anyhow!("Expected `&&` or `||`, found `{}`", token)),
+            }
+        }
+
+        Ok(Self {
+            source: source.trim().to_string(),
+            any,
+        })
+    }
+
+    pub fn eval(&self, cpu: &Cpu) -> bool {
+        self.any.iter().any(|all| all.iter().all(|c| c.eval(cpu)))
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Comparison {
+    fn eval(&self, cpu: &Cpu) -> bool {
+        let (left, right) = (self.left.eval(cpu), self.right.eval(cpu));
+        match self.op {
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+            Op::Lt => left < right,
+            Op::Le => left <= right,
+            Op::Gt => left > right,
+            Op::Ge => left >= right,
+        }
+    }
+}
+
+impl Value {
+    fn eval(&self, cpu: &Cpu) -> usize {
+        match self {
+            Value::Number(n) => *n,
+            Value::Register(r) => cpu.registers[*r] as usize,
+            Value::I => cpu.register_i as usize,
+            Value::Pc => cpu.position_in_memory,
+            Value::Sp => cpu.stack_pointer,
+            Value::Dt => cpu.delay_timer as usize,
+            Value::St => cpu.sound_timer as usize,
+            Value::Memory(addr) => cpu.memory[addr.eval(cpu) % cpu.memory.len()] as usize,
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            _ if c.is_whitespace() => {}
+            '[' | ']' => tokens.push(c.to_string()),
+            '=' | '!' | '<' | '>' | '&' | '|' => {
+                let mut op = c.to_string();
+                if let Some(&next) = chars.peek()
+                    && matches!(next, '=' | '&' | '|')
+                {
+                    op.push(next);
+                    chars.next();
+                }
+                tokens.push(op);
+            }
+            _ if c.is_ascii_alphanumeric() => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek()
+                    && next.is_ascii_alphanumeric()
+                {
+                    word.push(next);
+                    chars.next();
+                }
+                tokens.push(word.to_ascii_lowercase());
+            }
+            _ => return Err(anyhow!("Unexpected `{}` in condition", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect_next(&mut self) -> Result<String> {
+        self.next()
+            .ok_or_else(|| anyhow!("Unexpected end of condition"))
+    }
+
+    fn comparison(&mut self) -> Result<Comparison> {
+        let left = self.value()?;
+        let op = match self.expect_next()?.as_str() {
+            "==" => Op::Eq,
+            "!=" => Op::Ne,
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            token => return Err(anyhow!("Expected a comparison, found `{}`", token)),
+        };
+        let right = self.value()?;
+        Ok(Comparison { left, op, right })
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        let token = self.expect_next()?;
+        let value = match token.as_str() {
+            "[" => {
+                let addr = self.value()?;
+                if self.next().as_deref() != Some("]") {
+                    return Err(anyhow!("Missing `]`"));
+                }
+                Value::Memory(Box::new(addr))
+            }
+            "i" => Value::I,
+            "pc" => Value::Pc,
+            "sp" => Value::Sp,
+            "dt" => Value::Dt,
+            "st" => Value::St,
+            _ => {
+                if let Some(r) = token.strip_prefix('v')
+                    && r.len() == 1
+                    && let Ok(r) = usize::from_str_radix(r, 16)
+                {
+                    Value::Register(r)
+                } else if let Some(hex) = token.strip_prefix("0x") {
+                    let n = usize::from_str_radix(hex, 16)
+                        .map_err(|_| anyhow!("Bad number `{}`", token))?;
+                    Value::Number(n)
+                } else {
+                    let n = token
+                        .parse()
+                        .map_err(|_| anyhow!("Unknown value `{}`", token))?;
+                    Value::Number(n)
+                }
+            }
+        };
+        Ok(value)
+    }
+}
@@ -0,0 +1,197 @@
+use crate::Quirks;
+use crate::platform::Platform;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// The database of well-known ROMs that comes with the emulator
+const BUILTIN_ROM_DB: &str = include_str!("roms.toml");
+
+/// Known-good settings for a single ROM. Each entry of the database is a
+/// table named by the SHA-256 of the ROM:
+/// ```toml
+/// [0b2e3a8b...]
+/// name = "INVADERS"
+/// platform = "chip8"
+/// speed = 15
+/// palette = "amber"
+/// quirks = { shift = false, vf_reset = true }
+/// keymap = { 5 = "Space", 4 = "Left", 6 = "Right" }
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomSettings {
+    pub name: Option<String>,
+    /// The platform whose quirks to use when `quirks` isn't given
+    pub platform: Option<Platform>,
+    pub speed: Option<usize>,
+    pub palette: Option<String>,
+    pub quirks: Option<Quirks>,
+    #[serde(default)]
+    pub keymap: BTreeMap<String, String>,
+    /// Colors of lit and unlit pixels, which only Octo options give
+    #[serde(skip)]
+    pub fg: Option<u32>,
+    #[serde(skip)]
+    pub bg: Option<u32>,
+}
+
+impl RomSettings {
+    /// Takes what these settings leave out from `fallback`
+    pub fn with_fallback(mut self, fallback: RomSettings) -> RomSettings {
+        self.name = self.name.or(fallback.name);
+        self.platform = self.platform.or(fallback.platform);
+        self.speed = self.speed.or(fallback.speed);
+        self.palette = self.palette.or(fallback.palette);
+        self.quirks = self.quirks.or(fallback.quirks);
+        self.fg = self.fg.or(fallback.fg);
+        self.bg = self.bg.or(fallback.bg);
+        for (keypad_key, host_key) in fallback.keymap {
+            self.keymap.entry(keypad_key).or_insert(host_key);
+        }
+        self
+    }
+}
+
+/// Settings for every ROM, from `~/.config/chip8/config.toml` or the file
+/// given with `--config`. Command line flags take precedence, and the ROM
+/// database's palette, quirks and keys take precedence for its ROMs:
+/// ```toml
+/// palette = "amber"
+/// speed = 1.5
+/// scale = 12
+/// crt = true
+/// quirks = { vf_reset = true }
+/// keymap = { 5 = "Space" }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    palette: Option<String>,
+    quirks: Option<Quirks>,
+    #[serde(default)]
+    keymap: BTreeMap<String, String>,
+    // defaults for the flags of the same name
+    speed: Option<f64>,
+    scale: Option<usize>,
+    padding: Option<usize>,
+    fullscreen: Option<bool>,
+    crt: Option<bool>,
+    grid: Option<bool>,
+    phosphor: Option<u8>,
+    rotate: Option<u16>,
+    rotate_keys: Option<bool>,
+    fg: Option<String>,
+    bg: Option<String>,
+    border: Option<String>,
+    rewind_buffer: Option<usize>,
+}
+
+impl Config {
+    /// Values for the flags of the same name, by argument id, to be parsed
+    /// like the flags themselves when they aren't given
+    pub fn flag_defaults(&self) -> Vec<(&'static str, String)> {
+        let mut defaults = Vec::new();
+        let mut add = |id, value: Option<String>| {
+            if let Some(value) = value {
+                defaults.push((id, value));
+            }
+        };
+        add("speed", self.speed.map(|v| v.to_string()));
+        add("scale", self.scale.map(|v| v.to_string()));
+        add("padding", self.padding.map(|v| v.to_string()));
+        add("fullscreen", self.fullscreen.map(|v| v.to_string()));
+        add("crt", self.crt.map(|v| v.to_string()));
+        add("grid", self.grid.map(|v| v.to_string()));
+        add("phosphor", self.phosphor.map(|v| v.to_string()));
+        add("rotate", self.rotate.map(|v| v.to_string()));
+        add("rotate_keys", self.rotate_keys.map(|v| v.to_string()));
+        add("fg", self.fg.clone());
+        add("bg", self.bg.clone());
+        add("border", self.border.clone());
+        add("rewind_buffer", self.rewind_buffer.map(|v| v.to_string()));
+        defaults
+    }
+
+    /// Fills in the palette and quirks the ROM database doesn't set, and
+    /// the keys it doesn't bind. A platform in the database counts as
+    /// setting the quirks.
+    pub fn fill(&self, settings: &mut RomSettings) {
+        settings.palette = settings.palette.take().or_else(|| self.palette.clone());
+        if settings.platform.is_none() {
+            settings.quirks = settings.quirks.or(self.quirks);
+        }
+        for (keypad_key, host_key) in &self.keymap {
+            let bound = settings
+                .keymap
+                .keys()
+                .any(|k| k.eq_ignore_ascii_case(keypad_key));
+            if !bound {
+                settings.keymap.insert(keypad_key.clone(), host_key.clone());
+            }
+        }
+    }
+}
+
+pub fn default_config() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("chip8").join("config.toml"))
+}
+
+/// The configuration in `path`, or in the default file if there is one
+pub fn load_config(path: Option<&Path>) -> Result<Config> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match default_config() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        },
+    };
+    let text = fs::read_to_string(&path)
+        .with_context(|| format!("Couldn't read config `{}`", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("Invalid config `{}`", path.display()))
+}
+
+pub fn default_rom_db() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("chip8").join("roms.toml"))
+}
+
+/// Settings stored for the ROM with the given hash, if any. A missing
+/// database file is the same as an empty one.
+pub fn load_rom_settings(path: &Path, rom_hash: &str) -> Result<Option<RomSettings>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Couldn't read ROM database `{}`", path.display()));
+        }
+    };
+
+    let mut db: HashMap<String, RomSettings> = toml::from_str(&text)
+        .with_context(|| format!("Invalid ROM database `{}`", path.display()))?;
+
+    Ok(db.remove(rom_hash))
+}
+
+/// Settings for the ROM with the given hash from the built-in database
+pub fn builtin_rom_settings(rom_hash: &str) -> Option<RomSettings> {
+    let mut db: HashMap<String, RomSettings> =
+        toml::from_str(BUILTIN_ROM_DB).expect("the built-in ROM database is valid");
+    db.remove(rom_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_rom_db_parses() {
+        let db: HashMap<String, RomSettings> = toml::from_str(BUILTIN_ROM_DB).unwrap();
+        assert!(db.keys().all(|hash| hash.len() == 64));
+        assert!(db.values().all(|settings| settings.name.is_some()));
+        assert!(db.values().all(|settings| settings.speed.is_some()));
+    }
+}
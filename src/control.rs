@@ -0,0 +1,246 @@
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request as Handshake, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::{Message, WebSocket};
+
+/// A command from a control client, a JSON object with the command in
+/// `cmd`, like `{"cmd": "read", "addr": 768, "len": 16}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case", deny_unknown_fields)]
+enum Command {
+    Pause,
+    Resume,
+    Reset,
+    Load {
+        path: PathBuf,
+    },
+    /// Holds key `key` down, or lets go of it, until told otherwise
+    Key {
+        key: usize,
+        down: bool,
+    },
+    Read {
+        addr: usize,
+        len: usize,
+    },
+    /// The registers, timers, stack and display hash, as `--dump-state`
+    /// writes them
+    State,
+}
+
+/// A command for the emulator to answer, all of them but the ones about
+/// keys, which the server answers itself
+#[derive(Debug)]
+pub enum Request {
+    Pause,
+    Resume,
+    Reset,
+    Load { path: PathBuf },
+    Read { addr: usize, len: usize },
+    State,
+}
+
+/// A request with the `id` the client gave it, echoed in the reply
+#[derive(Deserialize)]
+struct Envelope {
+    id: Option<Value>,
+    #[serde(flatten)]
+    request: Value,
+}
+
+type Pending = (Command, Sender<Result<Value>>);
+
+/// Serves the control API over WebSocket on localhost. Every connection
+/// gets its own thread, which hands requests to the emulator through
+/// `poll` and waits for the reply, so the window never blocks on a client.
+/// Browsers are turned away, so that a web page can't drive the emulator.
+pub struct ControlServer {
+    requests: Receiver<Pending>,
+    /// Keys held down by clients
+    pub keys: [bool; 16],
+}
+
+impl ControlServer {
+    pub fn start(port: u16) -> Result<Self> {
+        Ok(Self::bind(SocketAddr::from(([127, 0, 0, 1], port)))?.0)
+    }
+
+    fn bind(addr: SocketAddr) -> Result<(Self, SocketAddr)> {
+        let listener = TcpListener::bind(addr)
+            .with_context(|| format!("Couldn't listen for control clients on {}", addr))?;
+        let addr = listener.local_addr()?;
+        let (tx, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let tx = tx.clone();
+                thread::spawn(move || serve(stream, tx));
+            }
+        });
+        let server = Self {
+            requests,
+            keys: [false; 16],
+        };
+        Ok((server, addr))
+    }
+
+    /// Answers the requests that came in since the last call, the ones
+    /// about keys here and the others with `handle`
+    pub fn poll(&mut self, mut handle: impl FnMut(Request) -> Result<Value>) {
+        while let Ok((command, reply)) = self.requests.try_recv() {
+            let result = match command {
+                Command::Key { key, down } => match self.keys.get_mut(key) {
+                    Some(held) => {
+                        *held = down;
+                        Ok(Value::Null)
+                    }
+                    None => Err(anyhow!("There's no key {}", key)),
+                },
+                Command::Pause => handle(Request::Pause),
+                Command::Resume => handle(Request::Resume),
+                Command::Reset => handle(Request::Reset),
+                Command::Load { path } => handle(Request::Load { path }),
+                Command::Read { addr, len } => handle(Request::Read { addr, len }),
+                Command::State => handle(Request::State),
+            };
+            let _ = reply.send(result);
+        }
+    }
+}
+
+/// Refuses handshakes that carry an `Origin`, which browsers send and
+/// other clients don't
+struct RefuseBrowsers;
+
+impl Callback for RefuseBrowsers {
+    fn on_request(
+        self,
+        request: &Handshake,
+        response: Response,
+    ) -> Result<Response, ErrorResponse> {
+        if request.headers().contains_key("origin") {
+            let mut refusal =
+                ErrorResponse::new(Some("Browsers can't control the emulator".into()));
+            *refusal.status_mut() = StatusCode::FORBIDDEN;
+            return Err(refusal);
+        }
+        Ok(response)
+    }
+}
+
+/// Talks to one client until it disconnects
+fn serve(stream: TcpStream, requests: Sender<Pending>) {
+    let Ok(mut socket) = tungstenite::accept_hdr(stream, RefuseBrowsers) else {
+        return;
+    };
+    while let Ok(message) = socket.read() {
+        let Message::Text(text) = message else {
+            continue;
+        };
+        let (id, result) = match serde_json::from_str::<Envelope>(&text) {
+            Err(e) => (None, Err(anyhow!("Bad request: {}", e))),
+            Ok(Envelope { id, request }) => {
+                let result = match serde_json::from_value(request) {
+                    Err(e) => Err(anyhow!("Bad request: {}", e)),
+                    Ok(command) => ask(&requests, command),
+                };
+                (id, result)
+            }
+        };
+        if reply(&mut socket, id, result).is_err() {
+            break;
+        }
+    }
+}
+
+/// Hands `command` to the emulator and waits for the answer
+fn ask(requests: &Sender<Pending>, command: Command) -> Result<Value> {
+    let (tx, rx) = mpsc::channel();
+    requests
+        .send((command, tx))
+        .map_err(|_| anyhow!("The emulator stopped"))?;
+    rx.recv().map_err(|_| anyhow!("The emulator stopped"))?
+}
+
+/// `{"id": .., "ok": true, ..}` with the fields of `result`, or
+/// `{"id": .., "ok": false, "error": ..}`
+fn reply(
+    socket: &mut WebSocket<TcpStream>,
+    id: Option<Value>,
+    result: Result<Value>,
+) -> tungstenite::Result<()> {
+    let ok = result.is_ok();
+    let mut reply = match result {
+        Ok(Value::Object(fields)) => Value::Object(fields),
+        Ok(Value::Null) => json!({}),
+        Ok(value) => json!({ "value": value }),
+        Err(e) => json!({ "error": format!("{:#}", e) }),
+    };
+    reply["ok"] = ok.into();
+    if let Some(id) = id {
+        reply["id"] = id;
+    }
+    socket.send(Message::text(reply.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_clients_through_poll() {
+        let (mut server, addr) =
+            ControlServer::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let client = thread::spawn(move || {
+            let (mut socket, _) =
+                tungstenite::client(format!("ws://{}", addr), TcpStream::connect(addr).unwrap())
+                    .unwrap();
+            let mut send = |text: &str| {
+                socket.send(Message::text(text)).unwrap();
+                let reply = socket.read().unwrap();
+                serde_json::from_str::<Value>(reply.to_text().unwrap()).unwrap()
+            };
+            [
+                send(r#"{"id": 1, "cmd": "key", "key": 5, "down": true}"#),
+                send(r#"{"id": 2, "cmd": "read", "addr": 512, "len": 2}"#),
+                send(r#"{"cmd": "jump"}"#),
+            ]
+        });
+
+        let mut handled = Vec::new();
+        while !client.is_finished() {
+            server.poll(|request| {
+                handled.push(format!("{:?}", request));
+                Ok(json!({ "bytes": [0x12, 0x00] }))
+            });
+            thread::yield_now();
+        }
+        let replies = client.join().unwrap();
+        assert_eq!(replies[0], json!({ "id": 1, "ok": true }));
+        assert_eq!(
+            replies[1],
+            json!({ "id": 2, "ok": true, "bytes": [0x12, 0x00] })
+        );
+        assert_eq!(replies[2]["ok"], false);
+        assert!(server.keys[5]);
+        assert_eq!(handled, ["Read { addr: 512, len: 2 }"]);
+    }
+
+    #[test]
+    fn turns_browsers_away() {
+        use tungstenite::client::IntoClientRequest;
+
+        let (_server, addr) = ControlServer::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let mut request = format!("ws://{}", addr).into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("Origin", "https://example.com".parse().unwrap());
+        let error = tungstenite::client(request, TcpStream::connect(addr).unwrap()).unwrap_err();
+        assert!(error.to_string().contains("403"), "{}", error);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::instruction::Instruction;
+use crate::trace::Executed;
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+/// Which bytes of the ROM were executed as instructions
+#[derive(Debug)]
+pub struct Coverage {
+    rom: Range<usize>,
+    program: Vec<u8>,
+    executed: Vec<bool>,
+}
+
+/// A line of the listing: an instruction or a lone byte before odd aligned
+/// code
+struct Line {
+    addr: usize,
+    bytes: Vec<u8>,
+    executed: bool,
+}
+
+impl Coverage {
+    /// Coverage of `program` loaded at `start`
+    pub fn new(program: &[u8], start: usize) -> Self {
+        Self {
+            rom: start..start + program.len(),
+            program: program.to_vec(),
+            executed: vec![false; program.len()],
+        }
+    }
+
+    pub fn add(&mut self, executed: &[Executed]) {
+        for e in executed {
+            for addr in [e.pc, e.pc + 1] {
+                if self.rom.contains(&addr) {
+                    self.executed[addr - self.rom.start] = true;
+                }
+            }
+        }
+    }
+
+    fn is_executed(&self, addr: usize) -> bool {
+        self.rom.contains(&addr) && self.executed[addr - self.rom.start]
+    }
+
+    fn summary(&self) -> String {
+        let executed = self.executed.iter().filter(|&&e| e).count();
+        format!(
+            "Executed {} of {} ROM bytes ({:.1}%)",
+            executed,
+            self.rom.len(),
+            executed as f64 * 100.0 / self.rom.len().max(1) as f64
+        )
+    }
+
+    /// The ROM split into instructions, two bytes each unless code starts
+    /// at an odd address
+    fn lines(&self) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut addr = self.rom.start;
+        while addr < self.rom.end {
+            let len = if !self.is_executed(addr) && self.is_executed(addr + 1) {
+                1
+            } else {
+                2.min(self.rom.end - addr)
+            };
+            lines.push(Line {
+                addr,
+                bytes: self.program[addr - self.rom.start..][..len].to_vec(),
+                executed: self.is_executed(addr),
+            });
+            addr += len;
+        }
+        lines
+    }
+
+    /// Writes the coverage map, as HTML if the file name ends in `.html`
+    /// and as text otherwise
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let html = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("html"));
+        let report = if html { self.html() } else { self.text() };
+        fs::write(path, report)
+            .with_context(|| format!("Couldn't write coverage `{}`", path.display()))
+    }
+
+    /// A listing with executed instructions marked with `+`
+    fn text(&self) -> String {
+        let mut out = format!("{}\n\n", self.summary());
+        for line in self.lines() {
+            let mark = if line.executed { '+' } else { ' ' };
+            let _ = writeln!(
+                out,
+                "{} {:04X}  {}",
+                mark,
+                line.addr,
+                disassemble(&line.bytes)
+            );
+        }
+        out
+    }
+
+    fn html(&self) -> String {
+        let mut out = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>CHIP-8 coverage</title>\n<style>\n\
+             body { font-family: monospace; }\n\
+             .hit { background: #c8f0c8; }\n\
+             .miss { background: #f0c8c8; }\n\
+             </style>\n</head>\n<body>\n",
+        );
+        let _ = writeln!(out, "<p>{}</p>\n<pre>", self.summary());
+        for line in self.lines() {
+            let class = if line.executed { "hit" } else { "miss" };
+            let _ = writeln!(
+                out,
+                "<span class=\"{}\">{:04X}  {}</span>",
+                class,
+                line.addr,
+                disassemble(&line.bytes)
+            );
+        }
+        out.push_str("</pre>\n</body>\n</html>\n");
+        out
+    }
+}
+
+/// `6A7B  LD VA, 7B`, or `7B` for a single byte
+fn disassemble(bytes: &[u8]) -> String {
+    match *bytes {
+        [high, low] => {
+            let opcode = u16::from_be_bytes([high, low]);
+            format!("{:04X}  {}", opcode, Instruction::decode(opcode))
+        }
+        _ => bytes.iter().map(|b| format!("{:02X}", b)).collect(),
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{Context, Result, anyhow};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+/// Commands waiting their turn, more are dropped until the crowd catches up
+const MAX_QUEUE: usize = 16;
+
+/// Keys sent by a crowd, a chat bridge writing lines to a TCP socket or
+/// an IRC channel like Twitch chat, played one after another: each key is
+/// held for `hold` frames and then let go for as long, so that a repeated
+/// key counts twice.
+pub struct Crowd {
+    commands: Receiver<usize>,
+    queue: VecDeque<usize>,
+    hold: u64,
+    /// The key being played and the frames it has been played for
+    current: Option<(usize, u64)>,
+}
+
+impl Crowd {
+    fn new(commands: Receiver<usize>, hold: u64) -> Self {
+        Self {
+            commands,
+            queue: VecDeque::new(),
+            hold: hold.max(1),
+            current: None,
+        }
+    }
+
+    /// Takes commands a line each from any number of connections on `port`
+    pub fn listen(port: u16, hold: u64) -> Result<Self> {
+        Ok(Self::bind(SocketAddr::from(([0, 0, 0, 0], port)), hold)?.0)
+    }
+
+    fn bind(addr: SocketAddr, hold: u64) -> Result<(Self, SocketAddr)> {
+        let listener = TcpListener::bind(addr)
+            .with_context(|| format!("Couldn't listen for crowd commands on {}", addr))?;
+        let addr = listener.local_addr()?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for line in BufReader::new(stream).lines() {
+                        let Ok(line) = line else { break };
+                        send(&tx, &line);
+                    }
+                });
+            }
+        });
+        Ok((Self::new(rx, hold), addr))
+    }
+
+    /// Joins `HOST:PORT/#channel` anonymously and takes commands from the
+    /// messages there, which is enough to read Twitch chat at
+    /// irc.chat.twitch.tv:6667
+    pub fn irc(spec: &str, hold: u64) -> Result<Self> {
+        let (server, channel) = spec
+            .split_once('/')
+            .filter(|(_, channel)| channel.starts_with('#'))
+            .ok_or_else(|| anyhow!("Expected HOST:PORT/#CHANNEL, found `{}`", spec))?;
+        let stream = TcpStream::connect(server)
+            .with_context(|| format!("Couldn't connect to the chat at {}", server))?;
+        // Twitch takes any justinfan nick without a password
+        let nick = format!("justinfan{}", 10000 + std::process::id() % 90000);
+        write!(&stream, "NICK {}\r\nJOIN {}\r\n", nick, channel)?;
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(&stream).lines() {
+                let Ok(line) = line else { break };
+                if let Some(token) = line.strip_prefix("PING ") {
+                    if write!(&stream, "PONG {}\r\n", token).is_err() {
+                        break;
+                    }
+                } else if let Some(text) = irc_message(&line) {
+                    send(&tx, text);
+                }
+            }
+            eprintln!("Lost the chat connection");
+        });
+        Ok(Self::new(rx, hold))
+    }
+
+    /// The keys to hold down this frame
+    pub fn keys(&mut self) -> [bool; 16] {
+        self.queue.extend(self.commands.try_iter());
+        self.queue.truncate(MAX_QUEUE);
+        if self.current.is_none() {
+            self.current = self.queue.pop_front().map(|key| (key, 0));
+        }
+        let mut keypad = [false; 16];
+        if let Some((key, frames)) = &mut self.current {
+            keypad[*key] = *frames < self.hold;
+            *frames += 1;
+            if *frames == self.hold * 2 {
+                self.current = None;
+            }
+        }
+        keypad
+    }
+}
+
+fn send(tx: &Sender<usize>, text: &str) {
+    if let Some(key) = parse_command(text) {
+        let _ = tx.send(key);
+    }
+}
+
+/// A key as a hex digit, `5` or `a`; anything else is just chat
+fn parse_command(text: &str) -> Option<usize> {
+    let text = text.trim();
+    if text.len() != 1 {
+        return None;
+    }
+    usize::from_str_radix(text, 16).ok()
+}
+
+/// The text of a `PRIVMSG`, after the tags Twitch can put in front
+fn irc_message(line: &str) -> Option<&str> {
+    let line = match line.strip_prefix('@') {
+        Some(tagged) => tagged.split_once(' ')?.1,
+        None => line,
+    };
+    let line = match line.strip_prefix(':') {
+        Some(prefixed) => prefixed.split_once(' ')?.1,
+        None => line,
+    };
+    let (command, params) = line.split_once(' ')?;
+    if command != "PRIVMSG" {
+        return None;
+    }
+    Some(params.split_once(" :")?.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plays_commands_in_turn() {
+        let (mut crowd, addr) = Crowd::bind(SocketAddr::from(([127, 0, 0, 1], 0)), 2).unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"5\nhello\nA\n5\n").unwrap();
+        drop(client);
+
+        // wait for all three to come in
+        while crowd.queue.len() < 3 {
+            crowd.queue.extend(crowd.commands.try_iter());
+            thread::yield_now();
+        }
+        let pressed: Vec<_> = (0..14)
+            .map(|_| crowd.keys().iter().position(|&held| held))
+            .collect();
+        let (five, a) = (Some(5), Some(0xA));
+        assert_eq!(
+            pressed,
+            [
+                five, five, None, None, a, a, None, None, five, five, None, None, None, None
+            ]
+        );
+        assert_eq!(
+            irc_message("@badges=;color= :nick!nick@nick.tmi.twitch.tv PRIVMSG #chan :b"),
+            Some("b")
+        );
+        assert_eq!(irc_message(":tmi.twitch.tv 001 justinfan1 :Welcome"), None);
+    }
+}
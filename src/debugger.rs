@@ -0,0 +1,750 @@
+use crate::condition::Condition;
+use crate::instruction::Instruction;
+use crate::smc::{self, SmcDetector};
+use crate::symbols::Symbols;
+use crate::{Cpu, Display, Fault, Rng};
+use anyhow::{Context, Result, anyhow};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::ops::Range;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// Instructions shown before and after the PC in the disassembly
+const DISASSEMBLY_BEFORE: usize = 3;
+const DISASSEMBLY_AFTER: usize = 5;
+
+/// Stack depth at which calls print a warning, the stack holds 16
+const STACK_WARNING_DEPTH: usize = 14;
+
+/// Instructions that can be stepped back
+const HISTORY: usize = 100_000;
+
+const HELP: &str = "\
+Commands (addresses are hex or names from the symbol file):
+  break [ADDR]    set a breakpoint, or list them without ADDR
+  break ADDR if COND
+                  break only when COND holds, e.g. `v3 == 0x1F && dt == 0`
+                  (v0-vf, i, pc, sp, dt, st, [addr], == != < <= > >=, && ||)
+  delete ADDR     remove a breakpoint
+  break-op [OP]   pause before any instruction matching OP, like DXYN or Fx0A,
+                  or list them without OP
+  delete-op OP    remove an opcode breakpoint
+  watch [RANGE]   pause when ADDR or START..END is written, or list watchpoints
+  unwatch RANGE   remove a watchpoint
+  step            execute one instruction
+  next            execute one instruction, running calls to completion
+  continue        run until a breakpoint is hit
+  frame           run one frame, ignoring breakpoints (also F6 in the window)
+  reverse-step    undo the last instruction
+  reverse-continue
+                  go back until a breakpoint is hit
+  regs            show registers, I, PC, SP and timers
+  backtrace       show the call stack with the call sites
+  list [ADDR]     disassemble around ADDR (default PC)
+  sprite/N [ADDR] draw N rows of memory from ADDR as a sprite (default 15 at I)
+  poke ADDR BYTE   write a byte to memory
+  set REG VALUE   set v0-vf, i, pc, sp, dt or st
+  reset           start the program over (also F8 in the window)
+  x/N [ADDR]      dump N bytes of memory from ADDR (default 16 bytes at I)
+  quit            exit the emulator";
+
+pub enum Action {
+    Continue,
+    /// Start the program over, which takes the ROM the debugger doesn't have
+    Reset,
+    Quit,
+}
+
+/// Command line debugger. Commands are read from stdin on a separate thread,
+/// so the window keeps running while the debugger waits for input.
+pub struct Debugger {
+    commands: Receiver<String>,
+    breakpoints: BTreeMap<usize, Option<Condition>>,
+    watchpoints: Vec<Range<usize>>,
+    op_breakpoints: Vec<OpPattern>,
+    /// States before the most recent instructions, newest last
+    history: VecDeque<Snapshot>,
+    /// Return address and stack depth to stop at after `next` over a call
+    step_over: Option<(usize, usize)>,
+    symbols: Symbols,
+    /// Pauses on writes to code that already ran, with `--detect-smc`
+    smc: Option<SmcDetector>,
+    pub paused: bool,
+}
+
+impl Debugger {
+    /// Starts paused, or running until one of `breakpoints` is hit. With
+    /// `detect_smc` it also pauses when the program changes its own code.
+    pub fn new(
+        cpu: &Cpu,
+        breakpoints: &[usize],
+        paused: bool,
+        symbols: Symbols,
+        detect_smc: bool,
+    ) -> Self {
+        let (tx, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let paused = paused || breakpoints.contains(&cpu.position_in_memory);
+        let debugger = Self {
+            commands,
+            breakpoints: breakpoints.iter().map(|&addr| (addr, None)).collect(),
+            watchpoints: Vec::new(),
+            op_breakpoints: Vec::new(),
+            history: VecDeque::new(),
+            step_over: None,
+            symbols,
+            smc: detect_smc.then(SmcDetector::default),
+            paused,
+        };
+        println!("Debugger started, type `help` for commands");
+        for &addr in breakpoints {
+            print_breakpoint(addr, None);
+        }
+        if paused {
+            debugger.stop(cpu, "Paused");
+        }
+        debugger
+    }
+
+    /// Executes the commands typed since the last call
+    pub fn poll(&mut self, cpu: &mut Cpu) -> Action {
+        while let Ok(line) = self.commands.try_recv() {
+            match self.execute(cpu, line.trim()) {
+                Ok(Action::Continue) => {}
+                Ok(action) => return action,
+                Err(e) => println!("{}", e),
+            }
+            if self.paused {
+                prompt();
+            }
+        }
+        Action::Continue
+    }
+
+    fn execute(&mut self, cpu: &mut Cpu, line: &str) -> Result<Action> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(Action::Continue);
+        };
+        let arg = words.next();
+        // gdb style `x/16`
+        let (command, count) = match command.split_once('/') {
+            Some((command, count)) => (command, Some(count)),
+            None => (command, None),
+        };
+
+        match command {
+            "break" | "b" => match arg {
+                Some(addr) => {
+                    let addr = self.addr(addr)?;
+                    let condition = match line.split_once(" if ") {
+                        Some((_, condition)) => Some(Condition::parse(condition)?),
+                        None if words.next().is_some() => {
+                            return Err(anyhow!("Expected `if` after the address"));
+                        }
+                        None => None,
+                    };
+                    print_breakpoint(addr, condition.as_ref());
+                    self.breakpoints.insert(addr, condition);
+                }
+                None if self.breakpoints.is_empty() => println!("No breakpoints"),
+                None => {
+                    for (&addr, condition) in &self.breakpoints {
+                        print_breakpoint(addr, condition.as_ref());
+                    }
+                }
+            },
+            "delete" | "d" => {
+                let addr = self.addr(arg.context("delete needs an address")?)?;
+                if self.breakpoints.remove(&addr).is_none() {
+                    return Err(anyhow!("No breakpoint at {:04X}", addr));
+                }
+            }
+            "break-op" | "bo" => match arg {
+                Some(pattern) => {
+                    let pattern = OpPattern::parse(pattern)?;
+                    println!("Breakpoint on {}", pattern.text);
+                    if !self.op_breakpoints.contains(&pattern) {
+                        self.op_breakpoints.push(pattern);
+                    }
+                }
+                None if self.op_breakpoints.is_empty() => println!("No opcode breakpoints"),
+                None => {
+                    for pattern in &self.op_breakpoints {
+                        println!("Breakpoint on {}", pattern.text);
+                    }
+                }
+            },
+            "delete-op" => {
+                let pattern = OpPattern::parse(arg.context("delete-op needs an opcode")?)?;
+                let len = self.op_breakpoints.len();
+                self.op_breakpoints.retain(|p| *p != pattern);
+                if self.op_breakpoints.len() == len {
+                    return Err(anyhow!("No breakpoint on {}", pattern.text));
+                }
+            }
+            "watch" | "w" => match arg {
+                Some(range) => {
+                    let range = parse_range(range)?;
+                    println!("Watchpoint at {}", format_range(&range));
+                    if !self.watchpoints.contains(&range) {
+                        self.watchpoints.push(range);
+                    }
+                }
+                None if self.watchpoints.is_empty() => println!("No watchpoints"),
+                None => {
+                    for range in &self.watchpoints {
+                        println!("Watchpoint at {}", format_range(range));
+                    }
+                }
+            },
+            "unwatch" => {
+                let range = parse_range(arg.context("unwatch needs an address or range")?)?;
+                let len = self.watchpoints.len();
+                self.watchpoints.retain(|w| *w != range);
+                if self.watchpoints.len() == len {
+                    return Err(anyhow!("No watchpoint at {}", format_range(&range)));
+                }
+            }
+            "step" | "s" => self.step(cpu),
+            "next" | "n" => {
+                let pc = cpu.position_in_memory;
+                if cpu.read_opcode() & 0xF000 == 0x2000 {
+                    self.step_over = Some((pc + 2, cpu.stack_pointer));
+                    self.paused = false;
+                } else {
+                    self.step(cpu);
+                }
+            }
+            "continue" | "c" => self.paused = false,
+            "frame" | "f" => self.run_one_frame(cpu),
+            "reverse-step" | "rs" => match self.history.pop_back() {
+                Some(snapshot) => {
+                    snapshot.restore(cpu);
+                    self.print_disassembly(cpu, cpu.position_in_memory);
+                }
+                None => println!("No history to step back through"),
+            },
+            "reverse-continue" | "rc" => {
+                let reason = loop {
+                    let Some(snapshot) = self.history.pop_back() else {
+                        break "Reached the start of the history";
+                    };
+                    snapshot.restore(cpu);
+                    if self.breakpoint_hit(cpu) {
+                        break "Breakpoint";
+                    }
+                };
+                println!("{}", reason);
+                self.print_disassembly(cpu, cpu.position_in_memory);
+            }
+            "regs" | "r" => {
+                for line in cpu.status_lines() {
+                    println!("{}", line);
+                }
+            }
+            "backtrace" | "bt" => print_backtrace(cpu, &self.symbols),
+            "list" | "l" => {
+                let addr = match arg {
+                    Some(addr) => self.addr(addr)?,
+                    None => cpu.position_in_memory,
+                };
+                self.print_disassembly(cpu, addr);
+            }
+            "x" => {
+                let addr = arg.map(|addr| self.addr(addr)).transpose()?;
+                let (addr, count) = memory_range(cpu, addr, count, 16)?;
+                dump_memory(cpu, addr, count);
+            }
+            "sprite" => {
+                let addr = arg.map(|addr| self.addr(addr)).transpose()?;
+                let (addr, rows) = memory_range(cpu, addr, count, 15)?;
+                print_sprite(cpu, addr, rows);
+            }
+            "poke" => {
+                let addr = self.addr(arg.context("poke needs an address and a byte")?)?;
+                let value = parse_value(words.next().context("poke needs a byte")?, 0xFF)?;
+                cpu.memory[addr] = value as u8;
+            }
+            "set" => {
+                let register = arg.context("set needs a register and a value")?;
+                let value = words.next().context("set needs a value")?;
+                set_register(cpu, &register.to_ascii_lowercase(), value)?;
+                for line in cpu.status_lines() {
+                    println!("{}", line);
+                }
+            }
+            "reset" => return Ok(Action::Reset),
+            "quit" | "q" => return Ok(Action::Quit),
+            "help" | "h" => println!("{}", HELP),
+            _ => return Err(anyhow!("Unknown command `{}`, try `help`", command)),
+        }
+
+        Ok(Action::Continue)
+    }
+
+    fn step(&mut self, cpu: &mut Cpu) {
+        if !cpu.can_run() {
+            println!("The program has stopped");
+            return;
+        }
+        match self.execute_instruction(cpu) {
+            Ok(_) => self.print_disassembly(cpu, cpu.position_in_memory),
+            Err(fault) => println!("{}", fault),
+        }
+    }
+
+    /// Like [`Cpu::run_frame`], but stops at breakpoints
+    pub fn run_frame(&mut self, cpu: &mut Cpu) {
+        if self.paused {
+            return;
+        }
+
+        for _ in 0..cpu.speed {
+            if !cpu.can_run() {
+                break;
+            }
+            match self.execute_instruction(cpu) {
+                Ok(None) => {}
+                Ok(Some(reason)) => {
+                    self.pause(cpu, reason);
+                    return;
+                }
+                Err(fault) => {
+                    self.pause(cpu, &fault.to_string());
+                    return;
+                }
+            }
+
+            let pc = cpu.position_in_memory;
+            if self.step_over == Some((pc, cpu.stack_pointer)) {
+                self.step_over = None;
+                self.pause(cpu, "Stepped over call");
+                return;
+            }
+            if self.breakpoint_hit(cpu) {
+                self.pause(cpu, "Breakpoint");
+                return;
+            }
+            let opcode = cpu.read_opcode();
+            if self.op_breakpoints.iter().any(|p| p.matches(opcode)) {
+                self.pause(cpu, "Opcode breakpoint");
+                return;
+            }
+        }
+
+        cpu.tick_timers();
+    }
+
+    /// Runs exactly one frame while paused, without stopping at
+    /// breakpoints
+    pub fn advance_frame(&mut self, cpu: &mut Cpu) {
+        self.run_one_frame(cpu);
+        prompt();
+    }
+
+    fn run_one_frame(&mut self, cpu: &mut Cpu) {
+        for _ in 0..cpu.speed {
+            if !cpu.can_run() {
+                break;
+            }
+            if let Err(fault) = self.execute_instruction(cpu) {
+                println!("{}", fault);
+                break;
+            }
+        }
+        cpu.tick_timers();
+
+        println!("Advanced one frame");
+        self.print_disassembly(cpu, cpu.position_in_memory);
+    }
+
+    /// Steps the CPU and reports writes to watched memory and to code.
+    /// Returns why to pause, if a watchpoint was hit or code was changed.
+    fn execute_instruction(&mut self, cpu: &mut Cpu) -> Result<Option<&'static str>, Fault> {
+        let pc = cpu.position_in_memory;
+        let mut snapshot = Snapshot::take(cpu);
+        cpu.step()?;
+        snapshot.memory = cpu.writes.clone();
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(snapshot);
+
+        if cpu.stack_pointer > self.history.back().map_or(0, |s| s.stack_pointer)
+            && cpu.stack_pointer >= STACK_WARNING_DEPTH
+        {
+            println!(
+                "Warning: stack depth {} of {} after the call at {:04X}",
+                cpu.stack_pointer,
+                cpu.stack.len(),
+                pc
+            );
+        }
+
+        let mut reason = None;
+        for &(addr, old) in &cpu.writes {
+            if self.watchpoints.iter().any(|w| w.contains(&addr)) {
+                println!(
+                    "{:04X} wrote {:04X}: {:02X} -> {:02X}",
+                    pc, addr, old, cpu.memory[addr]
+                );
+                reason = Some("Watchpoint");
+            }
+        }
+        if let Some(detector) = &mut self.smc {
+            for addr in detector.record(pc, &cpu.writes) {
+                println!("{}", smc::warning(pc, addr));
+                reason = reason.or(Some("Self-modifying code"));
+            }
+        }
+        Ok(reason)
+    }
+
+    /// Drops the reverse step history, after the machine state was replaced
+    pub fn forget_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Forgets what the program did before it started over, showing where
+    /// it starts if paused
+    pub fn restarted(&mut self, cpu: &Cpu) {
+        self.forget_history();
+        self.step_over = None;
+        if self.paused {
+            self.stop(cpu, "Program restarted");
+        }
+    }
+
+    fn breakpoint_hit(&self, cpu: &Cpu) -> bool {
+        self.breakpoints
+            .get(&cpu.position_in_memory)
+            .is_some_and(|condition| condition.as_ref().is_none_or(|c| c.eval(cpu)))
+    }
+
+    fn pause(&mut self, cpu: &Cpu, reason: &str) {
+        self.paused = true;
+        self.step_over = None;
+        self.stop(cpu, reason);
+    }
+
+    fn stop(&self, cpu: &Cpu, reason: &str) {
+        println!("{}", reason);
+        self.print_disassembly(cpu, cpu.position_in_memory);
+        prompt();
+    }
+
+    /// The address of a symbol, or a hex address
+    fn addr(&self, text: &str) -> Result<usize> {
+        match self.symbols.addr(text) {
+            Some(addr) => Ok(addr),
+            None => parse_addr(text),
+        }
+    }
+
+    /// Disassembly around `center`, marking the PC with `=>` and breakpoints
+    /// with `*`
+    fn print_disassembly(&self, cpu: &Cpu, center: usize) {
+        let start = center.saturating_sub(DISASSEMBLY_BEFORE * 2);
+        let end = (center + DISASSEMBLY_AFTER * 2 + 2).min(cpu.memory.len() - 1);
+        for addr in (start..end).step_by(2) {
+            let opcode = u16::from_be_bytes([cpu.memory[addr], cpu.memory[addr + 1]]);
+            let pc = if addr == cpu.position_in_memory {
+                "=>"
+            } else {
+                ""
+            };
+            let breakpoint = if self.breakpoints.contains_key(&addr) {
+                "*"
+            } else {
+                ""
+            };
+            if let Some(name) = self.symbols.name(addr) {
+                println!("{}:", name);
+            }
+            println!(
+                "{:>2}{:1} {:04X}  {:04X}  {}",
+                pc,
+                breakpoint,
+                addr,
+                opcode,
+                self.symbols.disassemble(Instruction::decode(opcode))
+            );
+        }
+    }
+}
+
+/// Opcode pattern like `DXYN`: hex digits have to match, other letters
+/// match any nibble
+#[derive(Debug, PartialEq, Eq)]
+struct OpPattern {
+    text: String,
+    mask: u16,
+    value: u16,
+}
+
+impl OpPattern {
+    fn parse(text: &str) -> Result<Self> {
+        if text.chars().count() != 4 || !text.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(anyhow!(
+                "Bad opcode `{}`, expected four characters like DXYN",
+                text
+            ));
+        }
+        let (mut mask, mut value) = (0, 0);
+        for c in text.chars() {
+            mask <<= 4;
+            value <<= 4;
+            if let Some(digit) = c.to_digit(16) {
+                mask |= 0xF;
+                value |= digit as u16;
+            }
+        }
+        Ok(Self {
+            text: text.to_ascii_uppercase(),
+            mask,
+            value,
+        })
+    }
+
+    fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+/// Machine state before an instruction, enough to undo it
+struct Snapshot {
+    registers: [u8; 16],
+    register_i: u16,
+    position_in_memory: usize,
+    stack: [u16; 16],
+    stack_pointer: usize,
+    delay_timer: u8,
+    sound_timer: u8,
+    rng: Rng,
+    halted: bool,
+    cycles: usize,
+    /// Memory the instruction overwrote, with the previous values
+    memory: Vec<(usize, u8)>,
+    /// The display, if the instruction draws or clears it
+    display: Option<Display>,
+}
+
+impl Snapshot {
+    fn take(cpu: &Cpu) -> Self {
+        let display = matches!(
+            Instruction::decode(cpu.read_opcode()),
+            Instruction::Draw { .. } | Instruction::Clear
+        )
+        .then(|| cpu.display.clone());
+
+        Self {
+            registers: cpu.registers,
+            register_i: cpu.register_i,
+            position_in_memory: cpu.position_in_memory,
+            stack: cpu.stack,
+            stack_pointer: cpu.stack_pointer,
+            delay_timer: cpu.delay_timer,
+            sound_timer: cpu.sound_timer,
+            rng: cpu.rng.clone(),
+            halted: cpu.halted,
+            cycles: cpu.cycles,
+            memory: Vec::new(),
+            display,
+        }
+    }
+
+    fn restore(self, cpu: &mut Cpu) {
+        cpu.registers = self.registers;
+        cpu.register_i = self.register_i;
+        cpu.position_in_memory = self.position_in_memory;
+        cpu.stack = self.stack;
+        cpu.stack_pointer = self.stack_pointer;
+        cpu.delay_timer = self.delay_timer;
+        cpu.sound_timer = self.sound_timer;
+        cpu.rng = self.rng;
+        cpu.halted = self.halted;
+        cpu.cycles = self.cycles;
+        for &(addr, old) in self.memory.iter().rev() {
+            cpu.memory[addr] = old;
+        }
+        if let Some(display) = self.display {
+            cpu.display = display;
+        }
+        cpu.writes.clear();
+    }
+}
+
+/// The return addresses on the stack, innermost first, with the calls
+/// that pushed them
+fn print_backtrace(cpu: &Cpu, symbols: &Symbols) {
+    println!(
+        "#0  {:04X}  {}",
+        cpu.position_in_memory,
+        symbols.disassemble(Instruction::decode(cpu.read_opcode()))
+    );
+    for (depth, &ret) in cpu.stack[..cpu.stack_pointer].iter().rev().enumerate() {
+        let call = (ret as usize).saturating_sub(2);
+        let byte = |addr: usize| cpu.memory.get(addr).copied().unwrap_or(0);
+        let opcode = u16::from_be_bytes([byte(call), byte(call + 1)]);
+        println!(
+            "#{:<2} {:04X}  {}",
+            depth + 1,
+            call,
+            symbols.disassemble(Instruction::decode(opcode))
+        );
+    }
+    if cpu.stack_pointer >= STACK_WARNING_DEPTH {
+        println!(
+            "Warning: stack depth {} of {}",
+            cpu.stack_pointer,
+            cpu.stack.len()
+        );
+    }
+}
+
+fn print_breakpoint(addr: usize, condition: Option<&Condition>) {
+    match condition {
+        Some(condition) => println!("Breakpoint at {:04X} if {}", addr, condition),
+        None => println!("Breakpoint at {:04X}", addr),
+    }
+}
+
+/// The address and byte count of `x/N ADDR` style commands, starting at I
+/// by default
+fn memory_range(
+    cpu: &Cpu,
+    addr: Option<usize>,
+    count: Option<&str>,
+    default_count: usize,
+) -> Result<(usize, usize)> {
+    let count = match count {
+        Some(count) => count
+            .parse()
+            .with_context(|| format!("Bad byte count `{}`", count))?,
+        None => default_count,
+    };
+    Ok((addr.unwrap_or(cpu.register_i as usize), count))
+}
+
+/// Prints `rows` bytes from `addr` as an 8 pixel wide sprite
+fn print_sprite(cpu: &Cpu, addr: usize, rows: usize) {
+    for a in addr..(addr + rows).min(cpu.memory.len()) {
+        let byte = cpu.memory[a];
+        let pixels: String = (0..8)
+            .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+            .collect();
+        println!("{:04X}  {:02X}  {}", a, byte, pixels);
+    }
+}
+
+/// Prints `count` bytes from `addr`, 16 per line. The instruction at PC and
+/// the byte at I are highlighted when printing to a terminal.
+fn dump_memory(cpu: &Cpu, addr: usize, count: usize) {
+    let end = (addr + count).min(cpu.memory.len());
+    let pc = cpu.position_in_memory;
+    let i = cpu.register_i as usize;
+    let highlight = io::stdout().is_terminal();
+
+    for line in (addr..end).step_by(16) {
+        let bytes: Vec<String> = (line..end.min(line + 16))
+            .map(|a| {
+                let byte = format!("{:02X}", cpu.memory[a]);
+                if !highlight {
+                    byte
+                } else if a == pc || a == pc + 1 {
+                    format!("\x1b[7m{}\x1b[0m", byte)
+                } else if a == i {
+                    format!("\x1b[4m{}\x1b[0m", byte)
+                } else {
+                    byte
+                }
+            })
+            .collect();
+        println!("{:04X}: {}", line, bytes.join(" "));
+    }
+}
+
+fn set_register(cpu: &mut Cpu, register: &str, value: &str) -> Result<()> {
+    match register {
+        "i" => cpu.register_i = parse_value(value, 0xFFFF)? as u16,
+        "pc" => cpu.position_in_memory = parse_addr(value)?,
+        "sp" => cpu.stack_pointer = parse_value(value, cpu.stack.len())?,
+        "dt" => cpu.delay_timer = parse_value(value, 0xFF)? as u8,
+        "st" => cpu.sound_timer = parse_value(value, 0xFF)? as u8,
+        _ => {
+            let r = register
+                .strip_prefix('v')
+                .filter(|r| r.len() == 1)
+                .and_then(|r| usize::from_str_radix(r, 16).ok())
+                .with_context(|| format!("Unknown register `{}`", register))?;
+            cpu.registers[r] = parse_value(value, 0xFF)? as u8;
+        }
+    }
+    Ok(())
+}
+
+/// A hex value up to `max`
+fn parse_value(s: &str, max: usize) -> Result<usize> {
+    let value = parse_hex(s).map_err(|_| anyhow!("Bad value `{}`", s))?;
+    if value > max {
+        return Err(anyhow!("Value {:X} is larger than {:X}", value, max));
+    }
+    Ok(value)
+}
+
+/// `0x3A0` or `0x3A0..0x3B0`, end exclusive
+pub fn parse_range(s: &str) -> Result<Range<usize>> {
+    let range = match s.split_once("..") {
+        Some((start, end)) => {
+            let end = parse_hex(end)?;
+            if end > 0x1000 {
+                return Err(anyhow!("Address {:X} is outside of memory", end));
+            }
+            parse_addr(start)?..end
+        }
+        None => {
+            let addr = parse_addr(s)?;
+            addr..addr + 1
+        }
+    };
+    if range.is_empty() {
+        return Err(anyhow!("Empty range `{}`", s));
+    }
+    Ok(range)
+}
+
+fn format_range(range: &Range<usize>) -> String {
+    if range.len() == 1 {
+        format!("{:04X}", range.start)
+    } else {
+        format!("{:04X}..{:04X}", range.start, range.end)
+    }
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
+
+fn parse_hex(s: &str) -> Result<usize> {
+    let hex = s.trim_start_matches("0x");
+    usize::from_str_radix(hex, 16).with_context(|| format!("Bad address `{}`", s))
+}
+
+pub fn parse_addr(s: &str) -> Result<usize> {
+    let addr = parse_hex(s)?;
+    if addr >= 0x1000 {
+        return Err(anyhow!("Address {:X} is outside of memory", addr));
+    }
+    Ok(addr)
+}
@@ -0,0 +1,202 @@
+use crate::PROGRAM_START;
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+use crate::symbols::Symbols;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// The program as assembly: an instruction per line for code reachable
+/// from the start, a byte per line for everything else, and labels for
+/// jump and call targets and for the data `LD I` points at. Addresses in
+/// `symbols` are labeled with their names instead.
+pub fn disassemble(program: &[u8], symbols: &Symbols) -> String {
+    let code = reachable(program);
+    let mut labels = labels(program, &code);
+    for (addr, name) in symbols.iter() {
+        labels.insert(addr, name.to_string());
+    }
+    let byte = |addr: usize| program[addr - PROGRAM_START];
+    let end = PROGRAM_START + program.len();
+
+    let mut text = String::new();
+    let mut addr = PROGRAM_START;
+    while addr < end {
+        if let Some(label) = labels.get(&addr) {
+            writeln!(text, "{}:", label).unwrap();
+        }
+        if code.contains_key(&addr) && addr + 1 < end {
+            let opcode = u16::from_be_bytes([byte(addr), byte(addr + 1)]);
+            let instruction = Instruction::decode(opcode);
+            let operand = |target: u16| {
+                labels
+                    .get(&(target as usize))
+                    .cloned()
+                    .unwrap_or_else(|| format!("{:03X}", target))
+            };
+            let mnemonic = match instruction {
+                Instruction::Jump { addr } => format!("JP {}", operand(addr)),
+                Instruction::Call { addr } => format!("CALL {}", operand(addr)),
+                Instruction::SetI { addr } => format!("LD I, {}", operand(addr)),
+                _ => instruction.to_string(),
+            };
+            writeln!(text, "  {:04X}  {:04X}  {}", addr, opcode, mnemonic).unwrap();
+            addr += 2;
+        } else {
+            let value = byte(addr);
+            let pixels: String = (0..8)
+                .map(|bit| if value << bit & 0x80 != 0 { '#' } else { '.' })
+                .collect();
+            writeln!(
+                text,
+                "  {:04X}  {:02X}    DB {:02X}  ; {}",
+                addr, value, value, pixels
+            )
+            .unwrap();
+            addr += 1;
+        }
+    }
+    text
+}
+
+/// How an address was reached while following the code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reached {
+    /// From the previous instruction, by a skip or by returning
+    Flow,
+    Jump,
+    Call,
+}
+
+/// Addresses of the instructions reachable from the start, following
+/// jumps, calls and skips. `Bnnn` targets depend on V0 and aren't followed.
+/// Opcodes of other platforms continue to the next instruction.
+pub fn reachable(program: &[u8]) -> BTreeMap<usize, Reached> {
+    use Instruction::*;
+
+    let end = PROGRAM_START + program.len();
+    let mut code = BTreeMap::new();
+    let mut pending = vec![(PROGRAM_START, Reached::Flow)];
+    while let Some((pc, reached)) = pending.pop() {
+        if pc < PROGRAM_START || pc + 1 >= end {
+            continue;
+        }
+        // a call or jump to code already seen still gets its label
+        let previous = code.insert(pc, reached);
+        if let Some(previous) = previous {
+            if previous != Reached::Flow && reached == Reached::Flow {
+                code.insert(pc, previous);
+            }
+            continue;
+        }
+
+        let offset = pc - PROGRAM_START;
+        let opcode = u16::from_be_bytes([program[offset], program[offset + 1]]);
+        match Instruction::decode(opcode) {
+            Halt | Return | JumpOffset { .. } => {}
+            // XO-CHIP's `i := long nnnn` is followed by the address
+            Unknown(0xF000) => pending.push((pc + 4, Reached::Flow)),
+            // 00FD exits
+            Unknown(0x00FD) => {}
+            Unknown(opcode) if Platform::of(opcode).is_some() => {
+                pending.push((pc + 2, Reached::Flow))
+            }
+            Unknown(_) => {}
+            Jump { addr } => pending.push((addr as usize, Reached::Jump)),
+            Call { addr } => {
+                pending.push((pc + 2, Reached::Flow));
+                pending.push((addr as usize, Reached::Call));
+            }
+            SkipEq { .. }
+            | SkipNe { .. }
+            | SkipEqRegisters { .. }
+            | SkipNeRegisters { .. }
+            | SkipKey { .. }
+            | SkipNotKey { .. } => {
+                pending.push((pc + 2, Reached::Flow));
+                pending.push((pc + 4, Reached::Flow));
+            }
+            _ => pending.push((pc + 2, Reached::Flow)),
+        }
+    }
+    code
+}
+
+/// Names for jump and call targets, and for data `LD I` points at
+fn labels(program: &[u8], code: &BTreeMap<usize, Reached>) -> BTreeMap<usize, String> {
+    let mut labels = BTreeMap::new();
+    for (&pc, &reached) in code {
+        match reached {
+            Reached::Jump => labels.insert(pc, format!("label_{:04X}", pc)),
+            Reached::Call => labels.insert(pc, format!("sub_{:04X}", pc)),
+            Reached::Flow => None,
+        };
+
+        let offset = pc - PROGRAM_START;
+        let opcode = u16::from_be_bytes([program[offset], program[offset + 1]]);
+        if let Instruction::SetI { addr } = Instruction::decode(opcode) {
+            let addr = addr as usize;
+            let in_program = (PROGRAM_START..PROGRAM_START + program.len()).contains(&addr);
+            if in_program && !code.contains_key(&addr) {
+                labels.insert(addr, format!("data_{:04X}", addr));
+            }
+        }
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_targets_and_data() {
+        let program = [
+            0x22, 0x08, // CALL sub_0208
+            0xA2, 0x0C, // LD I, data_020C
+            0x12, 0x06, // JP label_0206
+            0x00, 0xE0, // CLS
+            0x00, 0xEE, // RET
+            0x00, 0x00, // unreachable
+            0xF0, // sprite
+        ];
+        let expected = [
+            "  0200  2208  CALL sub_0208",
+            "  0202  A20C  LD I, data_020C",
+            "  0204  1206  JP label_0206",
+            "label_0206:",
+            "  0206  00E0  CLS",
+            "sub_0208:",
+            "  0208  00EE  RET",
+            "  020A  00    DB 00  ; ........",
+            "  020B  00    DB 00  ; ........",
+            "data_020C:",
+            "  020C  F0    DB F0  ; ####....",
+        ];
+        assert_eq!(
+            disassemble(&program, &Symbols::default())
+                .lines()
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn symbols_name_labels() {
+        let program = [0x22, 0x04, 0x00, 0x00, 0x00, 0xEE];
+        let mut symbols = Symbols::default();
+        symbols.insert(0x204, "draw");
+        let text = disassemble(&program, &symbols);
+        assert!(text.contains("  0200  2204  CALL draw\n"));
+        assert!(text.contains("draw:\n  0204  00EE  RET\n"));
+    }
+
+    #[test]
+    fn follows_both_ways_of_a_skip() {
+        let program = [0x30, 0x00, 0x12, 0x00, 0x12, 0x06, 0x00, 0xE0];
+        let code = reachable(&program);
+        assert_eq!(
+            code.keys().copied().collect::<Vec<_>>(),
+            [0x200, 0x202, 0x204, 0x206]
+        );
+    }
+}
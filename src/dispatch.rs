@@ -0,0 +1,113 @@
+use crate::{Cpu, Fault};
+
+pub(crate) type Handler = fn(&mut Cpu, u16);
+
+fn x(opcode: u16) -> u8 {
+    ((opcode >> 8) & 0x000F) as u8
+}
+
+fn y(opcode: u16) -> u8 {
+    ((opcode >> 4) & 0x000F) as u8
+}
+
+fn n(opcode: u16) -> u8 {
+    (opcode & 0x000F) as u8
+}
+
+fn kk(opcode: u16) -> u8 {
+    (opcode & 0x00FF) as u8
+}
+
+fn addr(opcode: u16) -> u16 {
+    opcode & 0x0FFF
+}
+
+/// Executes `opcode` through tables of handlers instead of decoding it into
+/// an `Instruction` first. The top nibble picks a handler from `MAIN`, and
+/// the 8xy? and Fx?? groups have tables of their own.
+pub(crate) fn execute(cpu: &mut Cpu, opcode: u16) {
+    MAIN[(opcode >> 12) as usize](cpu, opcode)
+}
+
+/// The handler `execute` ends up calling for `opcode`, for running it
+/// again without the table lookups
+pub(crate) fn handler(opcode: u16) -> Handler {
+    match opcode >> 12 {
+        0x8 => ALU[n(opcode) as usize],
+        0xF => MISC[kk(opcode) as usize],
+        c => MAIN[c as usize],
+    }
+}
+
+const MAIN: [Handler; 16] = [
+    system,
+    |cpu, op| cpu.jmp_to_addr(addr(op)),
+    |cpu, op| cpu.call(addr(op)),
+    |cpu, op| cpu.skip_if_eq(x(op), kk(op)),
+    |cpu, op| cpu.skip_if_neq(x(op), kk(op)),
+    |cpu, op| match n(op) {
+        0 => cpu.skip_if_eq_registers(x(op), y(op)),
+        _ => unknown(cpu, op),
+    },
+    |cpu, op| cpu.load_in_register(x(op), kk(op)),
+    |cpu, op| cpu.add_xkk(x(op), kk(op)),
+    |cpu, op| ALU[n(op) as usize](cpu, op),
+    |cpu, op| match n(op) {
+        0 => cpu.skip_if_neq_registers(x(op), y(op)),
+        _ => unknown(cpu, op),
+    },
+    |cpu, op| cpu.set_i(addr(op)),
+    |cpu, op| cpu.jmp_to_addr_x(x(op), addr(op)),
+    |cpu, op| cpu.set_rand_x(x(op), kk(op)),
+    |cpu, op| cpu.draw(x(op), y(op), n(op)),
+    |cpu, op| match kk(op) {
+        0x9E => cpu.skip_if_key(x(op)),
+        0xA1 => cpu.skip_if_not_key(x(op)),
+        _ => unknown(cpu, op),
+    },
+    |cpu, op| MISC[kk(op) as usize](cpu, op),
+];
+
+/// 8xy?, by the low nibble
+const ALU: [Handler; 16] = {
+    let mut table: [Handler; 16] = [unknown; 16];
+    table[0x0] = |cpu, op| cpu.set_xy(x(op), y(op));
+    table[0x1] = |cpu, op| cpu.or_xy(x(op), y(op));
+    table[0x2] = |cpu, op| cpu.and_xy(x(op), y(op));
+    table[0x3] = |cpu, op| cpu.xor_xy(x(op), y(op));
+    table[0x4] = |cpu, op| cpu.add_xy(x(op), y(op));
+    table[0x5] = |cpu, op| cpu.sub_xy(x(op), y(op));
+    table[0x6] = |cpu, op| cpu.shr_xy(x(op), y(op));
+    table[0x7] = |cpu, op| cpu.subn_xy(x(op), y(op));
+    table[0xE] = |cpu, op| cpu.shl_xy(x(op), y(op));
+    table
+};
+
+/// Fx??, by the low byte
+const MISC: [Handler; 256] = {
+    let mut table: [Handler; 256] = [unknown; 256];
+    table[0x07] = |cpu, op| cpu.load_delay_timer(x(op));
+    table[0x0A] = |cpu, op| cpu.wait_key(x(op));
+    table[0x15] = |cpu, op| cpu.set_delay_timer(x(op));
+    table[0x18] = |cpu, op| cpu.set_sound_timer(x(op));
+    table[0x1E] = |cpu, op| cpu.add_i(x(op));
+    table[0x29] = |cpu, op| cpu.load_font(x(op));
+    table[0x33] = |cpu, op| cpu.store_bcd(x(op));
+    table[0x55] = |cpu, op| cpu.store_registers(x(op));
+    table[0x65] = |cpu, op| cpu.load_registers(x(op));
+    table
+};
+
+/// 0000, 00E0 and 00EE
+fn system(cpu: &mut Cpu, opcode: u16) {
+    match opcode {
+        0x0000 => cpu.halted = true,
+        0x00E0 => cpu.display.clear(),
+        0x00EE => cpu.ret(),
+        _ => unknown(cpu, opcode),
+    }
+}
+
+fn unknown(cpu: &mut Cpu, opcode: u16) {
+    cpu.fault(|pc| Fault::UnknownOpcode { pc, opcode })
+}
@@ -0,0 +1,148 @@
+//! The C interface declared in `include/chip8.h`, for frontends in C, C++
+//! or Swift that embed the machine. A `Chip8` is only ever handled through
+//! the pointer `chip8_create` returns.
+
+use crate::{Cpu, MAX_PROGRAM_SIZE};
+use std::slice;
+
+pub const CHIP8_OK: i32 = 0;
+/// The program halted, or ran out of `max_cycles`
+pub const CHIP8_HALTED: i32 = 1;
+/// The program did something the machine can't carry out, the PC is left
+/// at the instruction
+pub const CHIP8_FAULT: i32 = -1;
+pub const CHIP8_ROM_TOO_LARGE: i32 = -2;
+
+pub struct Chip8 {
+    cpu: Cpu,
+    seed: u64,
+}
+
+fn status(result: Result<(), crate::Fault>, cpu: &Cpu) -> i32 {
+    match result {
+        Err(_) => CHIP8_FAULT,
+        Ok(()) if !cpu.can_run() => CHIP8_HALTED,
+        Ok(()) => CHIP8_OK,
+    }
+}
+
+/// A machine with empty memory, its `Cxkk` results decided by `seed`
+#[unsafe(no_mangle)]
+pub extern "C" fn chip8_create(seed: u64) -> *mut Chip8 {
+    Box::into_raw(Box::new(Chip8 {
+        cpu: Cpu::new(&[], seed),
+        seed,
+    }))
+}
+
+/// # Safety
+/// `chip8` comes from `chip8_create` and isn't used again, or is null
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_destroy(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        drop(unsafe { Box::from_raw(chip8) });
+    }
+}
+
+/// Starts the machine over with the `len` bytes at `rom` as the program,
+/// keeping the speed and quirks
+///
+/// # Safety
+/// `chip8` comes from `chip8_create` and `rom` points to `len` bytes
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_load_rom(chip8: *mut Chip8, rom: *const u8, len: usize) -> i32 {
+    let chip8 = unsafe { &mut *chip8 };
+    if len > MAX_PROGRAM_SIZE {
+        return CHIP8_ROM_TOO_LARGE;
+    }
+    let program = if len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(rom, len) }
+    };
+    let mut cpu = Cpu::new(program, chip8.seed);
+    cpu.speed = chip8.cpu.speed;
+    cpu.quirks = chip8.cpu.quirks;
+    cpu.halt_on_self_jump = chip8.cpu.halt_on_self_jump;
+    chip8.cpu = cpu;
+    CHIP8_OK
+}
+
+/// Executes one instruction, without ticking the timers
+///
+/// # Safety
+/// `chip8` comes from `chip8_create`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_step(chip8: *mut Chip8) -> i32 {
+    let cpu = unsafe { &mut (*chip8).cpu };
+    if !cpu.can_run() {
+        return CHIP8_HALTED;
+    }
+    let result = cpu.step();
+    status(result, cpu)
+}
+
+/// Executes a frame's worth of instructions and ticks the timers, to be
+/// called 60 times a second
+///
+/// # Safety
+/// `chip8` comes from `chip8_create`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_run_frame(chip8: *mut Chip8) -> i32 {
+    let cpu = unsafe { &mut (*chip8).cpu };
+    let result = cpu.run_frame();
+    status(result, cpu)
+}
+
+/// Instructions per frame, 10 by default
+///
+/// # Safety
+/// `chip8` comes from `chip8_create`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_set_speed(chip8: *mut Chip8, speed: usize) {
+    unsafe { (*chip8).cpu.speed = speed };
+}
+
+/// Writes the 64x32 screen to `pixels`, a byte per pixel, 1 for on and 0
+/// for off, row by row from the top left
+///
+/// # Safety
+/// `chip8` comes from `chip8_create` and `pixels` has room for 2048 bytes
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_framebuffer(chip8: *const Chip8, pixels: *mut u8) {
+    let display = unsafe { &(*chip8).cpu.display };
+    let pixels = unsafe { slice::from_raw_parts_mut(pixels, 64 * 32) };
+    for (n, pixel) in pixels.iter_mut().enumerate() {
+        *pixel = display.pixel(n / 64, n % 64) as u8;
+    }
+}
+
+/// Holds key `key` (0 to 15) down or lets go of it, other keys are ignored
+///
+/// # Safety
+/// `chip8` comes from `chip8_create`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_set_key(chip8: *mut Chip8, key: u8, down: bool) {
+    if let Some(held) = unsafe { (*chip8).cpu.keypad.get_mut(key as usize) } {
+        *held = down;
+    }
+}
+
+/// Sets the whole keypad at once, key `n` in bit `n`
+///
+/// # Safety
+/// `chip8` comes from `chip8_create`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_set_keys(chip8: *mut Chip8, keys: u16) {
+    let keypad = unsafe { &mut (*chip8).cpu.keypad };
+    *keypad = std::array::from_fn(|key| keys & 1 << key != 0);
+}
+
+/// Whether the buzzer sounds, for as long as the sound timer runs
+///
+/// # Safety
+/// `chip8` comes from `chip8_create`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_sound(chip8: *const Chip8) -> bool {
+    unsafe { (*chip8).cpu.sound_timer > 0 }
+}
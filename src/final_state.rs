@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use cpu::Cpu;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// The machine when emulation ends, as `--dump-state` writes it. The names
+/// are those of the JSON trace.
+#[derive(Serialize)]
+struct FinalState {
+    v: [u8; 16],
+    i: u16,
+    pc: usize,
+    sp: usize,
+    /// The return addresses on the stack, oldest first
+    stack: Vec<u16>,
+    dt: u8,
+    st: u8,
+    halted: bool,
+    cycles: usize,
+    frames: u64,
+    /// The hash movies record for every frame, as 16 hex digits
+    display_hash: String,
+}
+
+fn state(cpu: &Cpu, frames: u64) -> FinalState {
+    FinalState {
+        v: cpu.registers,
+        i: cpu.register_i,
+        pc: cpu.position_in_memory,
+        sp: cpu.stack_pointer,
+        stack: cpu.stack[..cpu.stack_pointer].to_vec(),
+        dt: cpu.delay_timer,
+        st: cpu.sound_timer,
+        halted: cpu.halted,
+        cycles: cpu.cycles,
+        frames,
+        display_hash: format!("{:016x}", cpu.display.hash()),
+    }
+}
+
+fn to_json(cpu: &Cpu, frames: u64) -> String {
+    serde_json::to_string(&state(cpu, frames)).expect("the state serializes")
+}
+
+/// The same state as a JSON value, for the control API
+pub fn to_value(cpu: &Cpu, frames: u64) -> serde_json::Value {
+    serde_json::to_value(state(cpu, frames)).expect("the state serializes")
+}
+
+/// Writes the state of `cpu` after `frames` frames to `path`, or to
+/// standard output for `-`
+pub fn write(path: &Path, cpu: &Cpu, frames: u64) -> Result<()> {
+    let json = to_json(cpu, frames);
+    if crate::is_stdio(path) {
+        println!("{}", json);
+        return Ok(());
+    }
+    fs::write(path, json + "\n").with_context(|| format!("Couldn't write `{}`", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn has_the_registers_and_the_stack_in_use() {
+        let mut cpu = Cpu::new(&[0x22, 0x04, 0x00, 0x00, 0x6A, 0x2B], 0);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let json: serde_json::Value = serde_json::from_str(&to_json(&cpu, 3)).unwrap();
+        assert_eq!(json["v"][10], 0x2B);
+        assert_eq!(json["pc"], 0x206);
+        assert_eq!(json["stack"], serde_json::json!([0x202]));
+        assert_eq!(json["frames"], 3);
+        assert_eq!(json["display_hash"].as_str().unwrap().len(), 16);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::PROGRAM_START;
+use crate::disasm;
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Instructions behaving differently depending on a quirk, by quirk
+const QUIRK_PATTERNS: [(&str, &[&str]); 3] = [
+    ("shift", &["8xy6", "8xyE"]),
+    ("jump", &["Bnnn"]),
+    ("vf_reset", &["8xy1", "8xy2", "8xy3"]),
+];
+
+/// What the code reachable from the start of a program uses
+pub struct Scan {
+    /// How often each pattern occurs, by the platform it needs
+    pub opcodes: BTreeMap<(Platform, &'static str), usize>,
+    /// The highest address of the code, of jump and call targets and of
+    /// what `LD I` points at
+    pub highest: usize,
+}
+
+impl Scan {
+    pub fn new(program: &[u8]) -> Self {
+        let mut opcodes = BTreeMap::new();
+        let mut highest = PROGRAM_START;
+        for pc in disasm::reachable(program).into_keys() {
+            let offset = pc - PROGRAM_START;
+            let opcode = u16::from_be_bytes([program[offset], program[offset + 1]]);
+            highest = highest.max(pc + 1);
+            if let Some(found) = Platform::of(opcode) {
+                *opcodes.entry(found).or_default() += 1;
+            }
+            match Instruction::decode(opcode) {
+                Instruction::Jump { addr }
+                | Instruction::Call { addr }
+                | Instruction::SetI { addr } => highest = highest.max(addr as usize),
+                _ => {}
+            }
+        }
+        Self { opcodes, highest }
+    }
+
+    /// The platform with every opcode the program uses
+    pub fn platform(&self) -> Platform {
+        self.opcodes
+            .keys()
+            .map(|&(platform, _)| platform)
+            .max()
+            .unwrap_or(Platform::Chip8)
+    }
+
+    fn count(&self, pattern: &str) -> usize {
+        self.opcodes
+            .iter()
+            .filter(|((_, p), _)| *p == pattern)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+/// A summary of the program for picking the platform and quirks to run it
+/// with
+pub fn report(program: &[u8]) -> String {
+    let scan = Scan::new(program);
+    let mut text = String::new();
+    writeln!(text, "Size:            {} bytes", program.len()).unwrap();
+    writeln!(text, "SHA-256:         {}", crate::movie::rom_hash(program)).unwrap();
+    writeln!(text, "Highest address: {:03X}", scan.highest).unwrap();
+    writeln!(text, "Platform:        {}", scan.platform()).unwrap();
+
+    for platform in [Platform::Schip, Platform::XoChip] {
+        let used: Vec<String> = scan
+            .opcodes
+            .iter()
+            .filter(|((p, _), _)| *p == platform)
+            .map(|((_, pattern), count)| format!("{} x{}", pattern, count))
+            .collect();
+        if !used.is_empty() {
+            writeln!(text, "{} opcodes:  {}", platform, used.join(", ")).unwrap();
+        }
+    }
+
+    writeln!(text, "Quirk-sensitive instructions:").unwrap();
+    for (quirk, patterns) in QUIRK_PATTERNS {
+        let used: Vec<String> = patterns
+            .iter()
+            .map(|pattern| (pattern, scan.count(pattern)))
+            .filter(|&(_, count)| count > 0)
+            .map(|(pattern, count)| format!("{} x{}", pattern, count))
+            .collect();
+        let used = match used.is_empty() {
+            true => "not used".to_string(),
+            false => used.join(", "),
+        };
+        writeln!(text, "  {:<9} {}", quirk, used).unwrap();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_extensions_and_quirks() {
+        let program = [
+            0x00, 0xFF, // hires
+            0x81, 0x26, // SHR V1, V2
+            0x81, 0x21, // OR V1, V2
+            0x81, 0x2E, // SHL V1, V2
+            0xA3, 0x00, // LD I, 300
+            0xD1, 0x20, // 16x16 sprite
+            0x12, 0x0C, // JP 20C
+            0x00, 0xFF, // data, not code
+        ];
+        let scan = Scan::new(&program);
+        assert_eq!(scan.platform(), Platform::Schip);
+        assert_eq!(scan.highest, 0x300);
+        assert_eq!(scan.count("00FF"), 1);
+
+        let report = report(&program);
+        assert!(report.contains("SCHIP opcodes:  00FF x1, Dxy0 x1\n"));
+        assert!(report.contains("  shift     8xy6 x1, 8xyE x1\n"));
+        assert!(report.contains("  jump      not used\n"));
+        assert!(report.contains("  vf_reset  8xy1 x1\n"));
+    }
+
+    #[test]
+    fn plain_chip8() {
+        let program = [0x60, 0x01, 0xF0, 0x29, 0x12, 0x04];
+        assert_eq!(Scan::new(&program).platform(), Platform::Chip8);
+    }
+}
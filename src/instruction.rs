@@ -0,0 +1,306 @@
+use std::fmt;
+
+/// A decoded CHIP-8 instruction, shared by the interpreter and the debugger.
+///
+/// `x` and `y` are register numbers, `kk` a byte, `n` a nibble and `addr` a
+/// 12-bit address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0000, stops the emulator
+    Halt,
+    /// 00E0
+    Clear,
+    /// 00EE
+    Return,
+    /// 1nnn
+    Jump {
+        addr: u16,
+    },
+    /// 2nnn
+    Call {
+        addr: u16,
+    },
+    /// 3xkk
+    SkipEq {
+        x: u8,
+        kk: u8,
+    },
+    /// 4xkk
+    SkipNe {
+        x: u8,
+        kk: u8,
+    },
+    /// 5xy0
+    SkipEqRegisters {
+        x: u8,
+        y: u8,
+    },
+    /// 6xkk
+    Load {
+        x: u8,
+        kk: u8,
+    },
+    /// 7xkk
+    Add {
+        x: u8,
+        kk: u8,
+    },
+    /// 8xy0
+    Set {
+        x: u8,
+        y: u8,
+    },
+    /// 8xy1
+    Or {
+        x: u8,
+        y: u8,
+    },
+    /// 8xy2
+    And {
+        x: u8,
+        y: u8,
+    },
+    /// 8xy3
+    Xor {
+        x: u8,
+        y: u8,
+    },
+    /// 8xy4
+    AddRegisters {
+        x: u8,
+        y: u8,
+    },
+    /// 8xy5
+    Sub {
+        x: u8,
+        y: u8,
+    },
+    /// 8xy6
+    Shr {
+        x: u8,
+        y: u8,
+    },
+    /// 8xy7
+    SubN {
+        x: u8,
+        y: u8,
+    },
+    /// 8xyE
+    Shl {
+        x: u8,
+        y: u8,
+    },
+    /// 9xy0
+    SkipNeRegisters {
+        x: u8,
+        y: u8,
+    },
+    /// Annn
+    SetI {
+        addr: u16,
+    },
+    /// Bnnn
+    JumpOffset {
+        x: u8,
+        addr: u16,
+    },
+    /// Cxkk
+    Random {
+        x: u8,
+        kk: u8,
+    },
+    /// Dxyn
+    Draw {
+        x: u8,
+        y: u8,
+        n: u8,
+    },
+    /// Ex9E
+    SkipKey {
+        x: u8,
+    },
+    /// ExA1
+    SkipNotKey {
+        x: u8,
+    },
+    /// Fx07
+    LoadDelayTimer {
+        x: u8,
+    },
+    /// Fx0A
+    WaitKey {
+        x: u8,
+    },
+    /// Fx15
+    SetDelayTimer {
+        x: u8,
+    },
+    /// Fx18
+    SetSoundTimer {
+        x: u8,
+    },
+    /// Fx1E
+    AddI {
+        x: u8,
+    },
+    /// Fx29
+    LoadFont {
+        x: u8,
+    },
+    /// Fx33
+    StoreBcd {
+        x: u8,
+    },
+    /// Fx55
+    StoreRegisters {
+        x: u8,
+    },
+    /// Fx65
+    LoadRegisters {
+        x: u8,
+    },
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Self {
+        use Instruction::*;
+
+        let c = ((opcode >> 12) & 0x000F) as u8;
+        let x = ((opcode >> 8) & 0x000F) as u8;
+        let y = ((opcode >> 4) & 0x000F) as u8;
+        let d = (opcode & 0x000F) as u8;
+
+        let addr = opcode & 0x0FFF;
+        let kk = (opcode & 0x00FF) as u8;
+
+        match (c, x, y, d) {
+            (0, 0, 0, 0) => Halt,
+            (0, 0, 0xE, 0) => Clear,
+            (0, 0, 0xE, 0xE) => Return,
+            (0x1, _, _, _) => Jump { addr },
+            (0x2, _, _, _) => Call { addr },
+            (0x3, _, _, _) => SkipEq { x, kk },
+            (0x4, _, _, _) => SkipNe { x, kk },
+            (0x5, _, _, 0) => SkipEqRegisters { x, y },
+            (0x6, _, _, _) => Load { x, kk },
+            (0x7, _, _, _) => Add { x, kk },
+            (0x8, _, _, 0x0) => Set { x, y },
+            (0x8, _, _, 0x1) => Or { x, y },
+            (0x8, _, _, 0x2) => And { x, y },
+            (0x8, _, _, 0x3) => Xor { x, y },
+            (0x8, _, _, 0x4) => AddRegisters { x, y },
+            (0x8, _, _, 0x5) => Sub { x, y },
+            (0x8, _, _, 0x6) => Shr { x, y },
+            (0x8, _, _, 0x7) => SubN { x, y },
+            (0x8, _, _, 0xE) => Shl { x, y },
+            (0x9, _, _, 0x0) => SkipNeRegisters { x, y },
+            (0xA, _, _, _) => SetI { addr },
+            (0xB, _, _, _) => JumpOffset { x, addr },
+            (0xC, _, _, _) => Random { x, kk },
+            (0xD, _, _, _) => Draw { x, y, n: d },
+            (0xE, _, 0x9, 0xE) => SkipKey { x },
+            (0xE, _, 0xA, 0x1) => SkipNotKey { x },
+            (0xF, _, 0x0, 0x7) => LoadDelayTimer { x },
+            (0xF, _, 0x0, 0xA) => WaitKey { x },
+            (0xF, _, 0x1, 0x5) => SetDelayTimer { x },
+            (0xF, _, 0x1, 0x8) => SetSoundTimer { x },
+            (0xF, _, 0x1, 0xE) => AddI { x },
+            (0xF, _, 0x2, 0x9) => LoadFont { x },
+            (0xF, _, 0x3, 0x3) => StoreBcd { x },
+            (0xF, _, 0x5, 0x5) => StoreRegisters { x },
+            (0xF, _, 0x6, 0x5) => LoadRegisters { x },
+            _ => Unknown(opcode),
+        }
+    }
+
+    /// The opcode pattern, like `6xkk`, to group instructions by
+    pub fn pattern(&self) -> &'static str {
+        use Instruction::*;
+
+        match self {
+            Halt => "0000",
+            Clear => "00E0",
+            Return => "00EE",
+            Jump { .. } => "1nnn",
+            Call { .. } => "2nnn",
+            SkipEq { .. } => "3xkk",
+            SkipNe { .. } => "4xkk",
+            SkipEqRegisters { .. } => "5xy0",
+            Load { .. } => "6xkk",
+            Add { .. } => "7xkk",
+            Set { .. } => "8xy0",
+            Or { .. } => "8xy1",
+            And { .. } => "8xy2",
+            Xor { .. } => "8xy3",
+            AddRegisters { .. } => "8xy4",
+            Sub { .. } => "8xy5",
+            Shr { .. } => "8xy6",
+            SubN { .. } => "8xy7",
+            Shl { .. } => "8xyE",
+            SkipNeRegisters { .. } => "9xy0",
+            SetI { .. } => "Annn",
+            JumpOffset { .. } => "Bnnn",
+            Random { .. } => "Cxkk",
+            Draw { .. } => "Dxyn",
+            SkipKey { .. } => "Ex9E",
+            SkipNotKey { .. } => "ExA1",
+            LoadDelayTimer { .. } => "Fx07",
+            WaitKey { .. } => "Fx0A",
+            SetDelayTimer { .. } => "Fx15",
+            SetSoundTimer { .. } => "Fx18",
+            AddI { .. } => "Fx1E",
+            LoadFont { .. } => "Fx29",
+            StoreBcd { .. } => "Fx33",
+            StoreRegisters { .. } => "Fx55",
+            LoadRegisters { .. } => "Fx65",
+            Unknown(_) => "????",
+        }
+    }
+}
+
+/// Cowgod's mnemonics, e.g. `LD V3, 1F`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            Halt => write!(f, "HALT"),
+            Clear => write!(f, "CLS"),
+            Return => write!(f, "RET"),
+            Jump { addr } => write!(f, "JP {:03X}", addr),
+            Call { addr } => write!(f, "CALL {:03X}", addr),
+            SkipEq { x, kk } => write!(f, "SE V{:X}, {:02X}", x, kk),
+            SkipNe { x, kk } => write!(f, "SNE V{:X}, {:02X}", x, kk),
+            SkipEqRegisters { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Load { x, kk } => write!(f, "LD V{:X}, {:02X}", x, kk),
+            Add { x, kk } => write!(f, "ADD V{:X}, {:02X}", x, kk),
+            Set { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddRegisters { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Shr { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubN { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Shl { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SkipNeRegisters { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            SetI { addr } => write!(f, "LD I, {:03X}", addr),
+            JumpOffset { addr, .. } => write!(f, "JP V0, {:03X}", addr),
+            Random { x, kk } => write!(f, "RND V{:X}, {:02X}", x, kk),
+            Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {:X}", x, y, n),
+            SkipKey { x } => write!(f, "SKP V{:X}", x),
+            SkipNotKey { x } => write!(f, "SKNP V{:X}", x),
+            LoadDelayTimer { x } => write!(f, "LD V{:X}, DT", x),
+            WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            SetDelayTimer { x } => write!(f, "LD DT, V{:X}", x),
+            SetSoundTimer { x } => write!(f, "LD ST, V{:X}", x),
+            AddI { x } => write!(f, "ADD I, V{:X}", x),
+            LoadFont { x } => write!(f, "LD F, V{:X}", x),
+            StoreBcd { x } => write!(f, "LD B, V{:X}", x),
+            StoreRegisters { x } => write!(f, "LD [I], V{:X}", x),
+            LoadRegisters { x } => write!(f, "LD V{:X}, [I]", x),
+            Unknown(opcode) => write!(f, "DW {:04X}", opcode),
+        }
+    }
+}
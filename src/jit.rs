@@ -0,0 +1,283 @@
+use crate::instruction::Instruction;
+use crate::{Cpu, Fault};
+use anyhow::{Context, Result};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{AbiParam, InstBuilder, MemFlagsData, Value, types};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{Module, default_libcall_names};
+use std::ops::Range;
+
+/// Most instructions compiled into one block
+const MAX_BLOCK: usize = 32;
+
+/// Compiled code for a block: takes pointers to V0-VF and I, returns the
+/// next PC
+type BlockFn = extern "C" fn(*mut u8, *mut u16) -> u32;
+
+/// A compiled block, or an address the interpreter has to handle
+struct Block {
+    range: Range<usize>,
+    code: Option<BlockFn>,
+    /// Instructions in the block, including the jump or skip ending it
+    len: usize,
+}
+
+/// Compiles basic blocks of register arithmetic, optionally ending in a
+/// jump or a skip, to native code with cranelift. Everything else, and
+/// code in memory the program has written to, is left to the interpreter.
+pub struct Jit {
+    module: JITModule,
+    builder_context: FunctionBuilderContext,
+    /// Blocks by the address they start at
+    blocks: Vec<Option<Block>>,
+    /// Addresses the program has written to, never compiled
+    written: Box<[bool; 0x1000]>,
+}
+
+impl Jit {
+    pub fn new() -> Result<Self> {
+        let builder = JITBuilder::new(default_libcall_names())
+            .context("The JIT doesn't support this machine")?;
+        Ok(Self {
+            module: JITModule::new(builder),
+            builder_context: FunctionBuilderContext::new(),
+            blocks: (0..0x1000).map(|_| None).collect(),
+            written: Box::new([false; 0x1000]),
+        })
+    }
+
+    /// Like `Cpu::run_frame`, running compiled blocks where it can. Falls
+    /// back to the interpreter while instructions are being recorded.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Result<(), Fault> {
+        // pokes between frames
+        for (addr, _) in cpu.writes.drain(..) {
+            self.invalidate(addr);
+        }
+        if cpu.executed.is_some() {
+            return cpu.run_frame();
+        }
+
+        let mut budget = cpu.speed;
+        while budget > 0 && cpu.can_run() {
+            let pc = cpu.position_in_memory;
+            if pc >= self.blocks.len() {
+                // let the interpreter report the PC leaving memory
+                return cpu.step();
+            }
+            let block = self.block(cpu, pc);
+            let (code, len) = (block.code, block.len);
+            match code {
+                Some(code) if len <= budget && len <= cpu.max_cycles - cpu.cycles => {
+                    let next = code(cpu.registers.as_mut_ptr(), &mut cpu.register_i);
+                    cpu.position_in_memory = next as usize;
+                    cpu.cycles += len;
+                    budget -= len;
+                }
+                _ => {
+                    cpu.step()?;
+                    for &(addr, _) in &cpu.writes {
+                        self.invalidate(addr);
+                    }
+                    budget -= 1;
+                }
+            }
+        }
+
+        cpu.tick_timers();
+        Ok(())
+    }
+
+    /// Forgets compiled code containing `addr` and never compiles it again
+    fn invalidate(&mut self, addr: usize) {
+        self.written[addr] = true;
+        let first = (addr + 1).saturating_sub(2 * MAX_BLOCK);
+        for block in &mut self.blocks[first..=addr] {
+            if block.as_ref().is_some_and(|b| b.range.contains(&addr)) {
+                *block = None;
+            }
+        }
+    }
+
+    fn block(&mut self, cpu: &Cpu, pc: usize) -> &Block {
+        if self.blocks[pc].is_none() {
+            self.blocks[pc] = Some(self.compile(cpu, pc));
+        }
+        self.blocks[pc].as_ref().unwrap()
+    }
+
+    /// The instructions at `pc` that can be compiled, the last one possibly
+    /// a jump or a skip
+    fn scan(&self, cpu: &Cpu, pc: usize) -> Vec<Instruction> {
+        use Instruction::*;
+
+        let mut instructions = Vec::new();
+        let mut addr = pc;
+        while instructions.len() < MAX_BLOCK
+            && addr + 1 < cpu.memory.len()
+            && !self.written[addr]
+            && !self.written[addr + 1]
+        {
+            let opcode = u16::from_be_bytes([cpu.memory[addr], cpu.memory[addr + 1]]);
+            let instruction = Instruction::decode(opcode);
+            match instruction {
+                // the interpreter halts on it
+                Jump { addr: target } if cpu.halt_on_self_jump && target as usize == addr => break,
+                Load { .. }
+                | Add { .. }
+                | Set { .. }
+                | Or { .. }
+                | And { .. }
+                | Xor { .. }
+                | AddRegisters { .. }
+                | Sub { .. }
+                | Shr { .. }
+                | SubN { .. }
+                | Shl { .. }
+                | SetI { .. } => instructions.push(instruction),
+                Jump { .. }
+                | SkipEq { .. }
+                | SkipNe { .. }
+                | SkipEqRegisters { .. }
+                | SkipNeRegisters { .. } => {
+                    instructions.push(instruction);
+                    break;
+                }
+                _ => break,
+            }
+            addr += 2;
+        }
+        instructions
+    }
+
+    fn compile(&mut self, cpu: &Cpu, pc: usize) -> Block {
+        let instructions = self.scan(cpu, pc);
+        let code = match instructions.is_empty() {
+            true => None,
+            // a block that fails to compile is interpreted instead
+            false => self.translate(cpu, pc, &instructions).ok(),
+        };
+        Block {
+            range: pc..pc + 2 * instructions.len().max(1),
+            code,
+            len: instructions.len(),
+        }
+    }
+
+    fn translate(&mut self, cpu: &Cpu, pc: usize, instructions: &[Instruction]) -> Result<BlockFn> {
+        use Instruction::*;
+
+        let config = self.module.target_config();
+        let pointer = config.pointer_type();
+        let mut context = self.module.make_context();
+        context.func.signature.params.push(AbiParam::new(pointer));
+        context.func.signature.params.push(AbiParam::new(pointer));
+        context
+            .func
+            .signature
+            .returns
+            .push(AbiParam::new(types::I32));
+        let id = self
+            .module
+            .declare_anonymous_function(&context.func.signature)?;
+
+        let mut b = FunctionBuilder::new(&mut context.func, &mut self.builder_context);
+        let entry = b.create_block();
+        b.append_block_params_for_function_params(entry);
+        b.switch_to_block(entry);
+        b.seal_block(entry);
+        let (v_ptr, i_ptr) = (b.block_params(entry)[0], b.block_params(entry)[1]);
+        let flags = MemFlagsData::trusted();
+
+        let mut v: Vec<Value> = (0..16)
+            .map(|r| b.ins().load(types::I8, flags, v_ptr, r))
+            .collect();
+        let mut i = None;
+        let mut next = b
+            .ins()
+            .iconst(types::I32, (pc + 2 * instructions.len()) as i64);
+        let zero = b.ins().iconst(types::I8, 0);
+
+        for &instruction in instructions {
+            let skip = |b: &mut FunctionBuilder, cc: IntCC, lhs: Value, rhs: Value, next: Value| {
+                let taken = b.ins().icmp(cc, lhs, rhs);
+                let skipped = b.ins().iadd_imm_u(next, 2);
+                b.ins().select(taken, skipped, next)
+            };
+            match instruction {
+                Load { x, kk } => v[x as usize] = b.ins().iconst(types::I8, kk as i64),
+                Add { x, kk } => v[x as usize] = b.ins().iadd_imm_u(v[x as usize], kk as i64),
+                Set { x, y } => v[x as usize] = v[y as usize],
+                Or { x, y } | And { x, y } | Xor { x, y } => {
+                    let (a, c) = (v[x as usize], v[y as usize]);
+                    v[x as usize] = match instruction {
+                        Or { .. } => b.ins().bor(a, c),
+                        And { .. } => b.ins().band(a, c),
+                        _ => b.ins().bxor(a, c),
+                    };
+                    if cpu.quirks.vf_reset {
+                        v[0xF] = zero;
+                    }
+                }
+                AddRegisters { x, y } => {
+                    let sum = b.ins().iadd(v[x as usize], v[y as usize]);
+                    let carry = b.ins().icmp(IntCC::UnsignedLessThan, sum, v[x as usize]);
+                    v[x as usize] = sum;
+                    v[0xF] = carry;
+                }
+                Sub { x, y } | SubN { x, y } => {
+                    let (a, c) = match instruction {
+                        Sub { .. } => (v[x as usize], v[y as usize]),
+                        _ => (v[y as usize], v[x as usize]),
+                    };
+                    let no_borrow = b.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, a, c);
+                    v[x as usize] = b.ins().isub(a, c);
+                    v[0xF] = no_borrow;
+                }
+                Shr { x, y } | Shl { x, y } => {
+                    let value = v[if cpu.quirks.shift { x } else { y } as usize];
+                    let (shifted, flag) = match instruction {
+                        Shr { .. } => (b.ins().ushr_imm_u(value, 1), b.ins().band_imm_u(value, 1)),
+                        _ => (b.ins().ishl_imm_u(value, 1), b.ins().ushr_imm_u(value, 7)),
+                    };
+                    v[x as usize] = shifted;
+                    v[0xF] = flag;
+                }
+                SetI { addr } => i = Some(b.ins().iconst(types::I16, addr as i64)),
+                Jump { addr } => next = b.ins().iconst(types::I32, addr as i64),
+                SkipEq { x, kk } | SkipNe { x, kk } => {
+                    let kk = b.ins().iconst(types::I8, kk as i64);
+                    let cc = match instruction {
+                        SkipEq { .. } => IntCC::Equal,
+                        _ => IntCC::NotEqual,
+                    };
+                    next = skip(&mut b, cc, v[x as usize], kk, next);
+                }
+                SkipEqRegisters { x, y } | SkipNeRegisters { x, y } => {
+                    let cc = match instruction {
+                        SkipEqRegisters { .. } => IntCC::Equal,
+                        _ => IntCC::NotEqual,
+                    };
+                    next = skip(&mut b, cc, v[x as usize], v[y as usize], next);
+                }
+                _ => unreachable!("`scan` only returns compilable instructions"),
+            }
+        }
+
+        for (r, &value) in v.iter().enumerate() {
+            b.ins().store(flags, value, v_ptr, r as i32);
+        }
+        if let Some(i) = i {
+            b.ins().store(flags, i, i_ptr, 0);
+        }
+        b.ins().return_(&[next]);
+        b.finalize(config);
+
+        self.module.define_function(id, &mut context)?;
+        self.module.clear_context(&mut context);
+        self.module.finalize_definitions()?;
+        let code = self.module.get_finalized_function(id);
+        // SAFETY: the function was built with the signature of `BlockFn`
+        Ok(unsafe { std::mem::transmute::<*const u8, BlockFn>(code) })
+    }
+}
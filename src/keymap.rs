@@ -0,0 +1,145 @@
+use anyhow::{Result, anyhow};
+use minifb::{Key, Window};
+
+/// Host keys for the CHIP8 keypad, indexed by key value. The default layout:
+/// ```text
+/// 1 2 3 C      1 2 3 4
+/// 4 5 6 D  ->  Q W E R
+/// 7 8 9 E      A S D F
+/// A 0 B F      Z X C V
+/// ```
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    keys: [Key; 16],
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            keys: [
+                Key::X,
+                Key::Key1,
+                Key::Key2,
+                Key::Key3,
+                Key::Q,
+                Key::W,
+                Key::E,
+                Key::A,
+                Key::S,
+                Key::D,
+                Key::Z,
+                Key::C,
+                Key::Key4,
+                Key::R,
+                Key::F,
+                Key::V,
+            ],
+        }
+    }
+}
+
+impl Keymap {
+    /// Binds the keypad key given as a hex digit (`"a"`) to a host key named
+    /// like minifb's `Key` variants (`"Space"`, `"Left"`, `"Key1"`)
+    pub fn bind(&mut self, keypad_key: &str, host_key: &str) -> Result<()> {
+        let index = u8::from_str_radix(keypad_key, 16)
+            .ok()
+            .filter(|&k| k < 16)
+            .ok_or_else(|| anyhow!("`{}` is not a keypad key (0-F)", keypad_key))?;
+        let key =
+            key_by_name(host_key).ok_or_else(|| anyhow!("unknown host key `{}`", host_key))?;
+        self.keys[index as usize] = key;
+        Ok(())
+    }
+
+    /// Rotates the bindings of the 1-9 block, which games use as a d-pad,
+    /// counterclockwise `quarter_turns` times, so that directions still
+    /// match a screen rotated clockwise by as much
+    pub fn rotate(&mut self, quarter_turns: usize) {
+        const BLOCK: [[usize; 3]; 3] = [[1, 2, 3], [4, 5, 6], [7, 8, 9]];
+
+        for _ in 0..quarter_turns % 4 {
+            let old = self.keys;
+            for (row, keys) in BLOCK.iter().enumerate() {
+                for (col, &key) in keys.iter().enumerate() {
+                    self.keys[BLOCK[2 - col][row]] = old[key];
+                }
+            }
+        }
+    }
+
+    /// The keypad keys held down, leaving out those bound to one of `taken`
+    pub fn read(&self, window: &Window, taken: &[Key]) -> [bool; 16] {
+        self.keys
+            .map(|key| !taken.contains(&key) && window.is_key_down(key))
+    }
+}
+
+const BINDABLE_KEYS: [Key; 60] = [
+    Key::Key0,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+    Key::Key8,
+    Key::Key9,
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+    Key::Up,
+    Key::Down,
+    Key::Left,
+    Key::Right,
+    Key::Space,
+    Key::Enter,
+    Key::Tab,
+    Key::Backspace,
+    Key::LeftShift,
+    Key::RightShift,
+    Key::LeftCtrl,
+    Key::RightCtrl,
+    Key::NumPad0,
+    Key::NumPad1,
+    Key::NumPad2,
+    Key::NumPad3,
+    Key::NumPad4,
+    Key::NumPad5,
+    Key::NumPad6,
+    Key::NumPad7,
+    Key::NumPad8,
+    Key::NumPad9,
+    Key::NumPadPlus,
+    Key::NumPadMinus,
+];
+
+fn key_by_name(name: &str) -> Option<Key> {
+    BINDABLE_KEYS
+        .into_iter()
+        .find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+}
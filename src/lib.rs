@@ -0,0 +1,663 @@
+//! The CHIP8 machine itself, without a window, so that it can be benchmarked
+
+use instruction::Instruction;
+use serde::{Deserialize, Serialize};
+use trace::{Executed, Registers};
+
+pub mod block_cache;
+mod dispatch;
+// the C interface means nothing on wasm32, and its exports clash with the JS
+// bindings in wasm/
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ffi;
+pub mod instruction;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod reference;
+pub mod symbols;
+pub mod trace;
+
+#[cfg(test)]
+mod tests;
+
+/// Where programs are loaded and execution starts
+pub const PROGRAM_START: usize = 0x200;
+/// Largest program that fits in memory after `PROGRAM_START`
+pub const MAX_PROGRAM_SIZE: usize = 0x1000 - PROGRAM_START;
+pub const INSTRUCTIONS_PER_FRAME: usize = 10;
+/// Where the hex digit sprites Fx29 points I at are loaded
+pub const FONT_START: usize = 0x50;
+
+/// The digits 0 to F, 4x5 pixels each in the high nibble of five bytes
+const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+/// Something the program did that the machine can't carry out. The PC is
+/// left at the faulting instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    UnknownOpcode {
+        pc: usize,
+        opcode: u16,
+    },
+    StackOverflow {
+        pc: usize,
+    },
+    StackUnderflow {
+        pc: usize,
+    },
+    /// A jump or a skip took the PC past the end of memory
+    PcOutOfBounds {
+        pc: usize,
+    },
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Fault::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode {:04X} at {:04X}", opcode, pc)
+            }
+            Fault::StackOverflow { pc } => {
+                write!(f, "Stack overflow: call with a full stack at {:04X}", pc)
+            }
+            Fault::StackUnderflow { pc } => {
+                write!(
+                    f,
+                    "Stack underflow: return with an empty stack at {:04X}",
+                    pc
+                )
+            }
+            Fault::PcOutOfBounds { pc } => write!(f, "PC {:04X} is outside of memory", pc),
+        }
+    }
+}
+
+impl std::error::Error for Fault {}
+
+/// Behaviours that differ between CHIP8 implementations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Quirks {
+    /// 8xy6/8xyE shift Vx in place instead of loading the shifted Vy
+    pub shift: bool,
+    /// Bnnn jumps to xnn + Vx instead of nnn + V0
+    pub jump: bool,
+    /// 8xy1/8xy2/8xy3 reset VF to zero
+    pub vf_reset: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift: true,
+            jump: true,
+            vf_reset: false,
+        }
+    }
+}
+
+impl Quirks {
+    /// What programs for the original COSMAC VIP interpreter expect
+    pub const CHIP8: Quirks = Quirks {
+        shift: false,
+        jump: false,
+        vf_reset: true,
+    };
+    /// What programs for SCHIP on the HP 48 expect
+    pub const SCHIP: Quirks = Quirks {
+        shift: true,
+        jump: true,
+        vf_reset: false,
+    };
+    /// What programs for Octo's XO-CHIP expect
+    pub const XOCHIP: Quirks = Quirks {
+        shift: false,
+        jump: false,
+        vf_reset: false,
+    };
+}
+
+#[derive(Debug, Clone)]
+pub struct Cpu {
+    pub registers: [u8; 16],
+    pub register_i: u16,
+    pub position_in_memory: usize,
+    pub memory: [u8; 0x1000],
+    pub stack: [u16; 16],
+    pub stack_pointer: usize,
+    pub display: Display,
+    pub keypad: [bool; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub rng: Rng,
+    pub halted: bool,
+    /// Halt on a `1NNN` jump to itself, the loop many programs end with
+    pub halt_on_self_jump: bool,
+    pub cycles: usize,
+    /// Instructions to execute before stopping, unlimited by default
+    pub max_cycles: usize,
+    pub speed: usize,
+    pub quirks: Quirks,
+    /// Addresses written by the last instruction, or by `poke` since, and
+    /// their previous values
+    pub writes: Vec<(usize, u8)>,
+    /// Instructions executed since the last `take_executed`, when tracing
+    pub executed: Option<Vec<Executed>>,
+    /// Set by an instruction that faulted, for `step` to return
+    fault: Option<Fault>,
+}
+
+impl Cpu {
+    /// Machine state for the debug overlay
+    pub fn status_lines(&self) -> Vec<String> {
+        let registers = |range: std::ops::Range<usize>| {
+            self.registers[range]
+                .iter()
+                .map(|r| format!("{:02X}", r))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        vec![
+            format!(
+                "V0-V7 {}  I {:04X}  PC {:04X}",
+                registers(0..8),
+                self.register_i,
+                self.position_in_memory
+            ),
+            format!(
+                "V8-VF {}  SP {:X}  DT {:02X}  ST {:02X}",
+                registers(8..16),
+                self.stack_pointer,
+                self.delay_timer,
+                self.sound_timer
+            ),
+            self.keypad_line(),
+        ]
+    }
+
+    /// Keys held down, and the register Fx0A is waiting to store a key in
+    fn keypad_line(&self) -> String {
+        let keys: String = self
+            .keypad
+            .iter()
+            .enumerate()
+            .map(|(key, &down)| {
+                if down {
+                    char::from_digit(key as u32, 16)
+                        .unwrap()
+                        .to_ascii_uppercase()
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let mut line = format!("KEYS {}", keys);
+        if let Instruction::WaitKey { x } = Instruction::decode(self.read_opcode()) {
+            line += &format!("  WAITING FOR KEY IN V{:X}", x);
+        }
+        line
+    }
+
+    pub fn new(program: &[u8], seed: u64) -> Self {
+        let mut cpu = Cpu {
+            registers: [0; 16],
+            register_i: 0,
+            memory: [0; 4096],
+            position_in_memory: PROGRAM_START,
+            stack: [0; 16],
+            stack_pointer: 0,
+            display: Display::new(),
+            keypad: [false; 16],
+            delay_timer: 0,
+            sound_timer: 0,
+            rng: Rng::new(seed),
+            halted: false,
+            halt_on_self_jump: false,
+            cycles: 0,
+            max_cycles: usize::MAX,
+            speed: INSTRUCTIONS_PER_FRAME,
+            quirks: Quirks::default(),
+            writes: Vec::new(),
+            executed: None,
+            fault: None,
+        };
+
+        cpu.memory[FONT_START..FONT_START + FONT.len()].copy_from_slice(&FONT);
+        cpu.memory[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(program);
+
+        cpu
+    }
+
+    /// The opcode at the PC, reading bytes outside of memory as zero
+    pub fn read_opcode(&self) -> u16 {
+        let p = self.position_in_memory;
+        let byte = |addr: usize| self.memory.get(addr).copied().unwrap_or(0) as u16;
+
+        byte(p) << 8 | byte(p + 1)
+    }
+
+    /// Writes to memory from outside the program, for cheats and scripts.
+    /// The write goes into `writes` like a store the program makes, so that
+    /// the block cache and the JIT drop the code at `addr` before the next
+    /// frame.
+    pub fn poke(&mut self, addr: usize, value: u8) {
+        self.write_memory(addr, value);
+    }
+
+    pub fn run_frame(&mut self) -> Result<(), Fault> {
+        // pokes since the last frame, which only cached code cares about
+        self.writes.clear();
+        for _ in 0..self.speed {
+            if !self.can_run() {
+                break;
+            }
+            self.step()?;
+        }
+
+        self.tick_timers();
+        Ok(())
+    }
+
+    pub fn trace_registers(&self) -> Registers {
+        Registers {
+            v: self.registers,
+            i: self.register_i,
+            sp: self.stack_pointer,
+            dt: self.delay_timer,
+            st: self.sound_timer,
+        }
+    }
+
+    /// Instructions executed since the last call, if recording them was
+    /// enabled by setting `executed`
+    pub fn take_executed(&mut self) -> Vec<Executed> {
+        self.executed
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    pub fn can_run(&self) -> bool {
+        !self.halted && self.cycles < self.max_cycles
+    }
+
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    pub fn step(&mut self) -> Result<(), Fault> {
+        let pc = self.position_in_memory;
+        if pc + 1 >= self.memory.len() {
+            return Err(Fault::PcOutOfBounds { pc });
+        }
+
+        self.cycles += 1;
+        self.writes.clear();
+        let opcode = self.read_opcode();
+        let before = self.executed.is_some().then(|| self.trace_registers());
+        self.position_in_memory += 2;
+
+        dispatch::execute(self, opcode);
+        if let Some(fault) = self.fault.take() {
+            self.position_in_memory = pc;
+            return Err(fault);
+        }
+
+        if let Some(before) = before {
+            let after = self.trace_registers();
+            if let Some(executed) = &mut self.executed {
+                executed.push(Executed {
+                    pc,
+                    opcode,
+                    before,
+                    after,
+                    writes: self.writes.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reports `fault` for the instruction being executed, whose address is
+    /// two bytes behind the PC
+    fn fault(&mut self, fault: impl FnOnce(usize) -> Fault) {
+        self.fault = Some(fault(self.position_in_memory - 2));
+    }
+
+    fn call(&mut self, addr: u16) {
+        let sp = self.stack_pointer;
+        let stack = &mut self.stack;
+
+        if sp >= stack.len() {
+            return self.fault(|pc| Fault::StackOverflow { pc });
+        }
+
+        stack[sp] = self.position_in_memory as u16;
+        self.stack_pointer += 1;
+        self.position_in_memory = addr as usize;
+    }
+
+    fn ret(&mut self) {
+        if self.stack_pointer == 0 {
+            return self.fault(|pc| Fault::StackUnderflow { pc });
+        }
+
+        self.stack_pointer -= 1;
+        let call_addr = self.stack[self.stack_pointer];
+        self.position_in_memory = call_addr as usize;
+    }
+
+    fn jmp_to_addr(&mut self, addr: u16) {
+        if self.halt_on_self_jump && addr as usize == self.position_in_memory - 2 {
+            self.halted = true;
+        }
+        self.position_in_memory = addr as usize;
+    }
+
+    fn skip_if_eq(&mut self, x: u8, kk: u8) {
+        if self.registers[x as usize] == kk {
+            self.position_in_memory += 2;
+        }
+    }
+
+    fn skip_if_neq(&mut self, r: u8, kk: u8) {
+        if self.registers[r as usize] != kk {
+            self.position_in_memory += 2;
+        }
+    }
+
+    fn skip_if_eq_registers(&mut self, x: u8, y: u8) {
+        if self.registers[x as usize] == self.registers[y as usize] {
+            self.position_in_memory += 2;
+        }
+    }
+
+    fn load_in_register(&mut self, x: u8, kk: u8) {
+        self.registers[x as usize] = kk;
+    }
+
+    fn add_xkk(&mut self, x: u8, kk: u8) {
+        let arg1 = self.registers[x as usize];
+
+        self.registers[x as usize] = arg1.wrapping_add(kk);
+    }
+
+    fn set_xy(&mut self, x: u8, y: u8) {
+        self.registers[x as usize] = self.registers[y as usize];
+    }
+
+    fn or_xy(&mut self, x: u8, y: u8) {
+        let arg1 = self.registers[x as usize];
+        let arg2 = self.registers[y as usize];
+        self.registers[x as usize] = arg1 | arg2;
+
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn and_xy(&mut self, x: u8, y: u8) {
+        let arg1 = self.registers[x as usize];
+        let arg2 = self.registers[y as usize];
+        self.registers[x as usize] = arg1 & arg2;
+
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn xor_xy(&mut self, x: u8, y: u8) {
+        let arg1 = self.registers[x as usize];
+        let arg2 = self.registers[y as usize];
+        self.registers[x as usize] = arg1 ^ arg2;
+
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn add_xy(&mut self, x: u8, y: u8) {
+        let arg1 = self.registers[x as usize];
+        let arg2 = self.registers[y as usize];
+
+        let (val, overflow) = arg1.overflowing_add(arg2);
+        self.registers[x as usize] = val;
+
+        if overflow {
+            self.registers[0xF] = 1;
+        } else {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn sub_xy(&mut self, x: u8, y: u8) {
+        let arg1 = self.registers[x as usize];
+        let arg2 = self.registers[y as usize];
+        self.registers[x as usize] = arg1.wrapping_sub(arg2);
+
+        if arg1 >= arg2 {
+            self.registers[0xF] = 1;
+        } else {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn shr_xy(&mut self, x: u8, y: u8) {
+        let src = if self.quirks.shift { x } else { y };
+        let val = self.registers[src as usize];
+        self.registers[x as usize] = val >> 1;
+
+        self.registers[0xF] = val & 1;
+    }
+
+    fn subn_xy(&mut self, x: u8, y: u8) {
+        let arg1 = self.registers[x as usize];
+        let arg2 = self.registers[y as usize];
+        self.registers[x as usize] = arg2.wrapping_sub(arg1);
+
+        if arg2 >= arg1 {
+            self.registers[0xF] = 1;
+        } else {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn shl_xy(&mut self, x: u8, y: u8) {
+        let src = if self.quirks.shift { x } else { y };
+        let val = self.registers[src as usize];
+        self.registers[x as usize] = val << 1;
+
+        self.registers[0xF] = val >> 7;
+    }
+
+    fn skip_if_neq_registers(&mut self, x: u8, y: u8) {
+        if self.registers[x as usize] != self.registers[y as usize] {
+            self.position_in_memory += 2;
+        }
+    }
+
+    fn set_i(&mut self, addr: u16) {
+        self.register_i = addr;
+    }
+
+    fn jmp_to_addr_x(&mut self, x: u8, addr: u16) {
+        let offset = if self.quirks.jump { x } else { 0 };
+        self.position_in_memory = (addr + (self.registers[offset as usize] as u16)) as usize;
+    }
+
+    fn set_rand_x(&mut self, x: u8, kk: u8) {
+        self.registers[x as usize] = self.rng.next_u8() & kk;
+    }
+
+    fn skip_if_key(&mut self, x: u8) {
+        let key = self.registers[x as usize] & 0xF;
+        if self.keypad[key as usize] {
+            self.position_in_memory += 2;
+        }
+    }
+
+    fn skip_if_not_key(&mut self, x: u8) {
+        let key = self.registers[x as usize] & 0xF;
+        if !self.keypad[key as usize] {
+            self.position_in_memory += 2;
+        }
+    }
+
+    fn load_delay_timer(&mut self, x: u8) {
+        self.registers[x as usize] = self.delay_timer;
+    }
+
+    fn set_delay_timer(&mut self, x: u8) {
+        self.delay_timer = self.registers[x as usize];
+    }
+
+    fn set_sound_timer(&mut self, x: u8) {
+        self.sound_timer = self.registers[x as usize];
+    }
+
+    fn write_memory(&mut self, addr: usize, value: u8) {
+        let addr = addr % self.memory.len();
+        self.writes.push((addr, self.memory[addr]));
+        self.memory[addr] = value;
+    }
+
+    /// Fx1E, leaving VF alone
+    fn add_i(&mut self, x: u8) {
+        self.register_i = self
+            .register_i
+            .wrapping_add(self.registers[x as usize] as u16);
+    }
+
+    fn load_font(&mut self, x: u8) {
+        let digit = (self.registers[x as usize] & 0xF) as usize;
+        self.register_i = (FONT_START + digit * 5) as u16;
+    }
+
+    fn store_bcd(&mut self, x: u8) {
+        let value = self.registers[x as usize];
+        let i = self.register_i as usize;
+        self.write_memory(i, value / 100);
+        self.write_memory(i + 1, value / 10 % 10);
+        self.write_memory(i + 2, value % 10);
+    }
+
+    /// Fx55, leaving I unchanged like SUPER-CHIP
+    fn store_registers(&mut self, x: u8) {
+        let i = self.register_i as usize;
+        for r in 0..=x as usize {
+            self.write_memory(i + r, self.registers[r]);
+        }
+    }
+
+    /// Fx65, leaving I unchanged like SUPER-CHIP
+    fn load_registers(&mut self, x: u8) {
+        let i = self.register_i as usize;
+        for r in 0..=x as usize {
+            self.registers[r] = self.memory[(i + r) % self.memory.len()];
+        }
+    }
+
+    fn wait_key(&mut self, x: u8) {
+        match self.keypad.iter().position(|&k| k) {
+            Some(key) => self.registers[x as usize] = key as u8,
+            None => self.position_in_memory -= 2,
+        }
+    }
+
+    fn draw(&mut self, ix: u8, iy: u8, n: u8) {
+        let start_x: usize = (self.registers[ix as usize] % 64).into();
+        let start_y: usize = (self.registers[iy as usize] % 32).into();
+        self.registers[0xF] = 0;
+
+        // sprites are clipped at the bottom and right edges
+        for i in 0..(n as usize).min(32 - start_y) {
+            let sprite = self.memory[(self.register_i as usize + i) % self.memory.len()];
+            if self.display.draw_row(start_y + i, start_x, sprite) {
+                self.registers[0xF] = 1;
+            }
+        }
+    }
+}
+
+/// The 64x32 screen, a row per `u64` with the leftmost pixel in the top bit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Display {
+    pub rows: [u64; 32],
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Self { rows: [0; 32] }
+    }
+
+    pub fn pixel(&self, row: usize, col: usize) -> bool {
+        self.rows[row] & (1 << 63 >> col) != 0
+    }
+
+    /// FNV-1a over the pixels, used to compare frames in recorded movies
+    pub fn hash(&self) -> u64 {
+        (0..32)
+            .flat_map(|row| (0..64).map(move |col| (row, col)))
+            .fold(0xcbf29ce484222325, |hash, (row, col)| {
+                (hash ^ self.pixel(row, col) as u64).wrapping_mul(0x100000001b3)
+            })
+    }
+
+    fn clear(&mut self) {
+        self.rows = [0; 32];
+    }
+
+    /// XORs the 8 pixels of `sprite` into `row` starting at `col`, returning
+    /// whether any pixel was turned off
+    fn draw_row(&mut self, row: usize, col: usize, sprite: u8) -> bool {
+        let mask = (sprite as u64) << 56 >> col;
+        let collision = self.rows[row] & mask != 0;
+        self.rows[row] ^= mask;
+        collision
+    }
+}
+
+/// splitmix64, so that a given seed always produces the same `Cxkk` results
+#[derive(Debug, Clone)]
+pub struct Rng {
+    pub state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u8(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        (z ^ (z >> 31)) as u8
+    }
+}
@@ -1,346 +1,1956 @@
 use anyhow::{Context, Result, *};
-use clap::Parser;
-use minifb::{Window, WindowOptions};
+use builtin::BUILTINS;
+use capture::{GifRecorder, VideoRecorder};
+use cheats::Cheat;
+use clap::builder::TypedValueParser;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use config::{Config, RomSettings};
+use control::{ControlServer, Request};
+use coverage::Coverage;
+use cpu::block_cache::BlockCache;
+use cpu::instruction;
+#[cfg(feature = "jit")]
+use cpu::jit::Jit;
+use cpu::reference;
+use cpu::symbols::{self, Symbols};
+use cpu::trace::{self, TraceFormat, Tracer};
+use cpu::{
+    Cpu, Display, Fault, INSTRUCTIONS_PER_FRAME, MAX_PROGRAM_SIZE, PROGRAM_START, Quirks, Rng,
+};
+use crowd::Crowd;
+use debugger::{Action, Debugger};
+use keymap::Keymap;
+use minifb::{Key, KeyRepeat};
+use movie::{Movie, MovieHeader, Player, Recorder};
+use netplay::{Game, Netplay};
+use osd::Osd;
+use pacing::FramePacer;
+use palette::PALETTES;
+use platform::Platform;
+use profile::Profiler;
+use render::{Renderer, Rotation};
+use rewind::Rewind;
+use savestate::StateFormat;
+use script::Script;
+use smc::SmcDetector;
 use std::fs;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use stream::StreamServer;
+use watch::FileWatcher;
+
+mod archive;
+mod asm;
+mod bench;
+mod builtin;
+mod capture;
+mod cfg;
+mod cheats;
+mod condition;
+mod config;
+mod control;
+mod coverage;
+mod crowd;
+mod debugger;
+mod disasm;
+mod final_state;
+mod font;
+mod info;
+mod keymap;
+mod movie;
+mod netplay;
+mod octo_options;
+mod octocart;
+mod osd;
+mod pacing;
+mod palette;
+mod patch;
+mod platform;
+mod profile;
+mod recent;
+mod render;
+mod rewind;
+mod savestate;
+mod screenshot;
+mod script;
+mod smc;
+mod sprites;
+mod stream;
+mod test_roms;
+mod trace_diff;
+mod watch;
 
 #[derive(Parser, Debug)]
 #[command(name = "CHIP8 emulator", about = "A simple chip8 emulator on rust")]
 struct Cli {
-    /// Path to the program (in binary format)
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path or http(s) URL of the program, in binary format or as Octo source (.o8), or - for
+    /// standard input [default: picked in a file dialog]
     #[arg(short, long)]
-    file: std::path::PathBuf,
-}
+    file: Option<std::path::PathBuf>,
+
+    /// Refuse to run the program unless its SHA-256 (after assembling, for Octo source) is this,
+    /// to check downloads
+    #[arg(long, value_name = "HASH")]
+    sha256: Option<String>,
+
+    /// Run one of the last ROMs run in a window, picked from a list in the terminal or by its
+    /// number in that list
+    #[arg(long, value_name = "N", num_args = 0..=1, conflicts_with = "file")]
+    recent: Option<Option<usize>>,
+
+    /// Run a ROM that comes with the emulator: ibm-logo, pong (1/4 and C/D move the paddles) or
+    /// quirks (shows which quirks are on)
+    #[arg(long, value_name = "NAME", value_parser = builtin::parse, conflicts_with_all = ["file", "recent"])]
+    builtin: Option<usize>,
+
+    /// Entry of the zip archive to run [default: the first .ch8, .sc8 or .xo8 file in it]
+    #[arg(long, value_name = "NAME")]
+    entry: Option<String>,
+
+    /// Record keypad input to a movie file
+    #[arg(long, value_name = "MOVIE")]
+    record_input: Option<std::path::PathBuf>,
+
+    /// Play keypad input back from a movie file
+    #[arg(long, value_name = "MOVIE")]
+    play_input: Option<std::path::PathBuf>,
+
+    /// Seed for the random number generator (random by default)
+    #[arg(long, conflicts_with = "play_input")]
+    seed: Option<u64>,
+
+    /// Color palette: default, green-phosphor, amber, gameboy or high-contrast
+    #[arg(long, value_name = "NAME", value_parser = palette::parse)]
+    palette: Option<usize>,
+
+    /// Color of lit pixels, as RRGGBB hex (overrides the palette)
+    #[arg(long, value_name = "COLOR", value_parser = parse_color)]
+    fg: Option<u32>,
+
+    /// Color of unlit pixels, as RRGGBB hex (overrides the palette)
+    #[arg(long, value_name = "COLOR", value_parser = parse_color)]
+    bg: Option<u32>,
+
+    /// Color of the border around the screen, as RRGGBB hex (overrides the palette)
+    #[arg(long, value_name = "COLOR", value_parser = parse_color)]
+    border: Option<u32>,
+
+    /// Let pixels fade out over this many frames to reduce flicker
+    #[arg(long, value_name = "FRAMES", default_value_t = 0)]
+    phosphor: u8,
+
+    /// Start with the CRT filter (scanlines and vignette) enabled
+    #[arg(long)]
+    crt: bool,
+
+    /// Rotate the screen clockwise by this many degrees
+    #[arg(long, value_name = "DEGREES", default_value = "0")]
+    rotate: Rotation,
+
+    /// Rotate the 1-9 keypad block along with the screen
+    #[arg(long)]
+    rotate_keys: bool,
+
+    /// Start with a grid drawn between emulated pixels
+    #[arg(long)]
+    grid: bool,
+
+    /// Size of an emulated pixel in window pixels
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..).map(usize::from))]
+    scale: usize,
+
+    /// Border around the screen in window pixels
+    #[arg(long, value_name = "PIXELS", default_value_t = 30)]
+    padding: usize,
+
+    /// Multiplier for the instructions run per frame, changed with +/- at
+    /// runtime
+    #[arg(long, default_value_t = 1.0, value_parser = parse_speed)]
+    speed: f64,
+
+    /// Start in fullscreen
+    #[arg(long)]
+    fullscreen: bool,
+
+    /// Memory for the states kept for rewinding with Backspace, 0 disables
+    /// it. Rewinding is always off while recording or playing a movie
+    #[arg(long, value_name = "MB", default_value_t = 64)]
+    rewind_buffer: usize,
+
+    /// Save the machine state when the window closes, for `--resume`
+    #[arg(long, conflicts_with_all = ["record_input", "play_input"])]
+    auto_save: bool,
+
+    /// Continue where the last run of this ROM with `--auto-save` or
+    /// `--resume` left off, and save again on exit
+    #[arg(long, conflicts_with_all = ["record_input", "play_input"])]
+    resume: bool,
+
+    /// Format of the states F5, --auto-save and --resume write
+    #[arg(long, value_name = "FORMAT", default_value = "binary")]
+    state_format: StateFormat,
+
+    /// Save slot that F5 and F9 start with, changed with Shift and a digit
+    #[arg(long, value_name = "SLOT", default_value_t = 0, value_parser = clap::value_parser!(u8).range(..savestate::SLOTS as i64))]
+    state_slot: u8,
+
+    /// Use the command line debugger (breakpoints, stepping), starting
+    /// paused unless --break-at is given
+    #[arg(long)]
+    debug: bool,
+
+    /// Run until this address and drop into the debugger there, can be
+    /// given more than once
+    #[arg(long, value_name = "ADDR", value_parser = debugger::parse_addr)]
+    break_at: Vec<usize>,
+
+    /// Start paused in the debugger, also with --break-at
+    #[arg(long)]
+    start_paused: bool,
+
+    /// Write every executed instruction to a file
+    #[arg(long, value_name = "FILE")]
+    trace: Option<std::path::PathBuf>,
+
+    /// Trace as text or as JSON lines
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        requires = "trace"
+    )]
+    trace_format: TraceFormat,
+
+    /// Only trace instructions in an address range, like 0x200..0x300
+    #[arg(long, value_name = "RANGE", requires = "trace", value_parser = debugger::parse_range)]
+    trace_range: Option<std::ops::Range<usize>>,
+
+    /// Count executed instructions per opcode and address, and print a
+    /// report on exit or write it as CSV to FILE
+    #[arg(long, value_name = "FILE", num_args = 0..=1)]
+    profile: Option<Option<std::path::PathBuf>>,
+
+    /// Reset and reload the program when the file (ROM or Octo source) changes on disk
+    #[arg(long, conflicts_with_all = ["record_input", "play_input", "builtin"])]
+    watch: bool,
+
+    /// Warn when the program writes to memory that already ran as code, and pause there in the
+    /// debugger. Instructions are interpreted, like while tracing
+    #[arg(long)]
+    detect_smc: bool,
+
+    /// Write which ROM bytes were executed to a file on exit, as HTML if
+    /// it ends in .html and as text otherwise
+    #[arg(long, value_name = "FILE")]
+    coverage: Option<std::path::PathBuf>,
+
+    /// Record video with sound to a file with ffmpeg (webm, mp4, ...)
+    #[arg(long, value_name = "VIDEO")]
+    record: Option<std::path::PathBuf>,
+
+    /// Compile straight-line code to native code with cranelift
+    /// (experimental, needs a build with `--features jit`). Instructions
+    /// are interpreted while tracing, profiling or measuring coverage
+    #[arg(long, conflicts_with_all = ["debug", "break_at", "start_paused"])]
+    jit: bool,
+
+    /// Decode straight-line code once and replay it from a cache, like
+    /// --jit without compiling
+    #[arg(long, conflicts_with_all = ["jit", "debug", "break_at", "start_paused"])]
+    block_cache: bool,
 
-#[derive(Debug)]
-struct CPU {
-    registers: [u8; 16],
-    register_I: u16,
-    position_in_memory: usize,
-    memory: [u8; 0x1000],
-    stack: [u16; 16],
-    stack_pointer: usize,
-    display: Display,
+    /// Check every frame against a plain reference interpreter and stop at the first difference,
+    /// to test changes to the core, --block-cache and --jit
+    #[arg(long, conflicts_with_all = ["debug", "break_at", "start_paused", "script"])]
+    lockstep: bool,
+
+    /// Run without a window and as fast as possible for SECONDS, then print
+    /// the instructions per second and frame times
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    bench: Option<u64>,
+
+    /// Run without a window for this many frames, save a screenshot and exit
+    #[arg(long, value_name = "N")]
+    screenshot_at_frame: Option<u64>,
+
+    /// Stop once the program halts, with 0000 or a 1NNN jump to itself, exiting with code 0 (a
+    /// fault exits with code 2). Runs without a window unless something needs one, and a
+    /// --screenshot-at-frame screenshot shows the screen at the halt
+    #[arg(long)]
+    exit_on_halt: bool,
+
+    /// Stop after executing N instructions, without a window unless something needs one
+    #[arg(long, value_name = "N")]
+    max_cycles: Option<usize>,
+
+    /// Stop after N frames, without a window unless something needs one
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_frames: Option<u64>,
+
+    /// Apply an IPS or BPS patch to the ROM before running it, can be given more than once.
+    /// verify needs the patches the movie was recorded with
+    #[arg(long, value_name = "FILE", global = true)]
+    patch: Vec<std::path::PathBuf>,
+
+    /// Keep a byte of memory at a value before every frame, like 3A2=05; can be given more than
+    /// once. Cheats in game.cht next to game.ch8 apply too, except in a movie or a two-player
+    /// game, and Ctrl+H in the window turns them off and on
+    #[arg(long, value_name = "ADDR=VALUE", value_parser = cheats::parse, conflicts_with_all = ["record_input", "play_input", "host", "connect"])]
+    cheat: Vec<Cheat>,
+
+    /// Accept JSON commands over WebSocket on localhost:PORT to pause, resume and reset, load
+    /// ROMs, hold keys down and read memory and registers
+    #[arg(long, value_name = "PORT")]
+    control_port: Option<u16>,
+
+    /// Serve the display over HTTP on PORT, to watch it from a browser at http://HOST:PORT/.
+    /// Without a window frames then run in real time
+    #[arg(long, value_name = "PORT")]
+    stream_port: Option<u16>,
+
+    /// Play keys sent by a crowd, a hex digit per line from any connection on PORT, for chat
+    /// bridges
+    #[arg(long, value_name = "PORT", conflicts_with_all = ["play_input", "crowd_irc"])]
+    crowd_port: Option<u16>,
+
+    /// Play keys sent to an IRC channel, like irc.chat.twitch.tv:6667/#channel, a hex digit per
+    /// message
+    #[arg(long, value_name = "HOST:PORT/#CHANNEL", conflicts_with = "play_input")]
+    crowd_irc: Option<String>,
+
+    /// Frames to hold each crowd key down for, and then to let go of it for
+    #[arg(long, value_name = "FRAMES", default_value_t = 6)]
+    crowd_hold: u64,
+
+    /// Host a two-player game on PORT, the other player joins with --connect and each plays
+    /// with their own keys
+    #[arg(long, value_name = "PORT", conflicts_with_all = ["screenshot_at_frame", "bench", "debug", "break_at", "start_paused", "play_input"])]
+    host: Option<u16>,
+
+    /// Join a two-player game hosted at HOST:PORT
+    #[arg(long, value_name = "HOST:PORT", conflicts_with_all = ["host", "screenshot_at_frame", "bench", "debug", "break_at", "start_paused", "play_input"])]
+    connect: Option<String>,
+
+    /// Frames before keys take effect in a two-player game, to give them time to reach the other
+    /// player
+    #[arg(long, value_name = "FRAMES", default_value_t = 4)]
+    input_delay: u64,
+
+    /// Run a Rhai script alongside the program, with callbacks before every frame and after every
+    /// instruction that can read and change the machine and press keys
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["debug", "break_at", "start_paused"])]
+    script: Option<std::path::PathBuf>,
+
+    /// Print the display as text when emulation ends, or write it to FILE (also Ctrl+T in the
+    /// window)
+    #[arg(long, value_name = "FILE", num_args = 0..=1)]
+    dump_display: Option<Option<std::path::PathBuf>>,
+
+    /// Write the registers, I, PC, timers, stack and a hash of the screen as JSON to FILE when
+    /// emulation ends, or to standard output for -
+    #[arg(long, value_name = "FILE")]
+    dump_state: Option<std::path::PathBuf>,
+
+    /// Where --screenshot-at-frame saves to [default: <ROM name>-<frame>.png]
+    #[arg(long, value_name = "FILE", requires = "screenshot_at_frame")]
+    screenshot_file: Option<std::path::PathBuf>,
+
+    /// Size of an emulated pixel in screenshots, GIF recordings and the HTTP stream
+    #[arg(long, value_name = "SCALE", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..).map(usize::from))]
+    screenshot_scale: usize,
+
+    /// Settings for every ROM: palette, quirks, keys and defaults for the display flags
+    /// [default: ~/.config/chip8/config.toml]
+    #[arg(long, value_name = "FILE", global = true)]
+    config: Option<std::path::PathBuf>,
+
+    /// Per-ROM settings database [default: ~/.config/chip8/roms.toml]
+    #[arg(long, value_name = "FILE", global = true)]
+    rom_db: Option<std::path::PathBuf>,
+
+    /// Names for addresses in the trace and the debugger, from a file with a hex address and a
+    /// name per line [default: the ROM with a .sym extension, if there is one]
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<std::path::PathBuf>,
+
+    /// Run with the quirks of this platform instead of the ROM database's or those of the
+    /// platform the file extension stands for or detected from the ROM's opcodes
+    #[arg(long, global = true)]
+    platform: Option<Platform>,
 }
 
-impl CPU {
-    fn read_opcode(&self) -> u16 {
-        let p = self.position_in_memory;
-        let op_byte1 = self.memory[p] as u16;
-        let op_byte2 = self.memory[p + 1] as u16;
+fn parse_color(s: &str) -> Result<u32> {
+    let hex = s.trim_start_matches('#').trim_start_matches("0x");
+    if hex.len() != 6 {
+        return Err(anyhow!("expected 6 hex digits, like `ff8800`"));
+    }
+    u32::from_str_radix(hex, 16).with_context(|| format!("`{}` is not a hex color", s))
+}
 
-        op_byte1 << 8 | op_byte2
+fn parse_speed(s: &str) -> Result<f64> {
+    let speed: f64 = s
+        .parse()
+        .with_context(|| format!("`{}` is not a number", s))?;
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(anyhow!("the speed has to be above 0"));
     }
+    Ok(speed)
+}
 
-    fn run(&mut self) {
-        let mut i = 0;
-        loop {
-            i += 1;
-            if i > 100 {
-                break;
-            }
-            let opcode = self.read_opcode();
-            println!("instruction: {:x}", opcode);
-            self.position_in_memory += 2;
+/// Speed multipliers the speed hotkeys step through
+const SPEEDS: [f64; 10] = [0.25, 0.5, 0.75, 1.0, 1.25, 1.5, 2.0, 3.0, 4.0, 8.0];
+
+/// Instructions per frame for a speed multiplier, at least one
+fn scale_speed(instructions_per_frame: usize, multiplier: f64) -> usize {
+    ((instructions_per_frame as f64 * multiplier).round() as usize).max(1)
+}
 
-            let c = ((opcode >> 12) & 0x000F) as u8;
-            let x = ((opcode >> 8) & 0x000F) as u8;
-            let y = ((opcode >> 4) & 0x000F) as u8;
-            let d = (opcode & 0x000F) as u8;
+/// A fresh machine running `program`, with the speed, quirks and tracing
+/// of `cpu`
+fn restart(cpu: &mut Cpu, program: &[u8], seed: u64) -> Cpu {
+    let mut fresh = Cpu::new(program, seed);
+    fresh.speed = cpu.speed;
+    fresh.quirks = cpu.quirks;
+    fresh.max_cycles = cpu.max_cycles;
+    fresh.halt_on_self_jump = cpu.halt_on_self_jump;
+    fresh.executed = cpu.executed.take();
+    fresh
+}
 
-            let nnn = opcode & 0x0FFF;
-            let kk = (opcode & 0x00FF) as u8;
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Play a movie back without a window and compare every frame with the recording
+    Verify {
+        /// Movie recorded with --record-input
+        movie: std::path::PathBuf,
 
-            match (c, x, y, d) {
-                (0, 0, 0, 0) => {
-                    return;
-                }
-                (0, 0, 0xE, 0) => self.display.clear(),
-                (0, 0, 0xE, 0xE) => self.ret(),
-                (0x1, _, _, _) => self.jmp_to_addr(nnn),
-                (0x2, _, _, _) => self.call(nnn),
-                (0x3, _, _, _) => self.skip_if_eq(x, kk),
-                (0x4, _, _, _) => self.skip_if_neq(x, kk),
-                (0x5, _, _, 0) => self.skip_if_eq_registers(x, y),
-                (0x6, _, _, _) => self.load_in_register(x, kk),
-                (0x7, _, _, _) => self.add_xkk(x, kk),
-                (0x8, _, _, 0x0) => self.set_xy(x, y),
-                (0x8, _, _, 0x1) => self.or_xy(x, y),
-                (0x8, _, _, 0x2) => self.and_xy(x, y),
-                (0x8, _, _, 0x3) => self.xor_xy(x, y),
-                (0x8, _, _, 0x4) => self.add_xy(x, y),
-                (0x8, _, _, 0x5) => self.sub_xy(x, y),
-                (0x8, _, _, 0x6) => self.shr_x(x),
-                (0x8, _, _, 0x7) => self.subn_xy(x, y),
-                (0x8, _, _, 0xE) => self.shl_x(x),
-                (0x9, _, _, 0x0) => self.skip_if_neq_registers(x, y),
-                (0xA, _, _, _) => self.set_I(nnn),
-                (0xB, _, _, _) => self.jmp_to_addr_x(x, nnn),
-                (0xC, _, _, _) => self.set_rand_x(x, kk),
-                (0xD, _, _, _) => self.draw(x, y, d),
-                _ => todo!("opcode: {:04x}", opcode),
-            }
-        }
-    }
+        /// Path to the program, in binary format or as Octo source (.o8)
+        #[arg(short, long)]
+        file: std::path::PathBuf,
+    },
+    /// Assemble an Octo source file into a program
+    Asm {
+        /// Octo source (.o8)
+        source: std::path::PathBuf,
 
-    fn call(&mut self, addr: u16) {
-        let sp = self.stack_pointer;
-        let stack = &mut self.stack;
+        /// Where to write the program, - for standard output [default: the source with a .ch8
+        /// extension]
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
 
-        if sp > stack.len() {
-            panic!("Stack overflow");
-        }
+        /// Also write the labels to a symbol file for the disassembler, the trace and the debugger
+        #[arg(long, value_name = "FILE")]
+        symbols: Option<std::path::PathBuf>,
+    },
+    /// Print a program as assembly, with labels for jump and call targets
+    Disasm {
+        /// Path to the program, in binary format or as Octo source (.o8)
+        rom: std::path::PathBuf,
+
+        /// Names for addresses [default: the ROM with a .sym extension, if there is one]
+        #[arg(long, value_name = "FILE")]
+        symbols: Option<std::path::PathBuf>,
+    },
+    /// Write the control-flow graph of a program as Graphviz, following jumps, calls and skips
+    Cfg {
+        /// Path to the program, in binary format or as Octo source (.o8)
+        rom: std::path::PathBuf,
+
+        /// Where to write the graph [default: standard output]
+        #[arg(long, value_name = "FILE")]
+        dot: Option<std::path::PathBuf>,
+    },
+    /// Print the size, hash and highest address of a program, the SCHIP and XO-CHIP opcodes
+    /// and the quirk-sensitive instructions it uses
+    Info {
+        /// Path to the program, in binary format or as Octo source (.o8)
+        rom: std::path::PathBuf,
+    },
+    /// Save the sprites a program draws as PNGs, found by the `LD I` before each `DRW`
+    Sprites {
+        /// Path to the program, in binary format or as Octo source (.o8)
+        rom: std::path::PathBuf,
+
+        /// Directory to write the sprites to
+        #[arg(long, value_name = "DIR")]
+        out: std::path::PathBuf,
+
+        /// Size of a sprite pixel in image pixels
+        #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..).map(usize::from))]
+        scale: usize,
+    },
+    /// Compare two JSON traces (--trace-format json) and report the first instruction where the
+    /// PC or the registers differ
+    TraceDiff {
+        a: std::path::PathBuf,
+        b: std::path::PathBuf,
+    },
+    /// Run the Timendus CHIP-8 test ROMs without a window under each quirk profile and report
+    /// the results they show
+    TestRoms {
+        /// Test ROMs, or directories of them
+        #[arg(required = true)]
+        roms: Vec<std::path::PathBuf>,
 
-        stack[sp] = self.position_in_memory as u16;
-        self.stack_pointer += 1;
-        self.position_in_memory = addr as usize;
+        /// Frames to run each ROM for before reading the results
+        #[arg(long, default_value_t = 600)]
+        frames: u64,
+
+        /// Test for the combined suite to run, written to 0x1FF (1 IBM logo, 2 corax, 3 flags, 4 quirks),
+        /// with the platform of each quirk profile written to 0x1FE for the quirks test
+        #[arg(long)]
+        select: Option<u8>,
+    },
+    /// Work with savestate files
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum StateCommand {
+    /// Print a savestate of either format as JSON, which F9 also loads
+    Dump {
+        /// Savestate written with F5, --auto-save or --resume
+        state: std::path::PathBuf,
+
+        /// Where to write the JSON [default: standard output]
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+}
+
+/// Measures frames and instructions per second over roughly one second
+struct SpeedMeter {
+    since: Instant,
+    frame: u64,
+    cycles: usize,
+}
+
+impl SpeedMeter {
+    fn new(frame: u64, cycles: usize) -> Self {
+        Self {
+            since: Instant::now(),
+            frame,
+            cycles,
+        }
     }
 
-    fn ret(&mut self) {
-        if self.stack_pointer == 0 {
-            panic!("Stack underflow");
+    /// Frames and instructions per second, once a second has passed since
+    /// the last measurement
+    fn update(&mut self, frame: u64, cycles: usize) -> Option<(f64, f64)> {
+        let elapsed = self.since.elapsed().as_secs_f64();
+        if elapsed < 1.0 {
+            return None;
         }
 
-        self.stack_pointer -= 1;
-        let call_addr = self.stack[self.stack_pointer];
-        self.position_in_memory = call_addr as usize;
-    }
+        let fps = (frame - self.frame) as f64 / elapsed;
+        let ips = (cycles - self.cycles) as f64 / elapsed;
+        *self = Self::new(frame, cycles);
 
-    fn jmp_to_addr(&mut self, addr: u16) {
-        println!("jump to addr: {:x}", addr);
-        self.position_in_memory = addr as usize;
+        Some((fps, ips))
     }
+}
 
-    fn skip_if_eq(&mut self, x: u8, kk: u8) {
-        if self.registers[x as usize] == kk {
-            self.position_in_memory += 2;
-        }
+const DEBUG_KEY: Key = Key::F1;
+const PALETTE_KEY: Key = Key::F2;
+const CRT_KEY: Key = Key::F3;
+const GRID_KEY: Key = Key::F4;
+/// Pressed with Ctrl to switch to another ROM picked in a file dialog
+const OPEN_KEY: Key = Key::O;
+/// Pressed with Ctrl to print the display as text
+const DUMP_DISPLAY_KEY: Key = Key::T;
+/// Pressed with Ctrl to turn the cheats off and back on
+const CHEATS_KEY: Key = Key::H;
+const CTRL_KEYS: [Key; 2] = [Key::LeftCtrl, Key::RightCtrl];
+const SAVE_STATE_KEY: Key = Key::F5;
+const LOAD_STATE_KEY: Key = Key::F9;
+const RESET_KEY: Key = Key::F8;
+/// Held with a digit to pick the save slot
+const STATE_SLOT_MODIFIERS: [Key; 2] = [Key::LeftShift, Key::RightShift];
+const DIGIT_KEYS: [Key; 10] = [
+    Key::Key0,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+    Key::Key8,
+    Key::Key9,
+];
+const GIF_KEY: Key = Key::F10;
+const FULLSCREEN_KEY: Key = Key::F11;
+const SCREENSHOT_KEY: Key = Key::F12;
+const PAUSE_KEY: Key = Key::P;
+/// Held down to run as fast as possible, without frame pacing
+const TURBO_KEY: Key = Key::Tab;
+const SPEED_UP_KEYS: [Key; 2] = [Key::Equal, Key::NumPadPlus];
+const SPEED_DOWN_KEYS: [Key; 2] = [Key::Minus, Key::NumPadMinus];
+const SLOW_MOTION_KEY: Key = Key::F7;
+/// Speed in slow motion, the window still refreshes at full rate
+const SLOW_MOTION: f64 = 0.25;
+/// Runs one frame while paused
+const FRAME_ADVANCE_KEY: Key = Key::F6;
+/// Held down rather than pressed
+const REWIND_KEY: Key = Key::Backspace;
+
+/// Asks for the program in the desktop's file dialog, for when the emulator
+/// is started without `--file`, like from a desktop icon
+fn pick_rom() -> Result<std::path::PathBuf> {
+    rfd::FileDialog::new()
+        .set_title("Open a CHIP-8 program")
+        .add_filter(
+            "CHIP-8 programs",
+            &["ch8", "sc8", "xo8", "o8", "gif", "zip"],
+        )
+        .add_filter("All files", &["*"])
+        .pick_file()
+        .ok_or_else(|| anyhow!("No program given with --file or picked in the file dialog"))
+}
+
+/// The keypad bindings of the ROM's settings
+fn keymap_for(settings: &RomSettings, args: &Cli) -> Result<Keymap> {
+    let mut keymap = Keymap::default();
+    for (keypad_key, host_key) in &settings.keymap {
+        keymap.bind(keypad_key, host_key)?;
     }
+    if args.rotate_keys {
+        keymap.rotate(args.rotate.quarter_turns());
+    }
+    Ok(keymap)
+}
 
-    fn skip_if_neq(&mut self, r: u8, kk: u8) {
-        if self.registers[r as usize] != kk {
-            self.position_in_memory += 2;
-        }
+/// The ROM's name from the database, else its file name
+fn title(settings: &RomSettings, file: &std::path::Path) -> String {
+    match &settings.name {
+        Some(name) => name.clone(),
+        None => file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into(),
     }
+}
 
-    fn skip_if_eq_registers(&mut self, x: u8, y: u8) {
-        if self.registers[x as usize] == self.registers[y as usize] {
-            self.position_in_memory += 2;
-        }
+/// Assembles `source` and writes the program to `output` and the labels to
+/// `symbols`
+fn assemble(
+    source: &std::path::Path,
+    output: Option<&std::path::Path>,
+    symbols: Option<&std::path::Path>,
+) -> Result<()> {
+    let (program, labels) = assemble_file(source)?;
+
+    let output = match output {
+        Some(path) => path.to_path_buf(),
+        None if is_stdio(source) => source.to_path_buf(),
+        None => source.with_extension("ch8"),
+    };
+    if is_stdio(&output) {
+        // keep standard output to the program, for piping it into `cpu -f -`
+        std::io::stdout()
+            .write_all(&program)
+            .context("Couldn't write the program to standard output")?;
+        eprintln!("{} bytes written to standard output", program.len());
+    } else {
+        fs::write(&output, &program)
+            .with_context(|| format!("Couldn't write program `{}`", output.display()))?;
+        println!("{} bytes written to {}", program.len(), output.display());
+    }
+    if let Some(path) = symbols {
+        labels.save(path)?;
     }
+    Ok(())
+}
+
+/// The program and the labels assembled from an Octo source file
+fn assemble_file(source: &std::path::Path) -> Result<(Vec<u8>, Symbols)> {
+    let text = read_file(source)
+        .and_then(|bytes| Ok(String::from_utf8(bytes)?))
+        .with_context(|| format!("Couldn't read source `{}`", source.display()))?;
+    asm::assemble(&text).with_context(|| format!("In `{}`", source.display()))
+}
+
+/// Exit code of a run stopped by a fault, other errors exit with 1
+const FAULT_EXIT_CODE: i32 = 2;
+
+/// Largest download `--file URL` accepts, room for Octo source too
+const MAX_DOWNLOAD_SIZE: u64 = 1024 * 1024;
+
+fn is_url(path: &std::path::Path) -> bool {
+    path.to_str()
+        .is_some_and(|p| p.starts_with("http://") || p.starts_with("https://"))
+}
 
-    fn load_in_register(&mut self, x: u8, kk: u8) {
-        self.registers[x as usize] = kk;
-        println!("load in reg: {}", kk);
-        println!("reg[{}] = {}", x, self.registers[x as usize]);
+/// `-`, which stands for standard input or output
+fn is_stdio(path: &std::path::Path) -> bool {
+    path == std::path::Path::new("-")
+}
+
+/// The contents of a file, downloaded if `path` is an http(s) URL and read
+/// from standard input if it's `-`
+fn read_file(path: &std::path::Path) -> Result<Vec<u8>> {
+    if is_stdio(path) {
+        let mut bytes = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut bytes)?;
+        return Ok(bytes);
+    }
+    if !is_url(path) {
+        return Ok(fs::read(path)?);
     }
+    let url = path.to_string_lossy();
+    eprintln!("Downloading {}", url);
+    let mut response = ureq::get(url.as_ref()).call()?;
+    let bytes = response
+        .body_mut()
+        .with_config()
+        .limit(MAX_DOWNLOAD_SIZE)
+        .read_to_vec()?;
+    Ok(bytes)
+}
 
-    fn add_xkk(&mut self, x: u8, kk: u8) {
-        let arg1 = self.registers[x as usize];
+fn is_octo_source(path: &std::path::Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("o8"))
+}
 
-        self.registers[x as usize] = arg1 + kk;
+/// Symbols from `path`, or from the ROM's .sym file if there is one, or the
+/// labels of an Octo source
+fn load_symbols(path: Option<&std::path::Path>, rom: &std::path::Path) -> Result<Symbols> {
+    match path {
+        Some(path) => Symbols::load(path),
+        None if is_octo_source(rom) => Ok(assemble_file(rom)?.1),
+        None if rom.with_extension("sym").is_file() => Symbols::load(&rom.with_extension("sym")),
+        None => Ok(Symbols::default()),
     }
+}
 
-    fn set_xy(&mut self, x: u8, y: u8) {
-        self.registers[x as usize] = self.registers[y as usize];
+/// A program as read from a file
+struct Rom {
+    program: Vec<u8>,
+    /// The file the program is in, the entry for an archive
+    file: std::path::PathBuf,
+    /// Settings that come with the program, in an Octo cartridge or in
+    /// Octo's options next to it
+    settings: Option<RomSettings>,
+    /// Cheats from the file next to the program
+    cheats: Vec<Cheat>,
+}
+
+/// Reads a program, assembling it first if it's Octo source (.o8) or an
+/// Octo cartridge (.gif), unpacking `entry` or the first ROM if it's a zip
+/// archive. Octo's options are read from `game.json` next to `game.ch8` or
+/// `game.o8`, and cheats from `game.cht`.
+fn read_program(path: &std::path::Path, entry: Option<&str>) -> Result<Rom> {
+    if entry.is_some() && !archive::is_zip(path) {
+        return Err(anyhow!(
+            "`{}` isn't a zip archive to pick an entry from",
+            path.display()
+        ));
     }
+    let mut file = path.to_path_buf();
+    let mut settings = None;
+    let program = if is_octo_source(path) {
+        assemble_file(path)?.0
+    } else if archive::is_zip(path) {
+        let bytes = read_file(path)
+            .with_context(|| format!("Couldn't read archive `{}`", path.display()))?;
+        let (entry, program) =
+            archive::extract(bytes, entry).with_context(|| format!("In `{}`", path.display()))?;
+        // on stderr, to keep the output of disasm and the like clean
+        eprintln!("Running `{}` from `{}`", entry, path.display());
+        file = entry.into();
+        program
+    } else if octocart::is_cartridge(path) {
+        let bytes =
+            read_file(path).with_context(|| format!("Couldn't read `{}`", path.display()))?;
+        let (program, options) =
+            octocart::load(&bytes).with_context(|| format!("In `{}`", path.display()))?;
+        settings = options;
+        program
+    } else {
+        read_file(path).with_context(|| format!("Couldn't read program `{}`", path.display()))?
+    };
 
-    fn or_xy(&mut self, x: u8, y: u8) {
-        let arg1 = self.registers[x as usize];
-        let arg2 = self.registers[y as usize];
-        self.registers[x as usize] = arg1 | arg2;
+    let local = !is_url(path) && !is_stdio(path) && !archive::is_zip(path);
+    if settings.is_none() && local {
+        settings = octo_options::load(path)?;
+        if settings.is_some() {
+            eprintln!(
+                "Using Octo options from `{}`",
+                octo_options::path(path).display()
+            );
+        }
+    }
+    let cheats = if is_url(path) || is_stdio(path) {
+        Vec::new()
+    } else {
+        cheats::load(path)?
+    };
+    if !cheats.is_empty() {
+        eprintln!("Using cheats from `{}`", cheats::path(path).display());
     }
 
-    fn and_xy(&mut self, x: u8, y: u8) {
-        let arg1 = self.registers[x as usize];
-        let arg2 = self.registers[y as usize];
-        self.registers[x as usize] = arg1 & arg2;
+    if program.is_empty() {
+        return Err(anyhow!("Program don't contains code!!!"));
+    }
+    if program.len() > MAX_PROGRAM_SIZE {
+        return Err(anyhow!(
+            "Program is {} bytes, at most {} fit in memory",
+            program.len(),
+            MAX_PROGRAM_SIZE
+        ));
     }
 
-    fn xor_xy(&mut self, x: u8, y: u8) {
-        let arg1 = self.registers[x as usize];
-        let arg2 = self.registers[y as usize];
-        self.registers[x as usize] = arg1 ^ arg2;
+    Ok(Rom {
+        program,
+        file,
+        settings,
+        cheats,
+    })
+}
+
+/// `program` with the IPS or BPS patches applied in order
+fn apply_patches(mut program: Vec<u8>, patches: &[std::path::PathBuf]) -> Result<Vec<u8>> {
+    for path in patches {
+        program = patch::apply_file(path, &program)?;
+        eprintln!("Patched with `{}`", path.display());
+    }
+    if program.len() > MAX_PROGRAM_SIZE {
+        return Err(anyhow!(
+            "Patched program is {} bytes, at most {} fit in memory",
+            program.len(),
+            MAX_PROGRAM_SIZE
+        ));
     }
+    Ok(program)
+}
 
-    fn add_xy(&mut self, x: u8, y: u8) {
-        let arg1 = self.registers[x as usize];
-        let arg2 = self.registers[y as usize];
+/// Stands in for `cpu::jit::Jit` in builds without the `jit` feature
+#[cfg(not(feature = "jit"))]
+enum Jit {}
 
-        let (val, overflow) = arg1.overflowing_add(arg2);
-        self.registers[x as usize] = val;
+#[cfg(not(feature = "jit"))]
+impl Jit {
+    fn new() -> Result<Self> {
+        bail!("--jit needs the emulator built with `--features jit`")
+    }
 
-        if overflow {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
-        }
+    fn run_frame(&mut self, _: &mut Cpu) -> Result<(), Fault> {
+        match *self {}
     }
+}
 
-    fn sub_xy(&mut self, x: u8, y: u8) {
-        let arg1 = self.registers[x as usize];
-        let arg2 = self.registers[y as usize];
-        self.registers[x as usize] = arg1 - arg2;
+/// What runs the instructions outside of the debugger
+enum Engine {
+    Interpreter,
+    BlockCache(BlockCache),
+    Jit(Box<Jit>),
+    /// One of the others, checked against the reference interpreter after
+    /// every frame
+    Lockstep(Box<Engine>),
+}
 
-        if arg1 > arg2 {
-            self.registers[0xF] = 1;
+impl Engine {
+    fn new(args: &Cli) -> Result<Self> {
+        let engine = if args.jit {
+            Self::Jit(Box::new(Jit::new()?))
+        } else if args.block_cache {
+            Self::BlockCache(BlockCache::default())
         } else {
-            self.registers[0xF] = 0;
+            Self::Interpreter
+        };
+        Ok(match args.lockstep {
+            true => Self::Lockstep(Box::new(engine)),
+            false => engine,
+        })
+    }
+
+    fn run_frame(&mut self, cpu: &mut Cpu) -> Result<()> {
+        let Self::Lockstep(engine) = self else {
+            return Ok(self.run(cpu)?);
+        };
+        let before = cpu.clone();
+        let result = engine.run(cpu);
+        if let Err(difference) = reference::check_frame(&before, cpu, result) {
+            bail!(
+                "The core and the reference interpreter disagree: {}",
+                difference
+            );
         }
+        Ok(result?)
     }
 
-    fn shr_x(&mut self, x: u8) {
-        let val_x = self.registers[x as usize];
-        self.registers[x as usize] >>= 1;
+    fn run(&mut self, cpu: &mut Cpu) -> Result<(), Fault> {
+        match self {
+            Self::Interpreter => cpu.run_frame(),
+            Self::BlockCache(cache) => cache.run_frame(cpu),
+            Self::Jit(jit) => jit.run_frame(cpu),
+            Self::Lockstep(engine) => engine.run(cpu),
+        }
+    }
+}
 
-        self.registers[0xF] = val_x & 1;
+/// Runs a frame with `engine`, through the script if there is one
+fn run_frame(
+    engine: &mut Engine,
+    script: &mut Option<Script>,
+    cpu: &mut Cpu,
+    frame: u64,
+) -> Result<()> {
+    match script {
+        Some(script) => script.run_frame(cpu, frame, |cpu| engine.run_frame(cpu)),
+        None => engine.run_frame(cpu),
     }
+}
 
-    fn subn_xy(&mut self, x: u8, y: u8) {
-        let arg1 = self.registers[x as usize];
-        let arg2 = self.registers[y as usize];
-        self.registers[x as usize] = arg2 - arg1;
+fn save_profile(profiler: &Profiler, csv: Option<&std::path::Path>) -> Result<()> {
+    match csv {
+        Some(path) => {
+            profiler.save_csv(path)?;
+            eprintln!("Profile saved to {}", path.display());
+        }
+        None => profiler.print_report(),
+    }
+    Ok(())
+}
 
-        if arg2 > arg1 {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
+fn dump_display(path: Option<&std::path::Path>, display: &Display) -> Result<()> {
+    match path {
+        Some(path) => {
+            screenshot::save_text(path, display)?;
+            if !is_stdio(path) {
+                eprintln!("Display saved to {}", path.display());
+            }
         }
+        None => print!("{}", screenshot::to_text(display)),
     }
+    Ok(())
+}
 
-    fn shl_x(&mut self, x: u8) {
-        let val_x = self.registers[x as usize];
-        self.registers[x as usize] <<= 1;
+/// Whether to run without a window: for a screenshot, or when the run ends
+/// by itself and nothing needs a window
+fn headless(args: &Cli) -> bool {
+    let ends_by_itself =
+        args.max_frames.is_some() || args.max_cycles.is_some() || args.exit_on_halt;
+    let needs_window = args.debug
+        || args.start_paused
+        || !args.break_at.is_empty()
+        || args.host.is_some()
+        || args.connect.is_some()
+        || args.record.is_some()
+        || args.control_port.is_some();
+    args.screenshot_at_frame.is_some() || (ends_by_itself && !needs_window)
+}
 
-        self.registers[0xF] = val_x >> 7;
+/// Why the run should end after `frames` frames, if it should
+fn stop_reason(args: &Cli, cpu: &Cpu, frames: u64) -> Option<String> {
+    if args.exit_on_halt && cpu.halted {
+        Some(format!(
+            "Program halted at frame {}",
+            frames.saturating_sub(1)
+        ))
+    } else if cpu.cycles >= cpu.max_cycles {
+        Some(format!("Stopped after {} instructions", cpu.cycles))
+    } else if args.max_frames.is_some_and(|max| frames >= max) {
+        Some(format!("Stopped after {} frames", frames))
+    } else {
+        None
     }
+}
 
-    fn skip_if_neq_registers(&mut self, x: u8, y: u8) {
-        if self.registers[x as usize] != self.registers[y as usize] {
-            self.position_in_memory += 2;
-        }
+fn dump_state(path: &std::path::Path, cpu: &Cpu, frames: u64) -> Result<()> {
+    final_state::write(path, cpu, frames)?;
+    if !is_stdio(path) {
+        eprintln!("State saved to {}", path.display());
+    }
+    Ok(())
+}
+
+/// The settings that came with the program, completed from the ROM
+/// database and then from the config
+fn rom_settings(
+    rom_db: Option<&std::path::Path>,
+    config: &Config,
+    program: &[u8],
+    file_settings: Option<RomSettings>,
+) -> Result<RomSettings> {
+    let rom_hash = movie::rom_hash(program);
+    let user = match rom_db.map(Into::into).or_else(config::default_rom_db) {
+        Some(path) => config::load_rom_settings(&path, &rom_hash)?,
+        None => None,
+    };
+    let db = match (user, config::builtin_rom_settings(&rom_hash)) {
+        (Some(user), Some(builtin)) => user.with_fallback(builtin),
+        (user, builtin) => user.or(builtin).unwrap_or_default(),
+    };
+    let mut settings = match file_settings {
+        Some(file_settings) => file_settings.with_fallback(db),
+        None => db,
+    };
+    if let Some(name) = &settings.name {
+        eprintln!("Using settings for {}", name);
     }
 
-    fn set_I(&mut self, addr: u16) {
-        self.register_I = addr;
+    config.fill(&mut settings);
+    Ok(settings)
+}
+
+/// The quirks of `--platform`, else those from the ROM database or the
+/// config, else those of the ROM database's platform, of the platform the
+/// extension of `name` stands for or of the platform the program's opcodes
+/// need
+fn select_quirks(
+    platform: Option<Platform>,
+    settings: &RomSettings,
+    program: &[u8],
+    name: &std::path::Path,
+) -> Quirks {
+    if let Some(platform) = platform {
+        return platform.quirks();
+    }
+    if let Some(quirks) = settings.quirks {
+        return quirks;
     }
+    if let Some(platform) = settings.platform {
+        return platform.quirks();
+    }
+    if let Some(platform) = Platform::from_extension(name) {
+        eprintln!(
+            "Using {} quirks for the .{} extension",
+            platform,
+            name.extension().unwrap_or_default().to_string_lossy()
+        );
+        return platform.quirks();
+    }
+    let platform = Platform::detect(program);
+    eprintln!("Using {} quirks for the opcodes the program has", platform);
+    platform.quirks()
+}
 
-    fn jmp_to_addr_x(&mut self, x: u8, addr: u16) {
-        self.position_in_memory = (addr + (self.registers[x as usize] as u16)) as usize;
+fn verify(
+    file: &std::path::Path,
+    movie: &std::path::Path,
+    patches: &[std::path::PathBuf],
+    rom_db: Option<&std::path::Path>,
+    config: &Config,
+    platform: Option<Platform>,
+) -> Result<()> {
+    let Rom {
+        program,
+        file: program_file,
+        settings,
+        ..
+    } = read_program(file, None)?;
+    // by the ROM as released, as for the run that recorded the movie
+    let settings = rom_settings(rom_db, config, &program, settings)?;
+    let program = apply_patches(program, patches)?;
+    let mut movie = Movie::load(movie)?;
+
+    if movie.hashes.is_empty() {
+        return Err(anyhow!("Movie doesn't contain frame hashes"));
+    }
+    if movie.header.rom_hash != movie::rom_hash(&program) {
+        eprintln!("Warning: movie was recorded with a different ROM");
     }
 
-    fn set_rand_x(&mut self, x: u8, kk: u8) {
-        self.registers[x as usize] = 1 & kk;
+    let mut cpu = Cpu::new(&program, movie.header.seed);
+    cpu.speed = movie
+        .header
+        .speed
+        .or(settings.speed)
+        .unwrap_or(INSTRUCTIONS_PER_FRAME);
+    cpu.quirks = match movie.header.quirks {
+        Some(quirks) => quirks,
+        None => select_quirks(platform, &settings, &program, &program_file),
+    };
+    let hashes = std::mem::take(&mut movie.hashes);
+    let mut player = Player::new(movie);
+
+    for (frame, &expected) in hashes.iter().enumerate() {
+        let frame = frame as u64;
+        if let Some(keypad) = player.keypad(frame) {
+            cpu.keypad = keypad;
+        }
+        cpu.run_frame()?;
+
+        let actual = cpu.display.hash();
+        if actual != expected {
+            return Err(anyhow!(
+                "Frame {} diverges: expected hash {:016x}, got {:016x}",
+                frame,
+                expected,
+                actual
+            ));
+        }
     }
 
-    fn draw(&mut self, ix: u8, iy: u8, n: u8) {
-        println!("start draw");
-        let start_x: usize = (self.registers[ix as usize] % 64).into();
-        let start_y: usize = (self.registers[iy as usize] % 32).into();
-        println!("x: {}, y: {}", start_x, start_y);
-        self.registers[0xF] = 0;
+    println!("Movie verified: {} frames match", hashes.len());
 
-        let pixels = &mut self.display.pixels;
+    Ok(())
+}
 
-        for i in 0..n as usize {
-            let y: usize = start_y + i;
-            let sprite = self.memory[(self.register_I + i as u16) as usize];
-            for j in 0..8 {
-                let x: usize = start_x + j;
-                let p = sprite & (1 << (7 - j));
-                println!("bit: {} | {:b} | {:b}", j, sprite, p);
-                if p > 0 && pixels[y][x] {
-                    pixels[y][x] = false;
-                    self.registers[0xF] = 1;
-                } else if (p == 0 && pixels[y][x]) || (p > 0 && !pixels[y][x])  {
-                    pixels[y][x] = true;
+fn main() -> Result<()> {
+    // the config supplies defaults for other flags, so --config is read first
+    let early = Cli::command().ignore_errors(true).get_matches();
+    let config = config::load_config(
+        early
+            .get_one::<std::path::PathBuf>("config")
+            .map(|path| path.as_path()),
+    )?;
+    let mut command = Cli::command();
+    for (id, value) in config.flag_defaults() {
+        command = command.mut_arg(id, |arg| arg.default_value(value));
+    }
+    let args = Cli::from_arg_matches(&command.get_matches()).unwrap_or_else(|e| e.exit());
+
+    match &args.command {
+        Some(Command::Verify { movie, file }) => verify(
+            file,
+            movie,
+            &args.patch,
+            args.rom_db.as_deref(),
+            &config,
+            args.platform,
+        ),
+        Some(Command::Asm {
+            source,
+            output,
+            symbols,
+        }) => assemble(source, output.as_deref(), symbols.as_deref()),
+        Some(Command::Disasm { rom, symbols }) => {
+            let symbols = load_symbols(symbols.as_deref(), rom)?;
+            print!(
+                "{}",
+                disasm::disassemble(&read_program(rom, None)?.program, &symbols)
+            );
+            Ok(())
+        }
+        Some(Command::Cfg { rom, dot }) => {
+            let graph = cfg::to_dot(&read_program(rom, None)?.program);
+            match dot {
+                Some(path) => fs::write(path, graph)
+                    .with_context(|| format!("Couldn't write `{}`", path.display())),
+                None => {
+                    print!("{}", graph);
+                    Ok(())
+                }
+            }
+        }
+        Some(Command::Info { rom }) => {
+            print!("{}", info::report(&read_program(rom, None)?.program));
+            Ok(())
+        }
+        Some(Command::Sprites { rom, out, scale }) => {
+            sprites::export(&read_program(rom, None)?.program, out, *scale)
+        }
+        Some(Command::TestRoms {
+            roms,
+            frames,
+            select,
+        }) => test_roms::run(roms, *frames, *select),
+        Some(Command::TraceDiff { a, b }) => trace_diff::run(a, b),
+        Some(Command::State {
+            command: StateCommand::Dump { state, output },
+        }) => {
+            let json = savestate::dump(state)?;
+            match output {
+                Some(path) => fs::write(path, json + "\n")
+                    .with_context(|| format!("Couldn't write `{}`", path.display())),
+                None => {
+                    println!("{}", json);
+                    Ok(())
                 }
             }
         }
+        None => match run(args, &config) {
+            // scripts tell a program that crashed from one that didn't start
+            Err(e) if e.downcast_ref::<Fault>().is_some() => {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(FAULT_EXIT_CODE)
+            }
+            result => result,
+        },
     }
 }
 
-#[derive(Debug)]
-struct Display {
-    pixels: [[bool; 64]; 32],
-}
+fn run(args: Cli, config: &Config) -> Result<()> {
+    let builtin = args.builtin.map(|index| &BUILTINS[index]);
+    let mut file = match (&args.file, args.recent, builtin) {
+        (Some(file), _, _) => file.clone(),
+        (None, Some(choice), _) => recent::pick(choice)?,
+        (None, None, Some(builtin)) => std::path::PathBuf::from(builtin.name),
+        (None, None, None) => pick_rom()?,
+    };
+    let rom = match builtin {
+        Some(builtin) => Rom {
+            program: builtin.program()?,
+            file: file.clone(),
+            settings: None,
+            cheats: Vec::new(),
+        },
+        None => read_program(&file, args.entry.as_deref())?,
+    };
+    let Rom {
+        mut program,
+        file: program_file,
+        settings,
+        cheats: rom_cheats,
+    } = rom;
+    // loading a state or another ROM in the middle of a movie or a
+    // two-player game would desync it, and so would cheats writing memory
+    let in_movie = args.record_input.is_some()
+        || args.play_input.is_some()
+        || args.host.is_some()
+        || args.connect.is_some();
+    if in_movie && !rom_cheats.is_empty() {
+        eprintln!("Leaving the cheats off in a movie or a two-player game");
+    }
+    let mut cheats = if in_movie {
+        Vec::new()
+    } else {
+        [args.cheat.as_slice(), &rom_cheats].concat()
+    };
+    let mut cheats_on = true;
+    if let Some(expected) = &args.sha256
+        && !movie::rom_hash(&program).eq_ignore_ascii_case(expected)
+    {
+        return Err(anyhow!(
+            "The SHA-256 of `{}` is {}, not {}",
+            file.display(),
+            movie::rom_hash(&program),
+            expected
+        ));
+    }
+    // by the ROM as released, which the database knows
+    let mut settings = rom_settings(args.rom_db.as_deref(), config, &program, settings)?;
+    program = apply_patches(program, &args.patch)?;
+    let symbols = load_symbols(args.symbols.as_deref(), &file)?;
 
-impl Display {
-    fn new() -> Self {
-        Self {
-            pixels: [[false; 64]; 32],
-        }
+    let movie = args.play_input.as_deref().map(Movie::load).transpose()?;
+    if let Some(movie) = &movie
+        && movie.header.rom_hash != movie::rom_hash(&program)
+    {
+        eprintln!("Warning: movie was recorded with a different ROM, playback may desync");
     }
 
-    fn clear(&mut self) {
-        // self.pixels
-        // .iter_mut()
-        //.for_each(|r| r.iter_mut().for_each(|v| *v = false));
+    let seed = movie.as_ref().map(|m| m.header.seed).or(args.seed);
+    let seed = seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default()
+    });
+
+    let mut base_speed = settings.speed.unwrap_or(INSTRUCTIONS_PER_FRAME);
+    let mut speed = args.speed;
+    let mut cpu = Cpu::new(&program, seed);
+    cpu.speed = scale_speed(base_speed, speed);
+    cpu.quirks = select_quirks(args.platform, &settings, &program, &program_file);
+    // a movie replays at the speed and with the quirks it was recorded with
+    if let Some(movie) = &movie {
+        cpu.speed = movie.header.speed.unwrap_or(cpu.speed);
+        cpu.quirks = movie.header.quirks.unwrap_or(cpu.quirks);
     }
-}
+    cpu.halt_on_self_jump = args.exit_on_halt;
+    cpu.max_cycles = args.max_cycles.unwrap_or(usize::MAX);
 
-const BASE_WIDTH: usize = 640;
-const BASE_HEIGHT: usize = 320;
-const PADDING: usize = 30;
-const WIDTH: usize = PADDING + BASE_WIDTH + PADDING;
-const HEIGHT: usize = PADDING + BASE_HEIGHT + PADDING;
+    let mut engine = Engine::new(&args)?;
+    let mut script = args
+        .script
+        .as_deref()
+        .map(|path| Script::load(path, &mut cpu))
+        .transpose()?;
 
-fn main() -> Result<()> {
-    let args = Cli::parse();
+    if let Some(seconds) = args.bench {
+        bench::run(&mut cpu, Duration::from_secs(seconds), |cpu| {
+            engine.run_frame(cpu)
+        })?;
+        return Ok(());
+    }
 
-    let program = fs::read(&args.file)
-        .with_context(|| format!("Couldn't read program `{}`", &args.file.display()))?;
-    let program_len = program.len();
+    let debugging = args.debug || args.start_paused || !args.break_at.is_empty();
+    let mut tracer = args
+        .trace
+        .as_deref()
+        .map(|path| {
+            Tracer::create(
+                path,
+                args.trace_format,
+                args.trace_range.clone(),
+                symbols.clone(),
+            )
+        })
+        .transpose()?;
+    let mut profiler = args.profile.is_some().then(Profiler::default);
+    let mut coverage = args
+        .coverage
+        .is_some()
+        .then(|| Coverage::new(&program, PROGRAM_START));
+    let mut smc = (args.detect_smc && !debugging).then(SmcDetector::default);
+    if tracer.is_some() || profiler.is_some() || coverage.is_some() || smc.is_some() {
+        cpu.executed = Some(Vec::new());
+    }
 
-    if program_len == 0 {
-        return Err(anyhow!("Program don't contains code!!!"));
+    let mut keymap = keymap_for(&settings, &args)?;
+
+    let mut recorder = match &args.record_input {
+        Some(path) => {
+            let header = MovieHeader {
+                rom_hash: movie::rom_hash(&program),
+                seed,
+                speed: Some(cpu.speed),
+                quirks: Some(cpu.quirks),
+            };
+            Some(Recorder::create(path, &header)?)
+        }
+        None => None,
+    };
+
+    let mut player = movie.map(Player::new);
+
+    let palette = settings
+        .palette
+        .as_deref()
+        .map(palette::parse)
+        .transpose()?;
+    let mut palette_index = args.palette.or(palette).unwrap_or(0);
+    let mut colors = PALETTES[palette_index];
+    colors.pixels[0] = args.bg.or(settings.bg).unwrap_or(colors.bg());
+    colors.pixels[1] = args.fg.or(settings.fg).unwrap_or(colors.fg());
+    colors.border = args.border.unwrap_or(colors.border);
+
+    let stream = args
+        .stream_port
+        .map(|port| StreamServer::start(port, colors, args.screenshot_scale))
+        .transpose()?;
+    if let Some(port) = args.stream_port {
+        eprintln!("Streaming the display on http://localhost:{}/", port);
     }
+    let mut crowd = match (args.crowd_port, &args.crowd_irc) {
+        (Some(port), _) => {
+            let crowd = Crowd::listen(port, args.crowd_hold)?;
+            eprintln!("Taking crowd keys on port {}", port);
+            Some(crowd)
+        }
+        (None, Some(spec)) => {
+            let crowd = Crowd::irc(spec, args.crowd_hold)?;
+            eprintln!("Taking crowd keys from {}", spec);
+            Some(crowd)
+        }
+        (None, None) => None,
+    };
 
-    let mut cpu = CPU {
-        registers: [0; 16],
-        register_I: 0,
-        memory: [0; 4096],
-        position_in_memory: 512,
-        stack: [0; 16],
-        stack_pointer: 0,
-        display: Display::new(),
+    if headless(&args) {
+        // without a screenshot to take only a stop reason ends the run
+        let frames = args.screenshot_at_frame.unwrap_or(u64::MAX);
+        let mut ran = frames;
+        // a fault still leaves the dumps and reports behind, for a look at
+        // what went wrong
+        let mut fault = None;
+        // a stream is watched as it happens, not at full speed
+        let mut pacer = stream.is_some().then(FramePacer::new);
+        for frame in 0..frames {
+            if let Some(keypad) = player.as_mut().and_then(|p| p.keypad(frame)) {
+                cpu.keypad = keypad;
+            }
+            if let Some(crowd) = &mut crowd {
+                cpu.keypad = crowd.keys();
+            }
+            cheats::apply(&cheats, &mut cpu);
+            fault = run_frame(&mut engine, &mut script, &mut cpu, frame).err();
+            let executed = cpu.take_executed();
+            if let Some(tracer) = &mut tracer {
+                tracer.write(&executed)?;
+            }
+            if let Some(profiler) = &mut profiler {
+                profiler.add(&executed);
+            }
+            if let Some(coverage) = &mut coverage {
+                coverage.add(&executed);
+            }
+            if let Some(smc) = &mut smc {
+                smc.check(&executed);
+            }
+            if fault.is_some() {
+                ran = frame;
+                break;
+            }
+            if let Some(recorder) = &mut recorder {
+                recorder.record(frame, &cpu.keypad, cpu.display.hash())?;
+            }
+            if let Some(stream) = &stream {
+                stream.publish(&cpu.display, &colors);
+            }
+            if let Some(pacer) = &mut pacer {
+                pacer.wait();
+            }
+            if let Some(reason) = stop_reason(&args, &cpu, frame + 1) {
+                eprintln!("{}", reason);
+                ran = frame + 1;
+                break;
+            }
+        }
+        if let Some(recorder) = recorder {
+            recorder.finish(ran)?;
+        }
+        if let Some(tracer) = tracer {
+            tracer.finish()?;
+        }
+        if let Some(profiler) = &profiler {
+            save_profile(profiler, args.profile.flatten().as_deref())?;
+        }
+        if let (Some(coverage), Some(path)) = (&coverage, &args.coverage) {
+            coverage.save(path)?;
+            eprintln!("Coverage saved to {}", path.display());
+        }
+
+        if let Some(frames) = args.screenshot_at_frame {
+            let path = args
+                .screenshot_file
+                .unwrap_or_else(|| screenshot::default_path(&file, frames));
+            screenshot::save_png(&path, &cpu.display, &colors, args.screenshot_scale)?;
+            eprintln!("Screenshot saved to {}", path.display());
+        }
+        if let Some(path) = &args.dump_state {
+            dump_state(path, &cpu, ran)?;
+        }
+        if let Some(path) = &args.dump_display {
+            dump_display(path.as_deref(), &cpu.display)?;
+        }
+
+        return match fault {
+            Some(fault) => Err(fault),
+            None => Ok(()),
+        };
+    }
+
+    if builtin.is_none() {
+        recent::remember(&file);
+    }
+    let mut renderer = Renderer::new(args.phosphor);
+    renderer.crt = args.crt;
+    renderer.grid = args.grid;
+    renderer.rotation = args.rotate;
+    renderer.scale = args.scale;
+    renderer.padding = args.padding;
+
+    let (width, height) = renderer.window_size();
+    let mut buffer: Vec<u32> = vec![0; width * height];
+    let mut fullscreen = args.fullscreen;
+    let mut window = render::open_window(fullscreen, width, height)?;
+
+    let mut gif: Option<GifRecorder> = None;
+    let mut video = args
+        .record
+        .as_deref()
+        .map(|path| VideoRecorder::create(path, &colors, args.scale))
+        .transpose()?;
+
+    let mut rom_name = title(&settings, &file);
+    window.set_title(&format!("CHIP8 - {}", rom_name));
+    let mut meter = SpeedMeter::new(0, cpu.cycles);
+    let mut osd = Osd::default();
+    let mut show_debug = false;
+    let paused = args.start_paused || (args.debug && args.break_at.is_empty());
+    let mut debugger =
+        debugging.then(|| Debugger::new(&cpu, &args.break_at, paused, symbols, args.detect_smc));
+    let game = Game {
+        rom_hash: movie::rom_hash(&program),
+        speed: cpu.speed,
+        quirks: cpu.quirks,
+    };
+    // both players run the host's seed
+    let (mut netplay, seed) = match (args.host, &args.connect) {
+        (Some(port), _) => (
+            Some(Netplay::host(port, &game, seed, args.input_delay)?),
+            seed,
+        ),
+        (None, Some(addr)) => {
+            let (netplay, seed) = Netplay::connect(addr, &game, args.input_delay)?;
+            cpu.rng = Rng::new(seed);
+            (Some(netplay), seed)
+        }
+        (None, None) => (None, seed),
     };
 
-    let mem = &mut cpu.memory;
-
-    mem[512..512 + program_len].copy_from_slice(&program);
-
-    cpu.run();
-
-    let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
-    let mut window = Window::new("CHIP8", WIDTH, HEIGHT, WindowOptions::default())
-        .with_context(|| "Couldn't create window".to_string())?;
-
-    window.set_target_fps(60);
-
-    while window.is_open() && !window.is_key_down(minifb::Key::Escape) {
-        for (i, p) in buffer.iter_mut().enumerate() {
-            let row = i / WIDTH;
-            let col = i % WIDTH + 1;
-            let inner_row: i32 = row as i32 - 30;
-            let inner_col: i32 = col as i32 - 30;
-            let virtual_row = inner_row / 10;
-            let virtual_col = inner_col / 10;
-            if row < PADDING || row >= PADDING + BASE_HEIGHT {
-                *p = 0x252429;
-            } else if col < PADDING || col >= PADDING + BASE_WIDTH {
-                *p = 0x252429;
-            } else if virtual_row < 32
-                && virtual_col < 64
-                && cpu.display.pixels[virtual_row as usize][virtual_col as usize]
-            {
-                *p = 0xFFFFFF;
+    let mut rewind = (args.record_input.is_none() && player.is_none() && netplay.is_none())
+        .then(|| Rewind::new(args.rewind_buffer * 1024 * 1024));
+
+    let mut watcher = args.watch.then(|| FileWatcher::new(&file)).transpose()?;
+    let mut state_slot = args.state_slot;
+    let mut rom_hash = movie::rom_hash(&program);
+    if args.resume {
+        match savestate::resume_path(&rom_hash) {
+            Some(path) if path.exists() => match savestate::load(&path, &mut cpu, &rom_hash) {
+                Err(e) => eprintln!("Warning: couldn't resume, starting over: {:#}", e),
+                Result::Ok(()) => eprintln!("Resuming from {}", path.display()),
+            },
+            _ => eprintln!("No saved session for this ROM, starting over"),
+        }
+    }
+
+    let mut paused = false;
+    let mut pacer = FramePacer::new();
+    // how far into the next emulated frame slow motion is
+    let mut slow_motion: Option<f64> = None;
+    let mut frame: u64 = 0;
+    // set by the reset key, the debugger's `reset` command or a control client
+    let mut reset = false;
+    // a fault ends the run, after the dumps and reports are written
+    let mut fault = None;
+    let mut control = args.control_port.map(ControlServer::start).transpose()?;
+    if let Some(port) = args.control_port {
+        eprintln!("Control API listening on ws://127.0.0.1:{}", port);
+    }
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        // the program to start over with, the same after a change or another one
+        let mut load = None;
+        if let Some(control) = &mut control {
+            control.poll(|request| {
+                match request {
+                    Request::Pause => paused = true,
+                    Request::Resume => paused = false,
+                    Request::Reset if in_movie => bail!("Can't reset in the middle of a movie"),
+                    Request::Reset => reset = true,
+                    Request::Load { .. } if in_movie => {
+                        bail!("Can't load another ROM in the middle of a movie")
+                    }
+                    Request::Load { path } => {
+                        if !path.exists() && !is_url(&path) {
+                            bail!("There's no `{}`", path.display());
+                        }
+                        load = Some(path);
+                    }
+                    Request::Read { addr, len } => {
+                        let bytes = addr
+                            .checked_add(len)
+                            .and_then(|end| cpu.memory.get(addr..end))
+                            .ok_or_else(|| anyhow!("{:X}+{} is outside of memory", addr, len))?;
+                        return Result::Ok(serde_json::json!({ "bytes": bytes }));
+                    }
+                    Request::State => return Result::Ok(final_state::to_value(&cpu, frame)),
+                }
+                Result::Ok(serde_json::Value::Null)
+            });
+        }
+        if watcher.as_ref().is_some_and(FileWatcher::changed) {
+            load = Some(file.clone());
+        }
+        if !in_movie
+            && CTRL_KEYS.iter().any(|&key| window.is_key_down(key))
+            && window.is_key_pressed(OPEN_KEY, KeyRepeat::No)
+        {
+            match pick_rom() {
+                Err(e) => eprintln!("{:#}", e),
+                Result::Ok(path) => load = Some(path),
+            }
+        }
+        if let Some(path) = load {
+            // only the ROM given on the command line was picked from an archive with --entry
+            let entry = args.entry.as_deref().filter(|_| path == file);
+            let patches = if path == file { &args.patch[..] } else { &[] };
+            let rom = read_program(&path, entry).and_then(|rom| {
+                Ok(Rom {
+                    program: apply_patches(rom.program, patches)?,
+                    ..rom
+                })
+            });
+            match rom {
+                Err(e) => {
+                    eprintln!("Couldn't load: {:#}", e);
+                    osd.show("Loading failed");
+                }
+                Result::Ok(rom) => {
+                    // the cheats on the command line are for the ROM given there
+                    cheats = if in_movie {
+                        Vec::new()
+                    } else if path == file {
+                        [args.cheat.as_slice(), &rom.cheats].concat()
+                    } else {
+                        rom.cheats
+                    };
+                    if path != file {
+                        settings = rom_settings(
+                            args.rom_db.as_deref(),
+                            config,
+                            &rom.program,
+                            rom.settings,
+                        )?;
+                        base_speed = settings.speed.unwrap_or(INSTRUCTIONS_PER_FRAME);
+                        keymap = keymap_for(&settings, &args)?;
+                        rom_name = title(&settings, &path);
+                        window.set_title(&format!("CHIP8 - {}", rom_name));
+                        if watcher.is_some() {
+                            watcher = Some(FileWatcher::new(&path)?);
+                        }
+                        recent::remember(&path);
+                        file = path;
+                    }
+                    // restart() keeps the quirks
+                    cpu.quirks = select_quirks(args.platform, &settings, &rom.program, &rom.file);
+                    program = rom.program;
+                    rom_hash = movie::rom_hash(&program);
+                    reset = true;
+                    eprintln!("Loaded {}", file.display());
+                    osd.show(format!("Loaded {}", rom_name));
+                }
+            }
+        } else if !in_movie && window.is_key_pressed(RESET_KEY, KeyRepeat::No) {
+            reset = true;
+            osd.show("Reset");
+        }
+        if reset && in_movie {
+            eprintln!("Can't reset in the middle of a movie");
+        } else if reset {
+            // from the copy of the program kept since loading it, which
+            // the program may have changed in memory
+            cpu = restart(&mut cpu, &program, seed);
+            cpu.speed = scale_speed(base_speed, speed);
+            engine = Engine::new(&args)?;
+            if let Some(debugger) = &mut debugger {
+                debugger.restarted(&cpu);
+            }
+            if rewind.is_some() {
+                rewind = Some(Rewind::new(args.rewind_buffer * 1024 * 1024));
+            }
+        }
+        reset = false;
+        if window.is_key_pressed(PAUSE_KEY, KeyRepeat::No) {
+            paused = !paused;
+        }
+        if window.is_key_pressed(SLOW_MOTION_KEY, KeyRepeat::No) {
+            slow_motion = match slow_motion {
+                Some(_) => {
+                    osd.show("Slow motion off");
+                    None
+                }
+                None => {
+                    osd.show(format!("Slow motion {}x", SLOW_MOTION));
+                    Some(0.0)
+                }
+            };
+        }
+        let faster = SPEED_UP_KEYS
+            .iter()
+            .any(|&key| window.is_key_pressed(key, KeyRepeat::Yes));
+        let slower = SPEED_DOWN_KEYS
+            .iter()
+            .any(|&key| window.is_key_pressed(key, KeyRepeat::Yes));
+        if (faster || slower) && in_movie {
+            osd.show("Can't change the speed in a movie");
+        } else if faster || slower {
+            let next = if faster {
+                SPEEDS.iter().copied().find(|&s| s > speed)
+            } else {
+                SPEEDS.iter().copied().rev().find(|&s| s < speed)
+            };
+            speed = next.unwrap_or(speed);
+            cpu.speed = scale_speed(base_speed, speed);
+            osd.show(format!("Speed {}x", speed));
+        }
+        if window.is_key_pressed(PALETTE_KEY, KeyRepeat::No) {
+            palette_index = (palette_index + 1) % PALETTES.len();
+            colors = PALETTES[palette_index];
+            osd.show(format!("Palette: {}", colors.name));
+        }
+        if window.is_key_pressed(CRT_KEY, KeyRepeat::No) {
+            renderer.crt = !renderer.crt;
+            osd.show(if renderer.crt { "CRT on" } else { "CRT off" });
+        }
+        if window.is_key_pressed(GRID_KEY, KeyRepeat::No) {
+            renderer.grid = !renderer.grid;
+            osd.show(if renderer.grid { "Grid on" } else { "Grid off" });
+        }
+        let picking_slot = !in_movie
+            && STATE_SLOT_MODIFIERS
+                .iter()
+                .any(|&key| window.is_key_down(key));
+        if picking_slot
+            && let Some(slot) = DIGIT_KEYS
+                .iter()
+                .position(|&key| window.is_key_pressed(key, KeyRepeat::No))
+        {
+            state_slot = slot as u8;
+            osd.show(format!("Slot {}", state_slot));
+        }
+        let state_path = savestate::path(&file, state_slot);
+        if !in_movie && window.is_key_pressed(SAVE_STATE_KEY, KeyRepeat::No) {
+            match savestate::save(&state_path, &cpu, &rom_hash, args.state_format) {
+                Err(e) => {
+                    eprintln!("{:#}", e);
+                    osd.show(format!("Couldn't save slot {}", state_slot));
+                }
+                Result::Ok(()) => {
+                    eprintln!("State saved to {}", state_path.display());
+                    osd.show(format!("Saved slot {}", state_slot));
+                }
+            }
+        }
+        if !in_movie && window.is_key_pressed(LOAD_STATE_KEY, KeyRepeat::No) {
+            match savestate::load(&state_path, &mut cpu, &rom_hash) {
+                Err(e) => {
+                    eprintln!("{:#}", e);
+                    osd.show(format!("Couldn't load slot {}", state_slot));
+                }
+                Result::Ok(()) => {
+                    // cached blocks may not match the loaded memory
+                    engine = Engine::new(&args)?;
+                    if let Some(debugger) = &mut debugger {
+                        debugger.forget_history();
+                    }
+                    eprintln!("State loaded from {}", state_path.display());
+                    osd.show(format!("Loaded slot {}", state_slot));
+                }
+            }
+        }
+        if CTRL_KEYS.iter().any(|&key| window.is_key_down(key))
+            && window.is_key_pressed(DUMP_DISPLAY_KEY, KeyRepeat::No)
+        {
+            print!("{}", screenshot::to_text(&cpu.display));
+        }
+        if !in_movie
+            && CTRL_KEYS.iter().any(|&key| window.is_key_down(key))
+            && window.is_key_pressed(CHEATS_KEY, KeyRepeat::No)
+        {
+            cheats_on = !cheats_on;
+            osd.show(if cheats_on { "Cheats on" } else { "Cheats off" });
+        }
+        if window.is_key_pressed(SCREENSHOT_KEY, KeyRepeat::No) {
+            let path = screenshot::default_path(&file, frame);
+            screenshot::save_png(&path, &cpu.display, &colors, args.screenshot_scale)?;
+            eprintln!("Screenshot saved to {}", path.display());
+            osd.show("Screenshot saved");
+        }
+        if window.is_key_pressed(GIF_KEY, KeyRepeat::No) {
+            match gif.take() {
+                Some(recorder) => {
+                    recorder.finish()?;
+                    eprintln!("GIF recording stopped");
+                    osd.show("GIF saved");
+                }
+                None => {
+                    let path = capture::default_gif_path(&file, frame);
+                    gif = Some(GifRecorder::create(&path, &colors, args.screenshot_scale)?);
+                    eprintln!("Recording GIF to {}", path.display());
+                    osd.show("Recording GIF");
+                }
+            }
+        }
+        if window.is_key_pressed(FULLSCREEN_KEY, KeyRepeat::No) {
+            fullscreen = !fullscreen;
+            window = render::open_window(fullscreen, width, height)?;
+            window.set_title(&format!("CHIP8 - {}", rom_name));
+            renderer.redraw();
+        }
+        let turbo = window.is_key_down(TURBO_KEY);
+        if turbo {
+            osd.show("Fast forward");
+        }
+
+        cpu.keypad = match player.as_mut().and_then(|p| p.keypad(frame)) {
+            Some(keypad) => keypad,
+            None => {
+                if player.take().is_some() {
+                    eprintln!("Movie ended at frame {}", frame);
+                    osd.show("Movie ended");
+                }
+                // with Shift held the digits pick a slot, and 1 to 4 are
+                // keypad keys in the default layout
+                let taken: &[Key] = if picking_slot { &DIGIT_KEYS } else { &[] };
+                let mut keypad = keymap.read(&window, taken);
+                if let Some(control) = &control {
+                    for (key, &held) in keypad.iter_mut().zip(&control.keys) {
+                        *key |= held;
+                    }
+                }
+                if let Some(crowd) = &mut crowd {
+                    for (key, held) in keypad.iter_mut().zip(crowd.keys()) {
+                        *key |= held;
+                    }
+                }
+                keypad
             }
+        };
+        if let Some(debugger) = &mut debugger {
+            match debugger.poll(&mut cpu) {
+                Action::Quit => break,
+                Action::Reset => reset = true,
+                Action::Continue => {}
+            }
+        }
+        if cheats_on {
+            cheats::apply(&cheats, &mut cpu);
         }
+        let rewinding = rewind.is_some() && window.is_key_down(REWIND_KEY);
+        let mut ran_frame = false;
+        if rewinding {
+            if let Some(state) = rewind.as_mut().and_then(Rewind::pop) {
+                cpu = state;
+                cpu.speed = scale_speed(base_speed, speed);
+                // cached blocks may not match the memory rewound to
+                engine = Engine::new(&args)?;
+                if let Some(debugger) = &mut debugger {
+                    debugger.forget_history();
+                }
+            }
+            osd.show("Rewinding");
+        } else {
+            let frozen = paused || debugger.as_ref().is_some_and(|d| d.paused);
+            // in slow motion frames are only emulated every few refreshes
+            let due = match slow_motion {
+                Some(ref mut progress) => {
+                    *progress += SLOW_MOTION;
+                    let due = *progress >= 1.0;
+                    if due {
+                        *progress -= 1.0;
+                    }
+                    due
+                }
+                None => true,
+            };
+            let running = !frozen && due;
+            let advance = !running && window.is_key_pressed(FRAME_ADVANCE_KEY, KeyRepeat::Yes);
+            if running || advance {
+                if let Some(netplay) = &mut netplay {
+                    cpu.keypad = netplay.exchange(&cpu.keypad)?;
+                }
+                match &mut debugger {
+                    Some(debugger) if advance => debugger.advance_frame(&mut cpu),
+                    Some(debugger) => debugger.run_frame(&mut cpu),
+                    None => fault = run_frame(&mut engine, &mut script, &mut cpu, frame).err(),
+                }
+                ran_frame = fault.is_none();
+            }
+        }
+        let executed = cpu.take_executed();
+        if let Some(tracer) = &mut tracer {
+            tracer.write(&executed)?;
+        }
+        if let Some(profiler) = &mut profiler {
+            profiler.add(&executed);
+        }
+        if let Some(coverage) = &mut coverage {
+            coverage.add(&executed);
+        }
+        if let Some(smc) = &mut smc {
+            smc.check(&executed);
+        }
+        if fault.is_some() {
+            break;
+        }
+        if ran_frame && let Some(rewind) = &mut rewind {
+            rewind.push(&cpu);
+        }
+
+        if ran_frame {
+            if let Some(recorder) = &mut recorder {
+                recorder.record(frame, &cpu.keypad, cpu.display.hash())?;
+            }
+            if let Some(gif) = &mut gif {
+                gif.add_frame(&cpu.display)?;
+            }
+            if let Some(video) = &mut video {
+                video.add_frame(&cpu.display, cpu.sound_timer > 0)?;
+            }
+        }
+        if window.is_key_pressed(DEBUG_KEY, KeyRepeat::No) {
+            show_debug = !show_debug;
+        }
+        renderer.debug = show_debug.then(|| cpu.status_lines());
 
-        window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
+        let (width, height) = window.get_size();
+        buffer.resize(width * height, 0);
+        if renderer.draw(
+            &mut buffer,
+            width,
+            height,
+            &cpu.display,
+            &colors,
+            osd.text().or(paused.then_some("PAUSED")),
+        ) {
+            window.update_with_buffer(&buffer, width, height).unwrap();
+        } else {
+            window.update();
+        }
+        if let Some(stream) = &stream {
+            stream.publish(&cpu.display, &colors);
+        }
+        if ran_frame {
+            frame += 1;
+        }
+        if let Some(reason) = stop_reason(&args, &cpu, frame) {
+            eprintln!("{}", reason);
+            break;
+        }
+
+        if let Some((fps, ips)) = meter.update(frame, cpu.cycles) {
+            window.set_title(&format!(
+                "CHIP8 - {} - {:.0} FPS - {:.0} IPS",
+                rom_name, fps, ips
+            ));
+        }
+
+        if !turbo {
+            pacer.wait();
+        }
+    }
+
+    if let Some(recorder) = recorder {
+        recorder.finish(frame)?;
+    }
+    // resuming a machine that faulted would only fault again
+    if (args.auto_save || args.resume) && fault.is_none() {
+        let path = savestate::resume_path(&rom_hash)
+            .ok_or_else(|| anyhow!("No data directory to save the session in"))?;
+        savestate::save(&path, &cpu, &rom_hash, args.state_format)?;
+        eprintln!("Session saved to {}", path.display());
+    }
+    if let Some(gif) = gif {
+        gif.finish()?;
+    }
+    if let Some(tracer) = tracer {
+        tracer.finish()?;
+    }
+    if let Some(profiler) = &profiler {
+        save_profile(profiler, args.profile.flatten().as_deref())?;
+    }
+    if let (Some(coverage), Some(path)) = (&coverage, &args.coverage) {
+        coverage.save(path)?;
+        eprintln!("Coverage saved to {}", path.display());
+    }
+    if let Some(video) = video {
+        video.finish()?;
+        eprintln!(
+            "Video saved to {}",
+            args.record.unwrap_or_default().display()
+        );
     }
 
-    println!("{}", cpu.registers[0]);
+    if let Some(path) = &args.dump_state {
+        dump_state(path, &cpu, frame)?;
+    }
+    if let Some(path) = &args.dump_display {
+        dump_display(path.as_deref(), &cpu.display)?;
+    }
+    if let Some(fault) = fault {
+        return Err(fault);
+    }
+    // a dump in standard output is all that goes there
+    let dumped_to_stdout = args.dump_state.as_deref().is_some_and(is_stdio)
+        || args
+            .dump_display
+            .as_ref()
+            .is_some_and(|path| path.as_deref().is_none_or(is_stdio));
+    if !dumped_to_stdout {
+        println!("{}", cpu.registers[0]);
+    }
 
     Ok(())
 }
@@ -0,0 +1,288 @@
+use anyhow::{Context, Result, anyhow};
+use cpu::Quirks;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const MAGIC: &str = "chip8-movie 1";
+
+/// Everything besides the input that a replay needs to be deterministic
+#[derive(Debug)]
+pub struct MovieHeader {
+    pub rom_hash: String,
+    pub seed: u64,
+    /// Instructions per frame and quirks the movie was recorded with,
+    /// which movies from before they were written don't have
+    pub speed: Option<usize>,
+    pub quirks: Option<Quirks>,
+}
+
+/// Writes keypad state changes as `<frame> <keys>` lines, where keys is the
+/// keypad as a 16 bit mask (bit N set when key N is down), and the display
+/// hash after every frame as `hash <frame> <hash>`
+pub struct Recorder {
+    out: BufWriter<File>,
+    last: Option<u16>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, header: &MovieHeader) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Couldn't create movie `{}`", path.display()))?;
+        let mut out = BufWriter::new(file);
+
+        writeln!(out, "{}", MAGIC)?;
+        writeln!(out, "rom {}", header.rom_hash)?;
+        writeln!(out, "seed {}", header.seed)?;
+        if let Some(speed) = header.speed {
+            writeln!(out, "speed {}", speed)?;
+        }
+        if let Some(quirks) = header.quirks {
+            writeln!(
+                out,
+                "quirks shift={} jump={} vf_reset={}",
+                quirks.shift as u8, quirks.jump as u8, quirks.vf_reset as u8
+            )?;
+        }
+
+        Ok(Self { out, last: None })
+    }
+
+    pub fn record(&mut self, frame: u64, keypad: &[bool; 16], display_hash: u64) -> Result<()> {
+        let keys = keys_to_mask(keypad);
+        if self.last != Some(keys) {
+            writeln!(self.out, "{} {:04x}", frame, keys)?;
+            self.last = Some(keys);
+        }
+        writeln!(self.out, "hash {} {:016x}", frame, display_hash)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self, frames: u64) -> Result<()> {
+        writeln!(self.out, "end {}", frames)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// A parsed movie file
+#[derive(Debug)]
+pub struct Movie {
+    pub header: MovieHeader,
+    inputs: Vec<(u64, u16)>,
+    pub hashes: Vec<u64>,
+    pub frames: u64,
+}
+
+impl Movie {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read movie `{}`", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid movie `{}`", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.lines().enumerate().map(|(n, l)| (n + 1, l.trim()));
+
+        match lines.next() {
+            Some((_, MAGIC)) => {}
+            _ => return Err(anyhow!("missing `{}` header", MAGIC)),
+        }
+
+        let mut rom_hash = None;
+        let mut seed = None;
+        let mut speed = None;
+        let mut quirks = None;
+        let mut inputs = Vec::new();
+        let mut hashes = Vec::new();
+        let mut frames = None;
+
+        for (n, line) in lines {
+            let Some((key, value)) = line.split_once(' ') else {
+                return Err(anyhow!("line {}: expected two fields", n));
+            };
+            match key {
+                "rom" => rom_hash = Some(value.to_string()),
+                "seed" => {
+                    seed = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("line {}: bad seed", n))?,
+                    )
+                }
+                "speed" => {
+                    speed = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("line {}: bad speed", n))?,
+                    )
+                }
+                "quirks" => {
+                    quirks = Some(parse_quirks(value).with_context(|| format!("line {}", n))?)
+                }
+                "hash" => {
+                    let (frame, hash) = value
+                        .split_once(' ')
+                        .ok_or_else(|| anyhow!("line {}: expected frame and hash", n))?;
+                    let frame: usize = frame
+                        .parse()
+                        .with_context(|| format!("line {}: bad frame number", n))?;
+                    if frame != hashes.len() {
+                        return Err(anyhow!(
+                            "line {}: hash for frame {} is out of order",
+                            n,
+                            frame
+                        ));
+                    }
+                    hashes.push(
+                        u64::from_str_radix(hash, 16)
+                            .with_context(|| format!("line {}: bad hash", n))?,
+                    );
+                }
+                "end" => {
+                    frames = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("line {}: bad end", n))?,
+                    )
+                }
+                _ => {
+                    let frame: u64 = key
+                        .parse()
+                        .with_context(|| format!("line {}: bad frame number", n))?;
+                    let keys = u16::from_str_radix(value, 16)
+                        .with_context(|| format!("line {}: bad keypad state", n))?;
+                    if inputs.last().is_some_and(|&(last, _)| last >= frame) {
+                        return Err(anyhow!("line {}: frames out of order", n));
+                    }
+                    inputs.push((frame, keys));
+                }
+            }
+        }
+
+        let header = MovieHeader {
+            rom_hash: rom_hash.ok_or_else(|| anyhow!("missing `rom` line"))?,
+            seed: seed.ok_or_else(|| anyhow!("missing `seed` line"))?,
+            speed,
+            quirks,
+        };
+        let frames = frames
+            .or_else(|| inputs.last().map(|&(frame, _)| frame + 1))
+            .unwrap_or(0);
+
+        Ok(Self {
+            header,
+            inputs,
+            hashes,
+            frames,
+        })
+    }
+}
+
+/// Feeds the inputs of a movie back frame by frame
+pub struct Player {
+    movie: Movie,
+    next: usize,
+    keys: u16,
+}
+
+impl Player {
+    pub fn new(movie: Movie) -> Self {
+        Self {
+            movie,
+            next: 0,
+            keys: 0,
+        }
+    }
+
+    /// Keypad state for `frame`, or `None` once the movie has ended.
+    /// Frames must be requested in order.
+    pub fn keypad(&mut self, frame: u64) -> Option<[bool; 16]> {
+        if frame >= self.movie.frames {
+            return None;
+        }
+        while let Some(&(at, keys)) = self.movie.inputs.get(self.next) {
+            if at > frame {
+                break;
+            }
+            self.keys = keys;
+            self.next += 1;
+        }
+        Some(mask_to_keys(self.keys))
+    }
+}
+
+/// Quirks as `shift=1 jump=1 vf_reset=0`
+fn parse_quirks(text: &str) -> Result<Quirks> {
+    let mut quirks = Quirks::default();
+    for field in text.split_whitespace() {
+        let (name, value) = field
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected NAME=0 or NAME=1, found `{}`", field))?;
+        let value = match value {
+            "0" => false,
+            "1" => true,
+            _ => return Err(anyhow!("bad value for quirk `{}`", name)),
+        };
+        match name {
+            "shift" => quirks.shift = value,
+            "jump" => quirks.jump = value,
+            "vf_reset" => quirks.vf_reset = value,
+            _ => return Err(anyhow!("unknown quirk `{}`", name)),
+        }
+    }
+    Ok(quirks)
+}
+
+pub fn rom_hash(program: &[u8]) -> String {
+    Sha256::digest(program)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn keys_to_mask(keypad: &[bool; 16]) -> u16 {
+    keypad
+        .iter()
+        .enumerate()
+        .filter(|(_, down)| **down)
+        .fold(0, |mask, (key, _)| mask | 1 << key)
+}
+
+fn mask_to_keys(mask: u16) -> [bool; 16] {
+    std::array::from_fn(|key| mask & 1 << key != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_speed_and_quirks() {
+        let path = std::env::temp_dir().join(format!("chip8-movie-{}.c8m", std::process::id()));
+        let quirks = Quirks {
+            shift: false,
+            jump: true,
+            vf_reset: true,
+        };
+        let header = MovieHeader {
+            rom_hash: rom_hash(b"\x12\x00"),
+            seed: 7,
+            speed: Some(25),
+            quirks: Some(quirks),
+        };
+        let mut recorder = Recorder::create(&path, &header).unwrap();
+        recorder.record(0, &[false; 16], 0).unwrap();
+        recorder.finish(1).unwrap();
+
+        let movie = Movie::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(movie.header.speed, Some(25));
+        assert_eq!(movie.header.quirks, Some(quirks));
+
+        // movies from before speed and quirks were written still load
+        let old = Movie::parse("chip8-movie 1\nrom ab\nseed 1\nend 0\n").unwrap();
+        assert_eq!((old.header.speed, old.header.quirks), (None, None));
+    }
+}
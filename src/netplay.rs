@@ -0,0 +1,192 @@
+use anyhow::{Context, Result, anyhow};
+use cpu::Quirks;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// What both players' emulators have to agree on to run the same game
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Game {
+    pub rom_hash: String,
+    pub speed: usize,
+    pub quirks: Quirks,
+}
+
+/// The first message, from the host, a JSON line
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    game: Game,
+    seed: u64,
+}
+
+fn keypad_mask(keypad: &[bool; 16]) -> u16 {
+    (0..16).fold(0, |mask, key| mask | (keypad[key] as u16) << key)
+}
+
+/// Two emulators running a game in lockstep over TCP. Every frame each
+/// sends its keypad to the other, to take effect `delay` frames later so
+/// that it arrives in time, and both run the frame with the keys of both
+/// players held down.
+pub struct Netplay {
+    stream: TcpStream,
+    /// Our keys for the frames until they take effect
+    local: VecDeque<u16>,
+    /// Frames at the start with no keys from the other player, since
+    /// nothing was sent for them
+    waiting: u64,
+}
+
+impl Netplay {
+    fn new(stream: TcpStream, delay: u64) -> Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            local: (0..delay).map(|_| 0).collect(),
+            waiting: delay,
+        })
+    }
+
+    /// Waits on `port` for the other player and tells them the game and
+    /// the random seed
+    pub fn host(port: u16, game: &Game, seed: u64, delay: u64) -> Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))
+            .with_context(|| format!("Couldn't listen for the other player on port {}", port))?;
+        eprintln!("Waiting for the other player on port {}", port);
+        let (stream, addr) = listener
+            .accept()
+            .context("Couldn't accept the other player")?;
+        eprintln!("{} joined", addr);
+
+        let mut hello = serde_json::to_string(&Hello {
+            game: game.clone(),
+            seed,
+        })?;
+        hello.push('\n');
+        (&stream).write_all(hello.as_bytes())?;
+        let theirs: Game = read_line(&stream)?;
+        check(game, &theirs)?;
+        Self::new(stream, delay)
+    }
+
+    /// Joins the game hosted at `addr`, returning the seed to run it with
+    pub fn connect(addr: &str, game: &Game, delay: u64) -> Result<(Self, u64)> {
+        let stream = TcpStream::connect(addr)
+            .with_context(|| format!("Couldn't connect to the host at {}", addr))?;
+        let hello: Hello = read_line(&stream)?;
+        let mut ours = serde_json::to_string(game)?;
+        ours.push('\n');
+        (&stream).write_all(ours.as_bytes())?;
+        check(game, &hello.game)?;
+        eprintln!("Joined the game at {}", addr);
+        Ok((Self::new(stream, delay)?, hello.seed))
+    }
+
+    /// Sends our keys for this frame and returns the keypad to run the
+    /// frame `delay` frames later with, waiting for the other player if
+    /// their keys haven't arrived yet
+    pub fn exchange(&mut self, keypad: &[bool; 16]) -> Result<[bool; 16]> {
+        let mask = keypad_mask(keypad);
+        self.stream
+            .write_all(&mask.to_be_bytes())
+            .context("Lost the other player")?;
+        self.local.push_back(mask);
+        let ours = self.local.pop_front().unwrap_or_default();
+
+        let theirs = if self.waiting > 0 {
+            self.waiting -= 1;
+            0
+        } else {
+            let mut bytes = [0; 2];
+            self.stream
+                .read_exact(&mut bytes)
+                .context("Lost the other player")?;
+            u16::from_be_bytes(bytes)
+        };
+        let both = ours | theirs;
+        Ok(std::array::from_fn(|key| both & 1 << key != 0))
+    }
+}
+
+/// A JSON line, read a byte at a time since the keys follow right after
+fn read_line<T: for<'de> Deserialize<'de>>(mut stream: &TcpStream) -> Result<T> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+    while byte[0] != b'\n' {
+        stream
+            .read_exact(&mut byte)
+            .context("Lost the other player")?;
+        line.push(byte[0]);
+    }
+    serde_json::from_slice(&line).context("The other player doesn't speak this netplay protocol")
+}
+
+fn check(ours: &Game, theirs: &Game) -> Result<()> {
+    if ours.rom_hash != theirs.rom_hash {
+        return Err(anyhow!("The other player runs a different ROM"));
+    }
+    if ours != theirs {
+        return Err(anyhow!(
+            "The other player runs the ROM with different settings: {} instructions per frame \
+             and {:?}, here {} and {:?}",
+            theirs.speed,
+            theirs.quirks,
+            ours.speed,
+            ours.quirks
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn both_players_see_both_keypads_after_the_delay() {
+        let game = Game {
+            rom_hash: "ab".into(),
+            speed: 10,
+            quirks: Quirks::default(),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let guest = thread::spawn({
+            let game = game.clone();
+            move || {
+                let (mut netplay, seed) = Netplay::connect(&addr.to_string(), &game, 2).unwrap();
+                let frames: Vec<_> = (0..4)
+                    .map(|frame| netplay.exchange(&[frame == 0; 16]).unwrap()[0])
+                    .collect();
+                // closing with the host's last keys unread would reset
+                // the connection before the host reads ours
+                (seed, frames, netplay)
+            }
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let hello = Hello {
+            game: game.clone(),
+            seed: 7,
+        };
+        (&stream)
+            .write_all(format!("{}\n", serde_json::to_string(&hello).unwrap()).as_bytes())
+            .unwrap();
+        assert_eq!(read_line::<Game>(&stream).unwrap(), game);
+
+        let mut host = Netplay::new(stream, 2).unwrap();
+        let mut keys = [false; 16];
+        keys[1] = true;
+        let frames: Vec<_> = (0..4)
+            .map(|_| host.exchange(&keys).unwrap())
+            .map(|keypad| (keypad[0], keypad[1]))
+            .collect();
+        // the guest's key 0 on its first frame shows up two frames later
+        assert_eq!(
+            frames,
+            [(false, false), (false, false), (true, true), (false, true)]
+        );
+        let (seed, frames, _) = guest.join().unwrap();
+        assert_eq!((seed, frames), (7, vec![false, false, true, false]));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::Quirks;
+use crate::config::RomSettings;
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The options Octo exports next to a game as `game.json`, and keeps in its
+/// cartridges. Only the speed, the quirks the emulator has and the colors
+/// of the first plane are used.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OctoOptions {
+    tickrate: Option<usize>,
+    fill_color: Option<String>,
+    background_color: Option<String>,
+    shift_quirks: Option<Quirk>,
+    jump_quirks: Option<Quirk>,
+    logic_quirks: Option<Quirk>,
+}
+
+/// A quirk switch: `true`/`false`, or `1`/`0` in cartridges from older Octo
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Quirk {
+    Flag(bool),
+    Number(u8),
+}
+
+impl Quirk {
+    fn on(self) -> bool {
+        matches!(self, Quirk::Flag(true) | Quirk::Number(1..))
+    }
+}
+
+/// `#RRGGBB`, the way Octo writes colors
+fn color(text: &str) -> Result<u32> {
+    text.strip_prefix('#')
+        .filter(|hex| hex.len() == 6)
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| anyhow!("`{}` isn't a #RRGGBB color", text))
+}
+
+impl OctoOptions {
+    pub fn settings(self) -> Result<RomSettings> {
+        let quirks = (self.shift_quirks.is_some()
+            || self.jump_quirks.is_some()
+            || self.logic_quirks.is_some())
+        .then(|| {
+            let defaults = Quirks::default();
+            Quirks {
+                shift: self.shift_quirks.map_or(defaults.shift, Quirk::on),
+                jump: self.jump_quirks.map_or(defaults.jump, Quirk::on),
+                vf_reset: self.logic_quirks.map_or(defaults.vf_reset, Quirk::on),
+            }
+        });
+        Ok(RomSettings {
+            speed: self.tickrate,
+            quirks,
+            fg: self.fill_color.as_deref().map(color).transpose()?,
+            bg: self.background_color.as_deref().map(color).transpose()?,
+            ..RomSettings::default()
+        })
+    }
+}
+
+/// The Octo options file of `rom`, with the same name and a .json extension
+pub fn path(rom: &Path) -> PathBuf {
+    rom.with_extension("json")
+}
+
+/// The settings from the Octo options next to `rom`, if there are any
+pub fn load(rom: &Path) -> Result<Option<RomSettings>> {
+    let path = path(rom);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let text =
+        fs::read_to_string(&path).with_context(|| format!("Couldn't read `{}`", path.display()))?;
+    let options: OctoOptions = serde_json::from_str(&text)
+        .with_context(|| format!("`{}` isn't an Octo options file", path.display()))?;
+    let settings = options
+        .settings()
+        .with_context(|| format!("In `{}`", path.display()))?;
+    Ok(Some(settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_what_octo_exports() {
+        let options: OctoOptions = serde_json::from_str(
+            r##"{"tickrate":200,"fillColor":"#FFCC00","fillColor2":"#FF6600",
+            "blendColor":"#662200","backgroundColor":"#996600","buzzColor":"#FFAA00",
+            "quietColor":"#000000","shiftQuirks":false,"loadStoreQuirks":false,
+            "vfOrderQuirks":false,"clipQuirks":true,"jumpQuirks":false,"logicQuirks":true,
+            "vBlankQuirks":false,"screenRotation":0,"maxSize":3584,
+            "touchInputMode":"none","fontStyle":"octo"}"##,
+        )
+        .unwrap();
+        let settings = options.settings().unwrap();
+        assert_eq!(settings.speed, Some(200));
+        assert_eq!((settings.fg, settings.bg), (Some(0xFFCC00), Some(0x996600)));
+        let quirks = settings.quirks.unwrap();
+        assert!(!quirks.shift && !quirks.jump && quirks.vf_reset);
+
+        assert!(color("FFCC00").is_err());
+
+        let options: OctoOptions =
+            serde_json::from_str(r#"{"shiftQuirks":1,"jumpQuirks":0}"#).unwrap();
+        let quirks = options.settings().unwrap().quirks.unwrap();
+        assert!(quirks.shift && !quirks.jump);
+    }
+}
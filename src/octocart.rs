@@ -0,0 +1,119 @@
+use crate::asm;
+use crate::config::RomSettings;
+use crate::octo_options::OctoOptions;
+use anyhow::{Context, Result, anyhow};
+use gif::{ColorOutput, DecodeOptions};
+use serde::Deserialize;
+use std::path::Path;
+
+/// What an Octo cartridge holds: the game's source and its options
+#[derive(Deserialize)]
+struct Cartridge {
+    program: String,
+    options: Option<OctoOptions>,
+}
+
+pub fn is_cartridge(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("gif"))
+}
+
+/// The data hidden in a cartridge: every pixel of every frame carries 4
+/// bits in the low bits of its color, 1 from red, 2 from green and 1 from
+/// blue. Two pixels make a byte, high bits first, and the data starts with
+/// its length as 4 big-endian bytes.
+fn payload(nybbles: &[u8]) -> Result<Vec<u8>> {
+    let byte = |index: usize| {
+        nybbles
+            .get(index * 2..index * 2 + 2)
+            .map(|pair| pair[0] << 4 | pair[1])
+    };
+    let truncated = || anyhow!("Not an Octo cartridge, the data is cut short");
+    let mut size = 0usize;
+    for index in 0..4 {
+        size = size << 8 | byte(index).ok_or_else(truncated)? as usize;
+    }
+    (4..4 + size)
+        .map(|index| byte(index).ok_or_else(truncated))
+        .collect()
+}
+
+/// The 4 bits hidden in the `rgb` color of a pixel
+fn nybble(rgb: &[u8]) -> u8 {
+    rgb[0] << 3 & 8 | rgb[1] << 1 & 6 | rgb[2] & 1
+}
+
+/// The assembled program and the settings of the Octo cartridge `bytes`
+pub fn load(bytes: &[u8]) -> Result<(Vec<u8>, Option<RomSettings>)> {
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::Indexed);
+    let mut decoder = options.read_info(bytes).context("Not a GIF")?;
+    let global = decoder.global_palette().map(<[u8]>::to_vec);
+    let mut nybbles = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().context("Not a GIF")? {
+        let palette = frame
+            .palette
+            .as_deref()
+            .or(global.as_deref())
+            .ok_or_else(|| anyhow!("The GIF has no colors"))?;
+        for &index in frame.buffer.iter() {
+            let rgb = palette
+                .get(index as usize * 3..index as usize * 3 + 3)
+                .ok_or_else(|| anyhow!("Not a GIF, a pixel is out of the palette"))?;
+            nybbles.push(nybble(rgb));
+        }
+    }
+
+    let payload = payload(&nybbles)?;
+    let cartridge: Cartridge = serde_json::from_slice(&payload).context("Not an Octo cartridge")?;
+    let (program, _) = asm::assemble(&cartridge.program).context("In the cartridge's program")?;
+    let settings = cartridge.options.map(OctoOptions::settings).transpose()?;
+    Ok((program, settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gif::{Encoder, Frame};
+
+    #[test]
+    fn reads_the_program_and_options() {
+        let json = r##"{"program":": main\nv0 := 7","options":{"tickrate":30}}"##;
+        let mut data = (json.len() as u32).to_be_bytes().to_vec();
+        data.extend(json.as_bytes());
+        // color 16 + n hides n; the image's own colors are in the high bits
+        let palette: Vec<u8> = (0..32u8)
+            .flat_map(|n| [n >> 3 & 1 | 0x80, n >> 1 & 3 | 0x40, n & 1 | 0x20])
+            .collect();
+        let mut pixels: Vec<u8> = data
+            .iter()
+            .flat_map(|byte| [byte >> 4 | 16, byte & 15 | 16])
+            .collect();
+        pixels.resize(32 * 32, 0);
+
+        let mut gif = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut gif, 32, 32, &palette).unwrap();
+            let frame = Frame::from_indexed_pixels(32, 32, pixels, None);
+            encoder.write_frame(&frame).unwrap();
+        }
+
+        let (program, settings) = load(&gif).unwrap();
+        assert_eq!(program, [0x12, 0x02, 0x60, 0x07]);
+        assert_eq!(settings.unwrap().speed, Some(30));
+        assert!(load(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn reads_a_cartridge_from_octo() {
+        // exported from Octo; from the tests of the decart crate (MIT)
+        let (program, settings) = load(include_bytes!("../tests/carts/minimal.gif")).unwrap();
+        assert_eq!(program, [0x12, 0x02]);
+        let settings = settings.unwrap();
+        assert_eq!(settings.speed, Some(7));
+        assert_eq!(settings.fg, Some(0xFFCC00));
+        assert_eq!(settings.bg, Some(0x996600));
+        let quirks = settings.quirks.unwrap();
+        assert!(!quirks.shift && !quirks.jump && quirks.vf_reset);
+    }
+}
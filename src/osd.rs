@@ -0,0 +1,28 @@
+use std::time::{Duration, Instant};
+
+const MESSAGE_TIME: Duration = Duration::from_secs(2);
+
+/// On-screen display: a short message shown for a couple of seconds in the
+/// border below the screen, as feedback for hotkeys
+#[derive(Debug, Default)]
+pub struct Osd {
+    message: Option<(String, Instant)>,
+}
+
+impl Osd {
+    pub fn show(&mut self, text: impl Into<String>) {
+        self.message = Some((text.into(), Instant::now() + MESSAGE_TIME));
+    }
+
+    /// The message to show right now, if any
+    pub fn text(&mut self) -> Option<&str> {
+        if self
+            .message
+            .as_ref()
+            .is_some_and(|(_, until)| Instant::now() >= *until)
+        {
+            self.message = None;
+        }
+        self.message.as_ref().map(|(text, _)| text.as_str())
+    }
+}
@@ -0,0 +1,46 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Frames per second, also the rate the timers count down at
+pub const TARGET_FPS: u32 = 60;
+
+/// How long before a frame is due to stop sleeping and spin instead, sleep
+/// can overshoot by about this much
+const SPIN_MARGIN: Duration = Duration::from_micros(1500);
+
+/// Keeps frames exactly 1/60 s apart on average. Sleeps for most of the
+/// wait and spins for the rest, since sleeping alone is too coarse and the
+/// timers would drift.
+pub struct FramePacer {
+    period: Duration,
+    next: Instant,
+}
+
+impl FramePacer {
+    pub fn new() -> Self {
+        let period = Duration::from_secs(1) / TARGET_FPS;
+        Self {
+            period,
+            next: Instant::now() + period,
+        }
+    }
+
+    /// Waits until the next frame is due
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        if now > self.next + self.period {
+            // too far behind (a breakpoint, a slow frame, fast forward) to
+            // catch up, start counting from now
+            self.next = now + self.period;
+            return;
+        }
+
+        if let Some(sleep) = self.next.checked_duration_since(now + SPIN_MARGIN) {
+            thread::sleep(sleep);
+        }
+        while Instant::now() < self.next {
+            std::hint::spin_loop();
+        }
+        self.next += self.period;
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::{Result, anyhow};
+
+/// Window colors as 0RGB, the format minifb expects.
+///
+/// `pixels` is indexed by the plane bits of a pixel, like in Octo: background,
+/// plane 1 (the only one plain CHIP8 uses), plane 2 and both planes blended.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    pub name: &'static str,
+    pub pixels: [u32; 4],
+    pub border: u32,
+}
+
+impl Palette {
+    pub fn bg(&self) -> u32 {
+        self.pixels[0]
+    }
+
+    pub fn fg(&self) -> u32 {
+        self.pixels[1]
+    }
+}
+
+pub const PALETTES: [Palette; 5] = [
+    Palette {
+        name: "default",
+        pixels: [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555],
+        border: 0x252429,
+    },
+    Palette {
+        name: "green-phosphor",
+        pixels: [0x0A1A0F, 0x33FF66, 0x1F9940, 0x8CFFAA],
+        border: 0x050D08,
+    },
+    Palette {
+        name: "amber",
+        pixels: [0x1A1000, 0xFFB000, 0x996A00, 0xFFD780],
+        border: 0x0D0800,
+    },
+    Palette {
+        name: "gameboy",
+        pixels: [0x9BBC0F, 0x0F380F, 0x8BAC0F, 0x306230],
+        border: 0x306230,
+    },
+    Palette {
+        name: "high-contrast",
+        pixels: [0x000000, 0xFFFF00, 0x00FFFF, 0xFFFFFF],
+        border: 0x404040,
+    },
+];
+
+/// Index of the palette called `name` in [`PALETTES`]
+pub fn parse(name: &str) -> Result<usize> {
+    PALETTES.iter().position(|p| p.name == name).ok_or_else(|| {
+        let names: Vec<_> = PALETTES.iter().map(|p| p.name).collect();
+        anyhow!("unknown palette, expected one of: {}", names.join(", "))
+    })
+}
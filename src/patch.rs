@@ -0,0 +1,257 @@
+use anyhow::{Context, Result, anyhow};
+use std::path::Path;
+
+/// Applies the IPS or BPS patch at `path` to `rom`
+pub fn apply_file(path: &Path, rom: &[u8]) -> Result<Vec<u8>> {
+    let patch =
+        std::fs::read(path).with_context(|| format!("Couldn't read patch `{}`", path.display()))?;
+    apply(&patch, rom).with_context(|| format!("Couldn't apply patch `{}`", path.display()))
+}
+
+/// Applies `patch`, telling IPS and BPS apart by their headers
+pub fn apply(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>> {
+    if let Some(records) = patch.strip_prefix(b"PATCH") {
+        apply_ips(records, rom)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(patch, rom)
+    } else {
+        Err(anyhow!("not an IPS or BPS patch"))
+    }
+}
+
+/// Reads big-endian numbers and byte strings off the front of a patch
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.bytes.len() {
+            return Err(anyhow!("the patch is cut short"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn number(&mut self, len: usize) -> Result<usize> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0, |n, &byte| n << 8 | byte as usize))
+    }
+
+    /// A number in the variable length encoding of BPS
+    fn varint(&mut self) -> Result<usize> {
+        let mut n = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.take(1)?[0];
+            n = (byte as usize & 0x7F)
+                .checked_mul(shift)
+                .and_then(|add| n.checked_add(add))
+                .ok_or_else(|| anyhow!("a number in the patch is too large"))?;
+            if byte & 0x80 != 0 {
+                return Ok(n);
+            }
+            shift = shift
+                .checked_mul(0x80)
+                .ok_or_else(|| anyhow!("a number in the patch is too large"))?;
+            n = n
+                .checked_add(shift)
+                .ok_or_else(|| anyhow!("a number in the patch is too large"))?;
+        }
+    }
+}
+
+/// IPS: records of a 3 byte offset, a 2 byte size and the data, or a size
+/// of 0 and a 2 byte count of a repeated byte, until `EOF`. A 3 byte size
+/// the result is truncated to can follow.
+fn apply_ips(records: &[u8], rom: &[u8]) -> Result<Vec<u8>> {
+    let mut patched = rom.to_vec();
+    let mut reader = Reader { bytes: records };
+    loop {
+        let offset = reader.take(3)?;
+        if offset == b"EOF" {
+            break;
+        }
+        let offset = offset.iter().fold(0, |n, &byte| n << 8 | byte as usize);
+        let data = match reader.number(2)? {
+            0 => {
+                let count = reader.number(2)?;
+                vec![reader.take(1)?[0]; count]
+            }
+            size => reader.take(size)?.to_vec(),
+        };
+        let end = offset + data.len();
+        if patched.len() < end {
+            patched.resize(end, 0);
+        }
+        patched[offset..end].copy_from_slice(&data);
+    }
+    if !reader.bytes.is_empty() {
+        let size = reader.number(3)?;
+        patched.truncate(size);
+    }
+    Ok(patched)
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB88320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// BPS: the sizes and metadata, then actions that copy from the source,
+/// from the patch or from what was already written, and the CRC32s of the
+/// source, the target and the patch
+fn apply_bps(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>> {
+    if patch.len() < 4 + 12 {
+        return Err(anyhow!("the patch is cut short"));
+    }
+    let (body, footer) = patch.split_at(patch.len() - 12);
+    let crc = |n: usize| u32::from_le_bytes(footer[n * 4..n * 4 + 4].try_into().unwrap());
+    if crc32(&patch[..patch.len() - 4]) != crc(2) {
+        return Err(anyhow!("the patch is damaged"));
+    }
+    if crc32(rom) != crc(0) {
+        return Err(anyhow!("the patch is for a different ROM"));
+    }
+
+    let mut reader = Reader { bytes: &body[4..] };
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.take(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(anyhow!("the patch is for a different ROM"));
+    }
+
+    let mut target = Vec::new();
+    let (mut source_offset, mut target_offset) = (0usize, 0usize);
+    let out_of_range = || anyhow!("the patch copies from outside the ROM");
+    // a signed offset, the sign in the lowest bit
+    let relative = |reader: &mut Reader, offset: usize| -> Result<usize> {
+        let n = reader.varint()?;
+        let delta = n >> 1;
+        if n & 1 == 0 {
+            offset.checked_add(delta)
+        } else {
+            offset.checked_sub(delta)
+        }
+        .ok_or_else(out_of_range)
+    };
+    while !reader.bytes.is_empty() {
+        let action = reader.varint()?;
+        let len = (action >> 2) + 1;
+        if target
+            .len()
+            .checked_add(len)
+            .is_none_or(|end| end > target_size)
+        {
+            return Err(anyhow!("the patch writes past the end of the ROM"));
+        }
+        match action & 3 {
+            0 => {
+                let start = target.len();
+                let end = start.checked_add(len).ok_or_else(out_of_range)?;
+                target.extend_from_slice(rom.get(start..end).ok_or_else(out_of_range)?);
+            }
+            1 => target.extend_from_slice(reader.take(len)?),
+            2 => {
+                source_offset = relative(&mut reader, source_offset)?;
+                let end = source_offset.checked_add(len).ok_or_else(out_of_range)?;
+                let bytes = rom.get(source_offset..end).ok_or_else(out_of_range)?;
+                target.extend_from_slice(bytes);
+                source_offset += len;
+            }
+            _ => {
+                target_offset = relative(&mut reader, target_offset)?;
+                if target_offset >= target.len() {
+                    return Err(out_of_range());
+                }
+                // byte by byte, since the copy can overlap what it writes
+                for _ in 0..len {
+                    target.push(target[target_offset]);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if target.len() != target_size || crc32(&target) != crc(1) {
+        return Err(anyhow!("the patched ROM isn't what the patch expects"));
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_ips_records() {
+        let patch = [
+            b"PATCH".as_slice(),
+            &[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB],
+            // 3 bytes of 0xCC past the end of the ROM
+            &[0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x03, 0xCC],
+            b"EOF",
+        ]
+        .concat();
+        let rom = [1, 2, 3, 4];
+        assert_eq!(
+            apply(&patch, &rom).unwrap(),
+            [1, 0xAA, 0xBB, 4, 0, 0xCC, 0xCC, 0xCC]
+        );
+        let truncated = [patch.as_slice(), &[0x00, 0x00, 0x02]].concat();
+        assert_eq!(apply(&truncated, &rom).unwrap(), [1, 0xAA]);
+        assert!(apply(&patch[..patch.len() - 4], &rom).is_err());
+    }
+
+    #[test]
+    fn applies_bps_actions() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        let rom = [1, 2, 3, 4];
+        let target = [1, 2, 9, 9, 9, 9, 3, 4];
+        let mut patch = [
+            b"BPS1".as_slice(),
+            // sizes 4 and 8, no metadata
+            &[0x84, 0x88, 0x80],
+            // source read 2, target read 1, target copy 3 from offset 2,
+            // source copy 2 from offset 2
+            &[0x80 | 1 << 2, 0x80 | 1, 9, 0x80 | 2 << 2 | 3, 0x80 | 2 << 1],
+            &[0x80 | 1 << 2 | 2, 0x80 | 2 << 1],
+        ]
+        .concat();
+        patch.extend(crc32(&rom).to_le_bytes());
+        patch.extend(crc32(&target).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        assert_eq!(apply(&patch, &rom).unwrap(), target);
+        assert!(apply(&patch, &[1, 2, 3, 5]).is_err());
+        let last = patch.len() - 1;
+        patch[last] ^= 1;
+        assert!(apply(&patch, &rom).is_err());
+    }
+
+    #[test]
+    fn rejects_bps_numbers_that_overflow() {
+        let rom = [1, 2, 3, 4];
+        let bps = |actions: &[u8]| {
+            let mut patch = [b"BPS1".as_slice(), &[0x84, 0x88, 0x80], actions].concat();
+            patch.extend(crc32(&rom).to_le_bytes());
+            patch.extend([0; 4]);
+            patch.extend(crc32(&patch).to_le_bytes());
+            apply(&patch, &rom)
+        };
+        // a source copy from the largest offset forward
+        let mut far = vec![0x7F; 8];
+        far.push(0x80);
+        let error = bps(&[[0x80 | 1 << 2 | 2].as_slice(), &far].concat()).unwrap_err();
+        assert!(format!("{:#}", error).contains("outside the ROM"));
+        // a number longer than usize
+        let error = bps(&[0x7F; 11]).unwrap_err();
+        assert!(format!("{:#}", error).contains("too large"));
+    }
+}
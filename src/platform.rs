@@ -0,0 +1,108 @@
+use crate::Quirks;
+use crate::info::Scan;
+use crate::instruction::Instruction;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+
+/// The CHIP-8 variants, in the order they extend each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    Chip8,
+    Schip,
+    #[value(name = "xochip")]
+    XoChip,
+}
+
+impl Platform {
+    /// The platform the opcodes reachable from the start of `program` need
+    pub fn detect(program: &[u8]) -> Platform {
+        Scan::new(program).platform()
+    }
+
+    /// The platform the extension of `path` stands for, like SCHIP for .sc8
+    pub fn from_extension(path: &Path) -> Option<Platform> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ch8" => Some(Platform::Chip8),
+            "sc8" => Some(Platform::Schip),
+            "xo8" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    /// The quirks programs written for the platform expect
+    pub const fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::CHIP8,
+            Platform::Schip => Quirks::SCHIP,
+            Platform::XoChip => Quirks::XOCHIP,
+        }
+    }
+
+    /// The first platform with `opcode`, and the opcode's pattern like
+    /// `Fx30`, or `None` if no platform has it
+    pub fn of(opcode: u16) -> Option<(Platform, &'static str)> {
+        let x = (opcode >> 8) & 0xF;
+        let n = opcode & 0xF;
+        let found = match (opcode >> 12, x, opcode & 0xFF) {
+            (0x0, 0x0, 0xFB) => (Platform::Schip, "00FB"),
+            (0x0, 0x0, 0xFC) => (Platform::Schip, "00FC"),
+            (0x0, 0x0, 0xFD) => (Platform::Schip, "00FD"),
+            (0x0, 0x0, 0xFE) => (Platform::Schip, "00FE"),
+            (0x0, 0x0, 0xFF) => (Platform::Schip, "00FF"),
+            (0x0, 0x0, kk) if kk >> 4 == 0xC => (Platform::Schip, "00Cn"),
+            (0x0, 0x0, kk) if kk >> 4 == 0xD => (Platform::XoChip, "00Dn"),
+            (0x5, _, _) if n == 2 => (Platform::XoChip, "5xy2"),
+            (0x5, _, _) if n == 3 => (Platform::XoChip, "5xy3"),
+            (0xD, _, _) if n == 0 => (Platform::Schip, "Dxy0"),
+            (0xF, 0x0, 0x00) => (Platform::XoChip, "F000"),
+            (0xF, _, 0x01) => (Platform::XoChip, "Fn01"),
+            (0xF, 0x0, 0x02) => (Platform::XoChip, "F002"),
+            (0xF, _, 0x30) => (Platform::Schip, "Fx30"),
+            (0xF, _, 0x3A) => (Platform::XoChip, "Fx3A"),
+            (0xF, _, 0x75) => (Platform::Schip, "Fx75"),
+            (0xF, _, 0x85) => (Platform::Schip, "Fx85"),
+            _ => match Instruction::decode(opcode) {
+                Instruction::Unknown(_) => return None,
+                instruction => (Platform::Chip8, instruction.pattern()),
+            },
+        };
+        Some(found)
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Platform::Chip8 => write!(f, "CHIP-8"),
+            Platform::Schip => write!(f, "SCHIP"),
+            Platform::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions_stand_for_platforms() {
+        assert_eq!(
+            Platform::from_extension(Path::new("a.ch8")),
+            Some(Platform::Chip8)
+        );
+        assert_eq!(
+            Platform::from_extension(Path::new("b.SC8")),
+            Some(Platform::Schip)
+        );
+        assert_eq!(
+            Platform::from_extension(Path::new("c.xo8")),
+            Some(Platform::XoChip)
+        );
+        assert_eq!(Platform::from_extension(Path::new("d.o8")), None);
+        assert_eq!(Platform::from_extension(Path::new("pong")), None);
+    }
+}
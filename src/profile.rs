@@ -0,0 +1,89 @@
+use crate::instruction::Instruction;
+use crate::trace::Executed;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Addresses listed in the report
+const HOT_ADDRESSES: usize = 20;
+
+/// Counts executed instructions per address and opcode
+#[derive(Debug, Default)]
+pub struct Profiler {
+    counts: HashMap<(usize, u16), u64>,
+}
+
+impl Profiler {
+    pub fn add(&mut self, executed: &[Executed]) {
+        for e in executed {
+            *self.counts.entry((e.pc, e.opcode)).or_default() += 1;
+        }
+    }
+
+    /// Counts sorted by count, most executed first
+    fn sorted(&self) -> Vec<((usize, u16), u64)> {
+        let mut counts: Vec<_> = self.counts.iter().map(|(&k, &v)| (k, v)).collect();
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        counts
+    }
+
+    /// Prints the instructions executed per opcode pattern and the hottest
+    /// addresses
+    pub fn print_report(&self) {
+        let total: u64 = self.counts.values().sum();
+        let percent = |count: u64| count as f64 * 100.0 / total.max(1) as f64;
+
+        let mut patterns: HashMap<&str, u64> = HashMap::new();
+        for (&(_, opcode), &count) in &self.counts {
+            *patterns
+                .entry(Instruction::decode(opcode).pattern())
+                .or_default() += count;
+        }
+        let mut patterns: Vec<_> = patterns.into_iter().collect();
+        patterns.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+
+        println!("Instructions executed: {}", total);
+        println!();
+        println!("By opcode:");
+        for (pattern, count) in patterns {
+            println!("  {}  {:>12}  {:5.1}%", pattern, count, percent(count));
+        }
+        println!();
+        println!("Hottest addresses:");
+        for ((pc, opcode), count) in self.sorted().into_iter().take(HOT_ADDRESSES) {
+            println!(
+                "  {:04X}  {:04X}  {:<16}{:>12}  {:5.1}%",
+                pc,
+                opcode,
+                Instruction::decode(opcode).to_string(),
+                count,
+                percent(count)
+            );
+        }
+    }
+
+    /// Writes a row per address and opcode, most executed first
+    pub fn save_csv(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .with_context(|| format!("Couldn't create profile `{}`", path.display()))?;
+        let mut out = BufWriter::new(file);
+
+        writeln!(out, "address,opcode,pattern,mnemonic,count")?;
+        for ((pc, opcode), count) in self.sorted() {
+            let instruction = Instruction::decode(opcode);
+            writeln!(
+                out,
+                "{:04X},{:04X},{},\"{}\",{}",
+                pc,
+                opcode,
+                instruction.pattern(),
+                instruction,
+                count
+            )?;
+        }
+        out.flush()
+            .with_context(|| format!("Couldn't write profile `{}`", path.display()))
+    }
+}
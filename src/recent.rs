@@ -0,0 +1,115 @@
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// ROMs kept in the list
+const MAX_RECENT: usize = 10;
+
+/// The most recently run ROMs, newest first, a path per line
+fn list_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("chip8").join("recent.txt"))
+}
+
+/// The recent ROMs that still exist, and URLs
+pub fn load() -> Vec<PathBuf> {
+    let Some(text) = list_path().and_then(|path| fs::read_to_string(path).ok()) else {
+        return Vec::new();
+    };
+    text.lines()
+        .map(PathBuf::from)
+        .filter(|path| path.exists() || crate::is_url(path))
+        .collect()
+}
+
+/// Moves `rom` to the front of `list`, dropping the oldest beyond the limit
+fn push(list: &mut Vec<PathBuf>, rom: PathBuf) {
+    list.retain(|path| *path != rom);
+    list.insert(0, rom);
+    list.truncate(MAX_RECENT);
+}
+
+/// Puts `rom` at the top of the list, only warning if that fails since the
+/// ROM runs all the same
+pub fn remember(rom: &Path) {
+    if let Err(e) = add(rom) {
+        eprintln!("Warning: couldn't update the recent ROMs: {:#}", e);
+    }
+}
+
+fn add(rom: &Path) -> Result<()> {
+    let Some(path) = list_path() else {
+        return Ok(());
+    };
+    if crate::is_stdio(rom) {
+        return Ok(());
+    }
+    let rom = if crate::is_url(rom) {
+        rom.to_path_buf()
+    } else {
+        rom.canonicalize()
+            .with_context(|| format!("Couldn't find `{}`", rom.display()))?
+    };
+    let mut list = load();
+    push(&mut list, rom);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Couldn't create directory `{}`", dir.display()))?;
+    }
+    let text: String = list
+        .iter()
+        .map(|rom| format!("{}\n", rom.display()))
+        .collect();
+    fs::write(&path, text).with_context(|| format!("Couldn't write `{}`", path.display()))
+}
+
+/// The recent ROM numbered `choice`, counting from 1, or the one picked
+/// from the list in the terminal
+pub fn pick(choice: Option<usize>) -> Result<PathBuf> {
+    let list = load();
+    if list.is_empty() {
+        return Err(anyhow!("No recent ROMs yet"));
+    }
+    let choice = match choice {
+        Some(choice) => choice,
+        None => {
+            println!("Recent ROMs:");
+            for (n, rom) in list.iter().enumerate() {
+                println!("{:>4}  {}", n + 1, rom.display());
+            }
+            print!("Which one? ");
+            io::stdout().flush()?;
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            line.trim()
+                .parse()
+                .map_err(|_| anyhow!("`{}` isn't a number", line.trim()))?
+        }
+    };
+    choice
+        .checked_sub(1)
+        .and_then(|index| list.get(index))
+        .cloned()
+        .ok_or_else(|| anyhow!("There are {} recent ROMs, not {}", list.len(), choice))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newest_rom_goes_first() {
+        let mut list: Vec<PathBuf> = (0..MAX_RECENT)
+            .map(|n| PathBuf::from(format!("{}.ch8", n)))
+            .collect();
+        push(&mut list, PathBuf::from("3.ch8"));
+        assert_eq!(list[0], Path::new("3.ch8"));
+        assert_eq!(list.len(), MAX_RECENT);
+
+        push(&mut list, PathBuf::from("new.ch8"));
+        assert_eq!(list[0], Path::new("new.ch8"));
+        assert_eq!(list.len(), MAX_RECENT);
+        assert!(!list.contains(&PathBuf::from("9.ch8")));
+    }
+}
@@ -0,0 +1,386 @@
+//! A second interpreter, written as plainly as possible: opcodes matched
+//! nibble by nibble, the screen a grid of `bool`s and the stack a `Vec`.
+//! It is slow and shares nothing with the core but `Fault` and `Rng`, so
+//! that running the two side by side (`--lockstep`) catches mistakes in
+//! the dispatch tables, the bit-packed display, the block cache or the JIT.
+
+use crate::{Cpu, Fault, Quirks, Rng};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+pub struct Reference {
+    v: [u8; 16],
+    i: u16,
+    pc: usize,
+    stack: Vec<u16>,
+    memory: Vec<u8>,
+    screen: [[bool; WIDTH]; HEIGHT],
+    dt: u8,
+    st: u8,
+    halted: bool,
+    cycles: usize,
+    keypad: [bool; 16],
+    rng: Rng,
+    quirks: Quirks,
+    speed: usize,
+    max_cycles: usize,
+    halt_on_self_jump: bool,
+}
+
+impl Reference {
+    /// A copy of the machine `cpu` is
+    pub fn new(cpu: &Cpu) -> Self {
+        let mut screen = [[false; WIDTH]; HEIGHT];
+        for (y, row) in screen.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = cpu.display.pixel(y, x);
+            }
+        }
+        Self {
+            v: cpu.registers,
+            i: cpu.register_i,
+            pc: cpu.position_in_memory,
+            stack: cpu.stack[..cpu.stack_pointer].to_vec(),
+            memory: cpu.memory.to_vec(),
+            screen,
+            dt: cpu.delay_timer,
+            st: cpu.sound_timer,
+            halted: cpu.halted,
+            cycles: cpu.cycles,
+            keypad: cpu.keypad,
+            rng: cpu.rng.clone(),
+            quirks: cpu.quirks,
+            speed: cpu.speed,
+            max_cycles: cpu.max_cycles,
+            halt_on_self_jump: cpu.halt_on_self_jump,
+        }
+    }
+
+    pub fn run_frame(&mut self) -> Result<(), Fault> {
+        for _ in 0..self.speed {
+            if self.halted || self.cycles >= self.max_cycles {
+                break;
+            }
+            self.step()?;
+        }
+        self.dt = self.dt.saturating_sub(1);
+        self.st = self.st.saturating_sub(1);
+        Ok(())
+    }
+
+    /// Executes one instruction, changing nothing but the instruction count
+    /// if it faults
+    pub fn step(&mut self) -> Result<(), Fault> {
+        let pc = self.pc;
+        if pc + 1 >= self.memory.len() {
+            return Err(Fault::PcOutOfBounds { pc });
+        }
+        self.cycles += 1;
+        let opcode = (self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16;
+        let nibbles = (
+            opcode >> 12,
+            opcode >> 8 & 0xF,
+            opcode >> 4 & 0xF,
+            opcode & 0xF,
+        );
+        let x = nibbles.1 as usize;
+        let y = nibbles.2 as usize;
+        let kk = (opcode & 0xFF) as u8;
+        let nnn = (opcode & 0xFFF) as usize;
+        let mut next = pc + 2;
+
+        match nibbles {
+            (0x0, 0x0, 0x0, 0x0) => self.halted = true,
+            (0x0, 0x0, 0xE, 0x0) => self.screen = [[false; WIDTH]; HEIGHT],
+            (0x0, 0x0, 0xE, 0xE) => match self.stack.pop() {
+                Some(addr) => next = addr as usize,
+                None => return Err(Fault::StackUnderflow { pc }),
+            },
+            (0x1, _, _, _) => {
+                if self.halt_on_self_jump && nnn == pc {
+                    self.halted = true;
+                }
+                next = nnn;
+            }
+            (0x2, _, _, _) => {
+                if self.stack.len() == 16 {
+                    return Err(Fault::StackOverflow { pc });
+                }
+                self.stack.push(next as u16);
+                next = nnn;
+            }
+            (0x3, _, _, _) => {
+                if self.v[x] == kk {
+                    next += 2;
+                }
+            }
+            (0x4, _, _, _) => {
+                if self.v[x] != kk {
+                    next += 2;
+                }
+            }
+            (0x5, _, _, 0x0) => {
+                if self.v[x] == self.v[y] {
+                    next += 2;
+                }
+            }
+            (0x6, _, _, _) => self.v[x] = kk,
+            (0x7, _, _, _) => self.v[x] = self.v[x].wrapping_add(kk),
+            (0x8, _, _, 0x0) => self.v[x] = self.v[y],
+            (0x8, _, _, 0x1) => {
+                self.v[x] |= self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+            }
+            (0x8, _, _, 0x2) => {
+                self.v[x] &= self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+            }
+            (0x8, _, _, 0x3) => {
+                self.v[x] ^= self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+            }
+            (0x8, _, _, 0x4) => {
+                let sum = self.v[x] as u16 + self.v[y] as u16;
+                self.v[x] = (sum & 0xFF) as u8;
+                self.v[0xF] = if sum > 0xFF { 1 } else { 0 };
+            }
+            (0x8, _, _, 0x5) => {
+                let (vx, vy) = (self.v[x], self.v[y]);
+                self.v[x] = vx.wrapping_sub(vy);
+                self.v[0xF] = if vx >= vy { 1 } else { 0 };
+            }
+            (0x8, _, _, 0x6) => {
+                let value = if self.quirks.shift {
+                    self.v[x]
+                } else {
+                    self.v[y]
+                };
+                self.v[x] = value / 2;
+                self.v[0xF] = value % 2;
+            }
+            (0x8, _, _, 0x7) => {
+                let (vx, vy) = (self.v[x], self.v[y]);
+                self.v[x] = vy.wrapping_sub(vx);
+                self.v[0xF] = if vy >= vx { 1 } else { 0 };
+            }
+            (0x8, _, _, 0xE) => {
+                let value = if self.quirks.shift {
+                    self.v[x]
+                } else {
+                    self.v[y]
+                };
+                self.v[x] = value.wrapping_mul(2);
+                self.v[0xF] = if value >= 0x80 { 1 } else { 0 };
+            }
+            (0x9, _, _, 0x0) => {
+                if self.v[x] != self.v[y] {
+                    next += 2;
+                }
+            }
+            (0xA, _, _, _) => self.i = nnn as u16,
+            (0xB, _, _, _) => {
+                let offset = if self.quirks.jump {
+                    self.v[x]
+                } else {
+                    self.v[0]
+                };
+                next = nnn + offset as usize;
+            }
+            (0xC, _, _, _) => self.v[x] = self.rng.next_u8() & kk,
+            (0xD, _, _, n) => {
+                let left = self.v[x] as usize % WIDTH;
+                let top = self.v[y] as usize % HEIGHT;
+                self.v[0xF] = 0;
+                for row in 0..n as usize {
+                    let sprite = self.memory[(self.i as usize + row) % self.memory.len()];
+                    for col in 0..8 {
+                        let (px, py) = (left + col, top + row);
+                        if px >= WIDTH || py >= HEIGHT || sprite & 0x80 >> col == 0 {
+                            continue;
+                        }
+                        if self.screen[py][px] {
+                            self.v[0xF] = 1;
+                        }
+                        self.screen[py][px] = !self.screen[py][px];
+                    }
+                }
+            }
+            (0xE, _, 0x9, 0xE) => {
+                if self.keypad[(self.v[x] & 0xF) as usize] {
+                    next += 2;
+                }
+            }
+            (0xE, _, 0xA, 0x1) => {
+                if !self.keypad[(self.v[x] & 0xF) as usize] {
+                    next += 2;
+                }
+            }
+            (0xF, _, 0x0, 0x7) => self.v[x] = self.dt,
+            (0xF, _, 0x0, 0xA) => match self.keypad.iter().position(|&down| down) {
+                Some(key) => self.v[x] = key as u8,
+                None => next = pc,
+            },
+            (0xF, _, 0x1, 0x5) => self.dt = self.v[x],
+            (0xF, _, 0x1, 0x8) => self.st = self.v[x],
+            (0xF, _, 0x1, 0xE) => self.i = self.i.wrapping_add(self.v[x] as u16),
+            // five bytes a digit, from where the core loads the font
+            (0xF, _, 0x2, 0x9) => self.i = 0x50 + (self.v[x] & 0xF) as u16 * 5,
+            (0xF, _, 0x3, 0x3) => {
+                let digits = [self.v[x] / 100, self.v[x] / 10 % 10, self.v[x] % 10];
+                for (n, digit) in digits.into_iter().enumerate() {
+                    let addr = (self.i as usize + n) % self.memory.len();
+                    self.memory[addr] = digit;
+                }
+            }
+            (0xF, _, 0x5, 0x5) => {
+                for r in 0..=x {
+                    let addr = (self.i as usize + r) % self.memory.len();
+                    self.memory[addr] = self.v[r];
+                }
+            }
+            (0xF, _, 0x6, 0x5) => {
+                for r in 0..=x {
+                    self.v[r] = self.memory[(self.i as usize + r) % self.memory.len()];
+                }
+            }
+            _ => return Err(Fault::UnknownOpcode { pc, opcode }),
+        }
+        self.pc = next;
+        Ok(())
+    }
+
+    /// The first thing that differs between `cpu` and the reference, in
+    /// words
+    pub fn compare(&self, cpu: &Cpu) -> Option<String> {
+        let differs = |what: String, core: String, reference: String| {
+            Some(format!(
+                "{} is {} in the core and {} in the reference",
+                what, core, reference
+            ))
+        };
+        if let Some(r) = (0..16).find(|&r| cpu.registers[r] != self.v[r]) {
+            let (core, reference) = (cpu.registers[r], self.v[r]);
+            return differs(
+                format!("V{:X}", r),
+                format!("{:02X}", core),
+                format!("{:02X}", reference),
+            );
+        }
+        if cpu.register_i != self.i {
+            return differs(
+                "I".into(),
+                format!("{:03X}", cpu.register_i),
+                format!("{:03X}", self.i),
+            );
+        }
+        if cpu.position_in_memory != self.pc {
+            return differs(
+                "PC".into(),
+                format!("{:03X}", cpu.position_in_memory),
+                format!("{:03X}", self.pc),
+            );
+        }
+        if cpu.stack[..cpu.stack_pointer] != self.stack[..] {
+            return differs(
+                "the stack".into(),
+                format!("{:03X?}", &cpu.stack[..cpu.stack_pointer]),
+                format!("{:03X?}", self.stack),
+            );
+        }
+        if let Some(addr) = (0..self.memory.len()).find(|&a| cpu.memory[a] != self.memory[a]) {
+            return differs(
+                format!("memory at {:03X}", addr),
+                format!("{:02X}", cpu.memory[addr]),
+                format!("{:02X}", self.memory[addr]),
+            );
+        }
+        let pixels = (0..HEIGHT).flat_map(|y| (0..WIDTH).map(move |x| (x, y)));
+        for (x, y) in pixels {
+            let on = |on: bool| if on { "on" } else { "off" }.to_string();
+            if cpu.display.pixel(y, x) != self.screen[y][x] {
+                return differs(
+                    format!("pixel {},{}", x, y),
+                    on(cpu.display.pixel(y, x)),
+                    on(self.screen[y][x]),
+                );
+            }
+        }
+        let rest = [
+            ("DT", cpu.delay_timer.to_string(), self.dt.to_string()),
+            ("ST", cpu.sound_timer.to_string(), self.st.to_string()),
+            ("halted", cpu.halted.to_string(), self.halted.to_string()),
+            (
+                "the instruction count",
+                cpu.cycles.to_string(),
+                self.cycles.to_string(),
+            ),
+        ];
+        let (what, core, reference) = rest.into_iter().find(|(_, core, r)| core != r)?;
+        differs(what.into(), core, reference)
+    }
+}
+
+/// Runs the frame that took the core from `before` to `after` (ending in
+/// `result`) on the reference too, and describes how they came out
+/// differently, if they did. The frame is then replayed an instruction at
+/// a time to point at the instruction where they parted ways.
+pub fn check_frame(before: &Cpu, after: &Cpu, result: Result<(), Fault>) -> Result<(), String> {
+    let mut reference = Reference::new(before);
+    let expected = reference.run_frame();
+    let difference = if result != expected {
+        let describe = |result: Result<(), Fault>| match result {
+            Ok(()) => "ran the frame".to_string(),
+            Err(fault) => format!("stopped with \"{}\"", fault),
+        };
+        format!(
+            "the core {} and the reference {}",
+            describe(result),
+            describe(expected)
+        )
+    } else {
+        match reference.compare(after) {
+            Some(difference) => difference,
+            None => return Ok(()),
+        }
+    };
+
+    let mut core = before.clone();
+    let mut reference = Reference::new(before);
+    core.executed = None;
+    for _ in 0..core.speed {
+        if !core.can_run() {
+            break;
+        }
+        let (pc, opcode) = (core.position_in_memory, core.read_opcode());
+        let (result, expected) = (core.step(), reference.step());
+        let parted = if result != expected {
+            Some(format!(
+                "the core returned {:?} and the reference {:?}",
+                result, expected
+            ))
+        } else {
+            reference.compare(&core)
+        };
+        if let Some(parted) = parted {
+            return Err(format!(
+                "After the instruction at {:03X} ({:04X}) {}",
+                pc, opcode, parted
+            ));
+        }
+        if result.is_err() {
+            break;
+        }
+    }
+    Err(format!(
+        "Over the frame {}, though stepping an instruction at a time agrees, so the difference \
+         is in how the frame was run",
+        difference
+    ))
+}
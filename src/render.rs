@@ -0,0 +1,463 @@
+use crate::Display;
+use crate::font;
+use crate::palette::Palette;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use display_info::DisplayInfo;
+use minifb::{Window, WindowOptions};
+
+/// Opens the emulator window, either `width` x `height` or as a borderless
+/// window covering the primary monitor
+pub fn open_window(fullscreen: bool, width: usize, height: usize) -> Result<Window> {
+    let mut window = if fullscreen {
+        let displays = DisplayInfo::all().context("Couldn't query monitors")?;
+        let monitor = displays
+            .iter()
+            .find(|d| d.is_primary)
+            .or(displays.first())
+            .context("No monitor found for fullscreen")?;
+        let options = WindowOptions {
+            borderless: true,
+            title: false,
+            topmost: true,
+            ..WindowOptions::default()
+        };
+        let mut window = Window::new(
+            "CHIP8",
+            monitor.width as usize,
+            monitor.height as usize,
+            options,
+        )
+        .context("Couldn't create window")?;
+        window.set_position(monitor.x as isize, monitor.y as isize);
+        window
+    } else {
+        let options = WindowOptions {
+            resize: true,
+            ..WindowOptions::default()
+        };
+        Window::new("CHIP8", width, height, options).context("Couldn't create window")?
+    };
+
+    // frames are paced by the emulator
+    window.set_target_fps(0);
+
+    Ok(window)
+}
+
+/// Clockwise rotation of the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Rotation {
+    #[value(name = "0")]
+    None,
+    #[value(name = "90")]
+    Cw90,
+    #[value(name = "180")]
+    Cw180,
+    #[value(name = "270")]
+    Cw270,
+}
+
+impl Rotation {
+    pub fn quarter_turns(self) -> usize {
+        self as usize
+    }
+
+    /// Size of the rotated screen in emulated pixels
+    fn size(self) -> (usize, usize) {
+        match self {
+            Rotation::None | Rotation::Cw180 => (64, 32),
+            Rotation::Cw90 | Rotation::Cw270 => (32, 64),
+        }
+    }
+
+    /// Display coordinates of the pixel shown at `row`, `col` of the rotated
+    /// screen
+    fn source(self, row: usize, col: usize) -> (usize, usize) {
+        match self {
+            Rotation::None => (row, col),
+            Rotation::Cw90 => (31 - col, row),
+            Rotation::Cw180 => (31 - row, 63 - col),
+            Rotation::Cw270 => (col, 63 - row),
+        }
+    }
+}
+
+/// Where the emulated screen sits inside a window of a given size: the
+/// largest integer scale that leaves at least `padding` pixels of border,
+/// centered
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    scale: usize,
+    left: usize,
+    top: usize,
+    /// Size of the screen in window pixels
+    width: usize,
+    height: usize,
+}
+
+impl Layout {
+    fn new(width: usize, height: usize, padding: usize, rotation: Rotation) -> Self {
+        let (cols, rows) = rotation.size();
+        let scale = (width.saturating_sub(2 * padding) / cols)
+            .min(height.saturating_sub(2 * padding) / rows)
+            .max(1);
+        Self {
+            scale,
+            left: width.saturating_sub(cols * scale) / 2,
+            top: height.saturating_sub(rows * scale) / 2,
+            width: cols * scale,
+            height: rows * scale,
+        }
+    }
+}
+
+/// Keeps pixels glowing for a few frames after they are turned off, like the
+/// phosphor of a CRT, which hides most of the CHIP8 sprite flicker
+#[derive(Debug)]
+struct Phosphor {
+    frames: u8,
+    levels: [[u8; 64]; 32],
+    /// The display as of the last update
+    rows: [u64; 32],
+}
+
+impl Phosphor {
+    /// Pixels fade out over `frames` frames, 0 turns them off instantly
+    fn new(frames: u8) -> Self {
+        Self {
+            frames,
+            levels: [[0; 64]; 32],
+            rows: [0; 32],
+        }
+    }
+
+    /// Catches up with the display, returning which rows look different now
+    fn update(&mut self, display: &Display) -> [bool; 32] {
+        let mut dirty = [false; 32];
+        for (row, levels) in self.levels.iter_mut().enumerate() {
+            dirty[row] = display.rows[row] != self.rows[row];
+            for (col, level) in levels.iter_mut().enumerate() {
+                let old_level = *level;
+                *level = if display.pixel(row, col) {
+                    self.frames
+                } else {
+                    level.saturating_sub(1)
+                };
+                dirty[row] |= *level != old_level;
+            }
+        }
+        self.rows = display.rows;
+        dirty
+    }
+
+    fn color(&self, display: &Display, colors: &Palette, row: usize, col: usize) -> u32 {
+        if display.pixel(row, col) {
+            colors.fg()
+        } else if self.levels[row][col] > 0 {
+            let brightness = self.levels[row][col] as u32 * 256 / self.frames as u32;
+            blend(colors.bg(), colors.fg(), brightness)
+        } else {
+            colors.bg()
+        }
+    }
+}
+
+/// Mixes `from` and `to` channel by channel, `amount` is in 1/256ths
+fn blend(from: u32, to: u32, amount: u32) -> u32 {
+    [16, 8, 0].iter().fold(0, |color, shift| {
+        let a = (from >> shift) & 0xFF;
+        let b = (to >> shift) & 0xFF;
+        let c = (a * (256 - amount) + b * amount) / 256;
+        color | c << shift
+    })
+}
+
+/// Brightness in 1/256ths that darkens every other line and the corners of
+/// the screen like an old monitor. `row` and `col` are relative to the
+/// screen area.
+fn crt_shade(row: usize, col: usize, layout: Layout) -> u32 {
+    let dx = col as f32 / layout.width as f32 - 0.5;
+    let dy = row as f32 / layout.height as f32 - 0.5;
+    let mut brightness = 1.0 - 0.6 * (dx * dx + dy * dy);
+    if row % 2 == 1 {
+        brightness *= 0.7;
+    }
+    (brightness * 256.0) as u32
+}
+
+/// Writes `text` in the border below the screen, or over the bottom of the
+/// screen when the border is too thin
+fn draw_osd(
+    buffer: &mut [u32],
+    width: usize,
+    height: usize,
+    layout: Layout,
+    color: u32,
+    text: &str,
+) {
+    let scale = (height.saturating_sub(layout.height) / 2 / 12).max(1);
+    let text_height = font::HEIGHT * scale;
+    let below = layout.top + layout.height;
+    let y = if height.saturating_sub(below) >= text_height + 2 * scale {
+        below + (height - below - text_height) / 2
+    } else {
+        below.saturating_sub(text_height + 2 * scale)
+    };
+    font::draw_text(
+        buffer,
+        width,
+        (layout.left + 2 * scale, y),
+        scale,
+        color,
+        text,
+    );
+}
+
+/// Scale of the debug overlay text in the border above the screen, and
+/// whether it fits there
+fn debug_scale(layout: Layout, lines: usize) -> (usize, bool) {
+    let line_height = font::HEIGHT + 1;
+    let scale = (layout.top / (lines * line_height + 1)).clamp(1, 3);
+    (scale, lines * line_height * scale <= layout.top)
+}
+
+fn draw_debug(buffer: &mut [u32], width: usize, layout: Layout, color: u32, lines: &[String]) {
+    let (scale, _) = debug_scale(layout, lines.len());
+    let line_height = (font::HEIGHT + 1) * scale;
+    let top = layout.top.saturating_sub(lines.len() * line_height) / 2;
+    for (i, line) in lines.iter().enumerate() {
+        let y = top + i * line_height;
+        font::draw_text(
+            buffer,
+            width,
+            (layout.left + 2 * scale, y),
+            scale,
+            color,
+            line,
+        );
+    }
+}
+
+/// Grid lines are only drawn when pixels are at least this big, below that
+/// they would cover most of the screen
+const MIN_GRID_SCALE: usize = 4;
+
+/// Per window pixel data that only depends on the layout, so that drawing
+/// doesn't have to divide by the scale or rotate for every pixel
+struct Lookup {
+    /// Visible part of the screen, tiny windows can cut it off
+    width: usize,
+    height: usize,
+    /// `row * 64 + col` of the display pixel shown
+    source: Vec<u16>,
+    /// Whether a grid line goes through
+    grid: Vec<bool>,
+    /// See [`crt_shade`]
+    shade: Vec<u32>,
+}
+
+impl Lookup {
+    fn new(layout: Layout, rotation: Rotation, width: usize, height: usize) -> Self {
+        let visible_width = layout.width.min(width - layout.left);
+        let visible_height = layout.height.min(height - layout.top);
+        let scale = layout.scale;
+
+        let mut lookup = Self {
+            width: visible_width,
+            height: visible_height,
+            source: Vec::with_capacity(visible_width * visible_height),
+            grid: Vec::with_capacity(visible_width * visible_height),
+            shade: Vec::with_capacity(visible_width * visible_height),
+        };
+        for row in 0..visible_height {
+            for col in 0..visible_width {
+                let (pixel_row, pixel_col) = rotation.source(row / scale, col / scale);
+                lookup.source.push((pixel_row * 64 + pixel_col) as u16);
+                lookup
+                    .grid
+                    .push(scale >= MIN_GRID_SCALE && (row % scale == 0 || col % scale == 0));
+                lookup.shade.push(crt_shade(row, col, layout));
+            }
+        }
+        lookup
+    }
+}
+
+/// Everything besides the display that the window contents depend on. The
+/// whole window is redrawn when any of it changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DrawState {
+    width: usize,
+    height: usize,
+    pixels: [u32; 4],
+    border: u32,
+    crt: bool,
+    grid: bool,
+    rotation: Rotation,
+    osd: Option<String>,
+}
+
+pub struct Renderer {
+    pub crt: bool,
+    pub grid: bool,
+    pub rotation: Rotation,
+    /// Lines of the debug overlay shown above the screen
+    pub debug: Option<Vec<String>>,
+    /// Size of an emulated pixel in a window of the default size
+    pub scale: usize,
+    /// Minimum border around the screen
+    pub padding: usize,
+    phosphor: Phosphor,
+    last: Option<DrawState>,
+    lookup: Option<Lookup>,
+    drawn_debug: Option<Vec<String>>,
+}
+
+impl Renderer {
+    /// Pixels fade out over `phosphor_frames` frames, see [`Phosphor`]
+    pub fn new(phosphor_frames: u8) -> Self {
+        Self {
+            crt: false,
+            grid: false,
+            rotation: Rotation::None,
+            debug: None,
+            scale: 10,
+            padding: 30,
+            phosphor: Phosphor::new(phosphor_frames),
+            last: None,
+            lookup: None,
+            drawn_debug: None,
+        }
+    }
+
+    /// The window size that fits the screen at `scale`
+    pub fn window_size(&self) -> (usize, usize) {
+        let (cols, rows) = self.rotation.size();
+        (
+            self.padding + cols * self.scale + self.padding,
+            self.padding + rows * self.scale + self.padding,
+        )
+    }
+
+    /// Makes the next `draw` redraw the whole window
+    pub fn redraw(&mut self) {
+        self.last = None;
+    }
+
+    /// Updates a `width` x `height` window buffer, only touching the rows of
+    /// the display that changed since the last call unless the size or any
+    /// of the settings changed. Returns whether anything was drawn.
+    pub fn draw(
+        &mut self,
+        buffer: &mut [u32],
+        width: usize,
+        height: usize,
+        display: &Display,
+        colors: &Palette,
+        osd: Option<&str>,
+    ) -> bool {
+        let dirty = self.phosphor.update(display);
+        let state = DrawState {
+            width,
+            height,
+            pixels: colors.pixels,
+            border: colors.border,
+            crt: self.crt,
+            grid: self.grid,
+            rotation: self.rotation,
+            osd: osd.map(String::from),
+        };
+        let layout = Layout::new(width, height, self.padding, self.rotation);
+        let mut full = self.last.as_ref() != Some(&state);
+        self.last = Some(state);
+
+        let debug_changed = full || self.drawn_debug != self.debug;
+        if debug_changed {
+            // text that doesn't fit the border covers the screen, which then
+            // has to be redrawn in full to get rid of it
+            let covers_screen = |lines: &Option<Vec<String>>| {
+                lines
+                    .as_ref()
+                    .is_some_and(|l| !debug_scale(layout, l.len()).1)
+            };
+            full |= covers_screen(&self.debug) || covers_screen(&self.drawn_debug);
+        }
+
+        if !full && !debug_changed && !dirty.contains(&true) {
+            return false;
+        }
+
+        let buffer = &mut buffer[..width * height];
+        if debug_changed && !full {
+            buffer[..layout.top * width].fill(colors.border);
+        }
+        if full {
+            buffer.fill(colors.border);
+            self.lookup = Some(Lookup::new(layout, self.rotation, width, height));
+            if let Some(text) = osd {
+                draw_osd(buffer, width, height, layout, colors.fg(), text);
+            }
+        }
+        let Some(lookup) = &self.lookup else {
+            return true;
+        };
+
+        let mut pixel_colors = [0; 64 * 32];
+        for (row, colors_row) in pixel_colors.chunks_mut(64).enumerate() {
+            if full || dirty[row] {
+                for (col, color) in colors_row.iter_mut().enumerate() {
+                    *color = self.phosphor.color(display, colors, row, col);
+                }
+            }
+        }
+
+        // without rotation a window row shows a single display row, so clean
+        // rows can be skipped whole, and without per-row effects the rows of
+        // an emulated pixel are all the same
+        let rows_align = matches!(self.rotation, Rotation::None | Rotation::Cw180);
+        let copy_rows = rows_align && !self.crt && !self.grid;
+
+        for row in 0..lookup.height {
+            let sources = &lookup.source[row * lookup.width..(row + 1) * lookup.width];
+            let Some(&first) = sources.first() else {
+                break;
+            };
+            if !full && rows_align && !dirty[first as usize / 64] {
+                continue;
+            }
+
+            let start = (layout.top + row) * width + layout.left;
+            if copy_rows && row % layout.scale != 0 {
+                buffer.copy_within(start - width..start - width + lookup.width, start);
+                continue;
+            }
+
+            let line = &mut buffer[start..start + lookup.width];
+            for (i, (p, &source)) in line.iter_mut().zip(sources).enumerate() {
+                if !full && !dirty[source as usize / 64] {
+                    continue;
+                }
+
+                let mut color = pixel_colors[source as usize];
+                let i = row * lookup.width + i;
+                if self.grid && lookup.grid[i] {
+                    color = blend(color, colors.border, 160);
+                }
+                if self.crt {
+                    color = blend(0, color, lookup.shade[i]);
+                }
+                *p = color;
+            }
+        }
+
+        if debug_changed {
+            if let Some(lines) = &self.debug {
+                draw_debug(buffer, width, layout, colors.fg(), lines);
+            }
+            self.drawn_debug = self.debug.clone();
+        }
+
+        true
+    }
+}
@@ -0,0 +1,36 @@
+use crate::Cpu;
+use std::collections::VecDeque;
+use std::mem;
+
+/// Machine states of the last frames for rewinding, bounded by a memory
+/// budget. Once full, the oldest state is dropped for every new one.
+pub struct Rewind {
+    states: VecDeque<Cpu>,
+    capacity: usize,
+}
+
+impl Rewind {
+    pub fn new(budget_bytes: usize) -> Self {
+        let capacity = budget_bytes / mem::size_of::<Cpu>();
+        Self {
+            states: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Saves the state at the end of a frame
+    pub fn push(&mut self, cpu: &Cpu) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.states.len() == self.capacity {
+            self.states.pop_front();
+        }
+        self.states.push_back(cpu.clone());
+    }
+
+    /// The state one frame back, if there is one
+    pub fn pop(&mut self) -> Option<Cpu> {
+        self.states.pop_back()
+    }
+}
@@ -0,0 +1,452 @@
+use crate::Cpu;
+use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8] = b"chip8-state";
+/// Bumped whenever the layout below changes, so that old states are
+/// refused instead of loaded wrong
+const VERSION: u8 = 1;
+
+/// Number of save slots, selected with Shift and a digit
+pub const SLOTS: u8 = 10;
+
+/// How states are written. Loading accepts either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StateFormat {
+    /// Compact binary
+    Binary,
+    /// JSON to read and edit by hand, with the screen drawn with `#` and
+    /// `.` and the memory as rows of hex bytes
+    Json,
+}
+
+/// Where the state of `rom` in `slot` is saved next to it: `game.state` for
+/// slot 0 and `game.state1` to `game.state9` for the others. States of
+/// downloaded ROMs go in the current directory, those of ROMs read from
+/// standard input in `stdin.state`.
+pub fn path(rom: &Path, slot: u8) -> PathBuf {
+    let rom = if crate::is_url(rom) {
+        Path::new(rom.file_name().unwrap_or_default())
+    } else if crate::is_stdio(rom) {
+        Path::new("stdin")
+    } else {
+        rom
+    };
+    match slot {
+        0 => rom.with_extension("state"),
+        n => rom.with_extension(format!("state{}", n)),
+    }
+}
+
+/// Where the session of the ROM with the given hash is kept between runs
+/// by `--auto-save` and `--resume`
+pub fn resume_path(rom_hash: &str) -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| {
+        dir.join("chip8")
+            .join("resume")
+            .join(format!("{}.state", rom_hash))
+    })
+}
+
+/// Writes everything the program can observe: registers, stack, timers,
+/// memory, screen and the RNG, so that a loaded state plays on exactly as
+/// the saved one would have. Settings like speed and quirks aren't saved.
+pub fn save(path: &Path, cpu: &Cpu, rom_hash: &str, format: StateFormat) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Couldn't create directory `{}`", dir.display()))?;
+    }
+    let state = State::of(cpu, rom_hash);
+    let bytes = match format {
+        StateFormat::Binary => state.to_bytes(),
+        StateFormat::Json => state.to_json().into_bytes(),
+    };
+    fs::write(path, bytes).with_context(|| format!("Couldn't write state `{}`", path.display()))
+}
+
+/// Restores a state written by `save` for the same ROM
+pub fn load(path: &Path, cpu: &mut Cpu, rom_hash: &str) -> Result<()> {
+    read(path)?
+        .restore(cpu, rom_hash)
+        .with_context(|| format!("Invalid state `{}`", path.display()))
+}
+
+/// A state file of either format as pretty-printed JSON
+pub fn dump(path: &Path) -> Result<String> {
+    Ok(read(path)?.to_json())
+}
+
+fn read(path: &Path) -> Result<State> {
+    let bytes =
+        fs::read(path).with_context(|| format!("Couldn't read state `{}`", path.display()))?;
+    State::parse(&bytes).with_context(|| format!("Invalid state `{}`", path.display()))
+}
+
+fn check_version(version: u8) -> Result<()> {
+    if version != VERSION {
+        return Err(anyhow!(
+            "saved by version {} of the format, this is version {}",
+            version,
+            VERSION
+        ));
+    }
+    Ok(())
+}
+
+/// The machine as saved, shared by both formats
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct State {
+    version: u8,
+    rom_hash: String,
+    registers: [u8; 16],
+    i: u16,
+    pc: u16,
+    sp: u8,
+    stack: [u16; 16],
+    dt: u8,
+    st: u8,
+    rng: u64,
+    halted: bool,
+    cycles: u64,
+    #[serde(with = "screen")]
+    display: [u64; 32],
+    #[serde(with = "hex_rows")]
+    memory: Vec<u8>,
+}
+
+impl State {
+    fn of(cpu: &Cpu, rom_hash: &str) -> Self {
+        Self {
+            version: VERSION,
+            rom_hash: rom_hash.to_string(),
+            registers: cpu.registers,
+            i: cpu.register_i,
+            pc: cpu.position_in_memory as u16,
+            sp: cpu.stack_pointer as u8,
+            stack: cpu.stack,
+            dt: cpu.delay_timer,
+            st: cpu.sound_timer,
+            rng: cpu.rng.state,
+            halted: cpu.halted,
+            cycles: cpu.cycles as u64,
+            display: cpu.display.rows,
+            memory: cpu.memory.to_vec(),
+        }
+    }
+
+    /// Loads the state into `cpu` if it was saved from the same ROM and
+    /// makes sense
+    fn restore(&self, cpu: &mut Cpu, rom_hash: &str) -> Result<()> {
+        if self.rom_hash != rom_hash {
+            return Err(anyhow!("saved from a different ROM"));
+        }
+        if self.sp as usize > self.stack.len() {
+            return Err(anyhow!("stack pointer {} is out of range", self.sp));
+        }
+        let memory = self
+            .memory
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow!("memory is {} bytes instead of 4096", self.memory.len()))?;
+        // an instruction is two bytes, both of which have to be in memory
+        let in_memory = |addr: u16| (addr as usize) < self.memory.len() - 1;
+        if !in_memory(self.pc) {
+            return Err(anyhow!("PC {:04X} is outside of memory", self.pc));
+        }
+        if let Some(&addr) = self.stack[..self.sp as usize]
+            .iter()
+            .find(|&&addr| !in_memory(addr))
+        {
+            return Err(anyhow!("return address {:04X} is outside of memory", addr));
+        }
+
+        cpu.registers = self.registers;
+        cpu.register_i = self.i;
+        cpu.position_in_memory = self.pc as usize;
+        cpu.stack_pointer = self.sp as usize;
+        cpu.stack = self.stack;
+        cpu.delay_timer = self.dt;
+        cpu.sound_timer = self.st;
+        cpu.rng.state = self.rng;
+        cpu.halted = self.halted;
+        cpu.cycles = self.cycles as usize;
+        cpu.display.rows = self.display;
+        cpu.memory = memory;
+        Ok(())
+    }
+
+    /// Either format, told apart by the magic of the binary one
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        if let Some(rest) = bytes.strip_prefix(MAGIC) {
+            return Self::from_bytes(rest);
+        }
+        let state: Self = serde_json::from_slice(bytes).context("not a savestate")?;
+        check_version(state.version)?;
+        Ok(state)
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("states serialize")
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(self.version);
+        out.extend_from_slice(self.rom_hash.as_bytes());
+        out.push(b'\n');
+
+        out.extend_from_slice(&self.registers);
+        out.extend_from_slice(&self.i.to_be_bytes());
+        out.extend_from_slice(&self.pc.to_be_bytes());
+        out.push(self.sp);
+        for addr in self.stack {
+            out.extend_from_slice(&addr.to_be_bytes());
+        }
+        out.push(self.dt);
+        out.push(self.st);
+        out.extend_from_slice(&self.rng.to_be_bytes());
+        out.push(self.halted as u8);
+        out.extend_from_slice(&self.cycles.to_be_bytes());
+        for row in self.display {
+            out.extend_from_slice(&row.to_be_bytes());
+        }
+        out.extend_from_slice(&self.memory);
+        out
+    }
+
+    /// The binary format after the magic
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+        let version = reader.u8()?;
+        // later versions may lay out the rest differently
+        check_version(version)?;
+        let rom_hash = String::from_utf8(reader.line()?.to_vec())
+            .map_err(|_| anyhow!("the ROM hash isn't text"))?;
+
+        let registers = reader.take()?;
+        let i = reader.u16()?;
+        let pc = reader.u16()?;
+        let sp = reader.u8()?;
+        let mut stack = [0; 16];
+        for addr in &mut stack {
+            *addr = reader.u16()?;
+        }
+        let dt = reader.u8()?;
+        let st = reader.u8()?;
+        let rng = reader.u64()?;
+        let halted = reader.u8()? != 0;
+        let cycles = reader.u64()?;
+        let mut display = [0; 32];
+        for row in &mut display {
+            *row = reader.u64()?;
+        }
+        let memory = reader.take::<0x1000>()?.to_vec();
+        if !reader.bytes.is_empty() {
+            return Err(anyhow!("unexpected data after the state"));
+        }
+
+        Ok(Self {
+            version,
+            rom_hash,
+            registers,
+            i,
+            pc,
+            sp,
+            stack,
+            dt,
+            st,
+            rng,
+            halted,
+            cycles,
+            display,
+            memory,
+        })
+    }
+}
+
+/// The bytes of a binary state, read front to back
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let Some((taken, rest)) = self.bytes.split_first_chunk() else {
+            return Err(anyhow!("the file is cut short"));
+        };
+        self.bytes = rest;
+        Ok(*taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take()?))
+    }
+
+    /// The bytes up to the next newline, which is skipped
+    fn line(&mut self) -> Result<&'a [u8]> {
+        let end = self
+            .bytes
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| anyhow!("the file is cut short"))?;
+        let line = &self.bytes[..end];
+        self.bytes = &self.bytes[end + 1..];
+        Ok(line)
+    }
+}
+
+/// The screen in JSON, a string per row with `#` for lit pixels and `.`
+/// for dark ones
+mod screen {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(rows: &[u64; 32], serializer: S) -> Result<S::Ok, S::Error> {
+        let lines: Vec<String> = rows
+            .iter()
+            .map(|row| {
+                (0..64)
+                    .map(|col| if row << col >> 63 == 1 { '#' } else { '.' })
+                    .collect()
+            })
+            .collect();
+        lines.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u64; 32], D::Error> {
+        let lines = Vec::<String>::deserialize(deserializer)?;
+        if lines.len() != 32 {
+            return Err(D::Error::custom(format!(
+                "the screen has {} rows instead of 32",
+                lines.len()
+            )));
+        }
+        let mut rows = [0; 32];
+        for (row, line) in rows.iter_mut().zip(&lines) {
+            if line.chars().count() != 64 {
+                return Err(D::Error::custom(format!(
+                    "screen row `{}` isn't 64 pixels wide",
+                    line
+                )));
+            }
+            for c in line.chars() {
+                let lit = match c {
+                    '#' => 1,
+                    '.' => 0,
+                    _ => return Err(D::Error::custom(format!("`{}` isn't `#` or `.`", c))),
+                };
+                *row = *row << 1 | lit;
+            }
+        }
+        Ok(rows)
+    }
+}
+
+/// Memory in JSON, a string of 16 hex bytes per row like `"00 E0 A2 2A ..."`
+mod hex_rows {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let lines: Vec<String> = bytes
+            .chunks(16)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        lines.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let lines = Vec::<String>::deserialize(deserializer)?;
+        lines
+            .iter()
+            .flat_map(|line| line.split_whitespace())
+            .map(|byte| {
+                u8::from_str_radix(byte, 16)
+                    .map_err(|_| D::Error::custom(format!("`{}` isn't a hex byte", byte)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu() -> Cpu {
+        let mut cpu = Cpu::new(&[0x60, 0x05, 0xC1, 0xFF], 42);
+        cpu.registers[3] = 7;
+        cpu.stack[0] = 0x234;
+        cpu.stack_pointer = 1;
+        cpu.delay_timer = 30;
+        cpu.display.rows[5] = 0xF0;
+        cpu.rng.state = 0x1234_5678;
+        cpu
+    }
+
+    #[test]
+    fn states_round_trip() {
+        let state = State::of(&cpu(), "abc");
+        let bytes = state.to_bytes();
+        assert_eq!(State::parse(&bytes).unwrap(), state);
+        assert!(State::parse(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut loaded = Cpu::new(&[], 0);
+        state.restore(&mut loaded, "abc").unwrap();
+        assert_eq!(State::of(&loaded, "abc"), state);
+        assert!(state.restore(&mut loaded, "def").is_err());
+    }
+
+    #[test]
+    fn rejects_addresses_outside_of_memory() {
+        let mut loaded = Cpu::new(&[], 0);
+        let mut state = State::of(&cpu(), "abc");
+        state.pc = 0xFFF;
+        assert!(state.restore(&mut loaded, "abc").is_err());
+
+        let mut state = State::of(&cpu(), "abc");
+        state.stack[0] = 0x1000;
+        assert!(state.restore(&mut loaded, "abc").is_err());
+        // only the entries below the stack pointer are return addresses
+        state.stack[0] = 0x234;
+        state.stack[1] = 0xFFFF;
+        state.restore(&mut loaded, "abc").unwrap();
+    }
+
+    #[test]
+    fn json_states_round_trip() {
+        let state = State::of(&cpu(), "abc");
+        let json = state.to_json();
+        assert!(json.contains(&format!("\"{}####....\"", ".".repeat(56))));
+        assert!(json.contains("\"60 05 C1 FF 00"));
+        assert_eq!(State::parse(json.as_bytes()).unwrap(), state);
+    }
+
+    #[test]
+    fn slots_have_their_own_files() {
+        let rom = Path::new("roms/pong.ch8");
+        assert_eq!(path(rom, 0), Path::new("roms/pong.state"));
+        assert_eq!(path(rom, 7), Path::new("roms/pong.state7"));
+        let url = Path::new("https://example.com/roms/pong.ch8");
+        assert_eq!(path(url, 0), Path::new("pong.state"));
+        assert_eq!(path(Path::new("-"), 2), Path::new("stdin.state2"));
+    }
+}
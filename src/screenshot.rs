@@ -0,0 +1,88 @@
+use crate::Display;
+use crate::capture;
+use crate::palette::Palette;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Default screenshot name: the ROM name and the frame, in the current
+/// directory
+pub fn default_path(rom: &Path, frame: u64) -> PathBuf {
+    let stem = rom.file_stem().unwrap_or_default().to_string_lossy();
+    PathBuf::from(format!("{}-{}.png", stem, frame))
+}
+
+/// The display as an RGB PNG with every emulated pixel `scale` x `scale`
+/// image pixels
+pub fn encode_png(display: &Display, colors: &Palette, scale: usize) -> Vec<u8> {
+    let width = 64 * scale;
+    let height = 32 * scale;
+
+    let data = capture::rgb_pixels(display, colors, scale);
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .expect("encoding to memory doesn't fail");
+    png
+}
+
+/// Writes the display as a PNG, see `encode_png`
+pub fn save_png(path: &Path, display: &Display, colors: &Palette, scale: usize) -> Result<()> {
+    fs::write(path, encode_png(display, colors, scale))
+        .with_context(|| format!("Couldn't write screenshot `{}`", path.display()))
+}
+
+/// The display as text, each line two rows of pixels drawn with half
+/// blocks, so that it fits in a terminal and a bug report
+pub fn to_text(display: &Display) -> String {
+    let mut text = String::new();
+    for row in (0..32).step_by(2) {
+        for col in 0..64 {
+            text.push(
+                match (display.pixel(row, col), display.pixel(row + 1, col)) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                },
+            );
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Writes the display as text to `path`, or prints it for `-`
+pub fn save_text(path: &Path, display: &Display) -> Result<()> {
+    if crate::is_stdio(path) {
+        print!("{}", to_text(display));
+        return Ok(());
+    }
+    fs::write(path, to_text(display))
+        .with_context(|| format!("Couldn't write `{}`", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_has_two_rows_per_line() {
+        let mut display = Display::new();
+        display.rows[0] = 0xC000_0000_0000_0001;
+        display.rows[1] = 0x4000_0000_0000_0000;
+        display.rows[31] = 1;
+        let text = to_text(&display);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 16);
+        assert!(lines.iter().all(|line| line.chars().count() == 64));
+        assert!(lines[0].starts_with("▀█ "));
+        assert!(lines[0].ends_with('▀'));
+        assert!(lines[15].ends_with('▄'));
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::{Context, Result, anyhow};
+use cpu::Cpu;
+use rhai::{AST, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope};
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// A Rhai script run alongside the program. It can define
+/// `fn on_frame(frame)`, called before every frame, and
+/// `fn on_instruction(pc, opcode)`, called after every instruction; both
+/// keep what they need between calls in `this`, a map that starts empty.
+///
+/// The functions it can call:
+/// - `v(n)`, `set_v(n, value)`: the registers V0 to VF
+/// - `i()`, `set_i(value)`, `pc()`, `set_pc(addr)`, `dt()`, `set_dt(value)`,
+///   `st()`, `set_st(value)`
+/// - `peek(addr)`, `poke(addr, value)`: memory
+/// - `press(key)`, `release(key)`: hold a key down until released
+/// - `pixel(x, y)`: whether a pixel of the screen is lit
+///
+/// Values wrap to the width of what they're written to.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    this: Dynamic,
+    /// The machine while the script runs, swapped with the emulator's
+    machine: Rc<RefCell<Cpu>>,
+    /// Keys the script holds down
+    held: Rc<RefCell<[bool; 16]>>,
+    on_frame: bool,
+    on_instruction: bool,
+}
+
+/// `n` as an index below `len`, or an error naming `what` it should be
+fn index(n: i64, len: usize, what: &str) -> ScriptResult<usize> {
+    usize::try_from(n)
+        .ok()
+        .filter(|&n| n < len)
+        .ok_or_else(|| format!("there's no {} {}", what, n).into())
+}
+
+fn register(engine: &mut Engine, machine: &Rc<RefCell<Cpu>>, held: &Rc<RefCell<[bool; 16]>>) {
+    let m = machine.clone();
+    engine.register_fn("v", move |n: i64| -> ScriptResult<i64> {
+        Ok(m.borrow().registers[index(n, 16, "register")?] as i64)
+    });
+    let m = machine.clone();
+    engine.register_fn("set_v", move |n: i64, value: i64| -> ScriptResult<()> {
+        m.borrow_mut().registers[index(n, 16, "register")?] = value as u8;
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("i", move || m.borrow().register_i as i64);
+    let m = machine.clone();
+    engine.register_fn("set_i", move |value: i64| {
+        m.borrow_mut().register_i = value as u16
+    });
+    let m = machine.clone();
+    engine.register_fn("pc", move || m.borrow().position_in_memory as i64);
+    let m = machine.clone();
+    engine.register_fn("set_pc", move |addr: i64| -> ScriptResult<()> {
+        m.borrow_mut().position_in_memory = index(addr, 0x1000, "address")?;
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("dt", move || m.borrow().delay_timer as i64);
+    let m = machine.clone();
+    engine.register_fn("set_dt", move |value: i64| {
+        m.borrow_mut().delay_timer = value as u8
+    });
+    let m = machine.clone();
+    engine.register_fn("st", move || m.borrow().sound_timer as i64);
+    let m = machine.clone();
+    engine.register_fn("set_st", move |value: i64| {
+        m.borrow_mut().sound_timer = value as u8
+    });
+    let m = machine.clone();
+    engine.register_fn("peek", move |addr: i64| -> ScriptResult<i64> {
+        Ok(m.borrow().memory[index(addr, 0x1000, "address")?] as i64)
+    });
+    let m = machine.clone();
+    engine.register_fn("poke", move |addr: i64, value: i64| -> ScriptResult<()> {
+        // through `poke`, so that the block cache and the JIT see the write
+        m.borrow_mut()
+            .poke(index(addr, 0x1000, "address")?, value as u8);
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("pixel", move |x: i64, y: i64| -> ScriptResult<bool> {
+        let (x, y) = (index(x, 64, "column")?, index(y, 32, "row")?);
+        Ok(m.borrow().display.pixel(y, x))
+    });
+    for (name, down) in [("press", true), ("release", false)] {
+        let (m, h) = (machine.clone(), held.clone());
+        engine.register_fn(name, move |key: i64| -> ScriptResult<()> {
+            let key = index(key, 16, "key")?;
+            h.borrow_mut()[key] = down;
+            m.borrow_mut().keypad[key] = down;
+            Ok(())
+        });
+    }
+}
+
+impl Script {
+    /// Loads the script at `path` and runs its top level against `cpu`,
+    /// which can set up memory before the program starts
+    pub fn load(path: &Path, cpu: &mut Cpu) -> Result<Self> {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read script `{}`", path.display()))?;
+        Self::new(&source, cpu).with_context(|| format!("Script `{}` failed", path.display()))
+    }
+
+    fn new(source: &str, cpu: &mut Cpu) -> Result<Self> {
+        let machine = Rc::new(RefCell::new(Cpu::new(&[], 0)));
+        let held = Rc::new(RefCell::new([false; 16]));
+        let mut engine = Engine::new();
+        register(&mut engine, &machine, &held);
+
+        let ast = engine.compile(source).map_err(|e| anyhow!("{}", e))?;
+        let defines = |name: &str| ast.iter_functions().any(|f| f.name == name);
+        let mut script = Self {
+            on_frame: defines("on_frame"),
+            on_instruction: defines("on_instruction"),
+            engine,
+            ast,
+            this: Dynamic::from_map(Map::new()),
+            machine,
+            held,
+        };
+        script.with_machine(cpu, |script| {
+            script
+                .engine
+                .run_ast_with_scope(&mut Scope::new(), &script.ast)
+        })?;
+        Ok(script)
+    }
+
+    /// Runs `f` with `cpu` as the machine the script functions see
+    fn with_machine(
+        &mut self,
+        cpu: &mut Cpu,
+        f: impl FnOnce(&mut Self) -> ScriptResult<()>,
+    ) -> Result<()> {
+        std::mem::swap(cpu, &mut self.machine.borrow_mut());
+        let result = f(self);
+        std::mem::swap(cpu, &mut self.machine.borrow_mut());
+        result.map_err(|e| anyhow!("{}", e))
+    }
+
+    fn call(&mut self, cpu: &mut Cpu, name: &str, args: impl FuncArgs) -> Result<()> {
+        self.with_machine(cpu, |script| {
+            let options = CallFnOptions::new()
+                .eval_ast(false)
+                .bind_this_ptr(&mut script.this);
+            script
+                .engine
+                .call_fn_with_options::<Dynamic>(
+                    options,
+                    &mut Scope::new(),
+                    &script.ast,
+                    name,
+                    args,
+                )
+                .map(|_| ())
+        })
+        .with_context(|| format!("Script failed in `{}`", name))
+    }
+
+    /// Runs frame number `frame` with `run`, calling the script's callbacks
+    /// around it. With `on_instruction` the frame is interpreted here
+    /// instead, an instruction at a time.
+    pub fn run_frame(
+        &mut self,
+        cpu: &mut Cpu,
+        frame: u64,
+        run: impl FnOnce(&mut Cpu) -> Result<()>,
+    ) -> Result<()> {
+        if self.on_frame {
+            self.call(cpu, "on_frame", (frame as i64,))?;
+        }
+        for (key, &held) in cpu.keypad.iter_mut().zip(self.held.borrow().iter()) {
+            *key |= held;
+        }
+        if !self.on_instruction {
+            return run(cpu);
+        }
+
+        for _ in 0..cpu.speed {
+            if !cpu.can_run() {
+                break;
+            }
+            let pc = cpu.position_in_memory;
+            let opcode = cpu.read_opcode();
+            cpu.step()?;
+            self.call(cpu, "on_instruction", (pc as i64, opcode as i64))?;
+        }
+        cpu.tick_timers();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn callbacks_see_and_change_the_machine() {
+        // LD V0, 05; ADD V0, 01; JP 202
+        let mut cpu = Cpu::new(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02], 0);
+        cpu.speed = 3;
+        let mut script = Script::new(
+            r#"
+            poke(0x300, 0xAB);
+            fn on_frame(frame) {
+                if frame == 1 { press(0xF); set_v(1, 0x1FF); }
+            }
+            fn on_instruction(pc, opcode) {
+                this.adds = (this.adds ?? 0) + if opcode == 0x7001 { 1 } else { 0 };
+                set_v(2, this.adds);
+            }
+            "#,
+            &mut cpu,
+        )
+        .unwrap();
+        assert_eq!(cpu.memory[0x300], 0xAB);
+        assert_eq!(cpu.writes, [(0x300, 0x00)]);
+
+        for frame in 0..2 {
+            script
+                .run_frame(&mut cpu, frame, |_| unreachable!())
+                .unwrap();
+        }
+        assert_eq!(cpu.registers[0], 0x08);
+        assert_eq!(cpu.registers[1], 0xFF);
+        assert_eq!(cpu.registers[2], 3);
+        assert!(cpu.keypad[0xF]);
+
+        let mut script = Script::new("fn on_frame(frame) { v(16) }", &mut cpu).unwrap();
+        let error = script.run_frame(&mut cpu, 0, |_| Ok(())).unwrap_err();
+        assert!(format!("{:#}", error).contains("on_frame"));
+        assert!(Script::new("peek(0x1000)", &mut cpu).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::trace::Executed;
+
+/// Catches programs writing to memory that already ran as code. Each
+/// address is reported once.
+pub struct SmcDetector {
+    ran: Box<[bool; 0x1000]>,
+    reported: Box<[bool; 0x1000]>,
+}
+
+impl Default for SmcDetector {
+    fn default() -> Self {
+        Self {
+            ran: Box::new([false; 0x1000]),
+            reported: Box::new([false; 0x1000]),
+        }
+    }
+}
+
+impl SmcDetector {
+    /// Records the instruction at `pc` with the memory it wrote, returning
+    /// the written addresses that ran as code and weren't reported yet
+    pub fn record(&mut self, pc: usize, writes: &[(usize, u8)]) -> Vec<usize> {
+        for addr in [pc, pc + 1] {
+            if let Some(ran) = self.ran.get_mut(addr) {
+                *ran = true;
+            }
+        }
+        let mut found = Vec::new();
+        for &(addr, _) in writes {
+            if self.ran[addr] && !self.reported[addr] {
+                self.reported[addr] = true;
+                found.push(addr);
+            }
+        }
+        found
+    }
+
+    /// Records the instructions of a frame, warning about code they changed
+    pub fn check(&mut self, executed: &[Executed]) {
+        for e in executed {
+            for addr in self.record(e.pc, &e.writes) {
+                eprintln!("{}", warning(e.pc, addr));
+            }
+        }
+    }
+}
+
+pub fn warning(pc: usize, addr: usize) -> String {
+    format!(
+        "Self-modifying code: {:04X} wrote {:04X}, which already ran as code",
+        pc, addr
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_writes_to_code_once() {
+        let mut smc = SmcDetector::default();
+        assert!(smc.record(0x200, &[(0x300, 0)]).is_empty());
+        assert!(smc.record(0x300, &[]).is_empty());
+        assert_eq!(smc.record(0x202, &[(0x301, 0), (0x302, 0)]), [0x301]);
+        assert!(smc.record(0x202, &[(0x301, 0)]).is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::PROGRAM_START;
+use crate::disasm::{self, Reached};
+use crate::instruction::Instruction;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Sprite data in the program, found by what `DRW` draws
+#[derive(Debug, PartialEq, Eq)]
+struct Sprite {
+    addr: usize,
+    /// 8, or 16 for SCHIP's `DXY0`
+    width: usize,
+    height: usize,
+}
+
+impl Sprite {
+    fn len(&self) -> usize {
+        self.height * self.width / 8
+    }
+
+    fn pixel(&self, program: &[u8], row: usize, col: usize) -> bool {
+        let offset = self.addr - PROGRAM_START + row * self.width / 8 + col / 8;
+        program[offset] << (col % 8) & 0x80 != 0
+    }
+}
+
+/// Sprites the reachable code draws: each `DRW` after an `LD I` in the same
+/// block, taking the tallest draw of every address. Sprites set up through
+/// `ADD I` or `LD F`, or drawn in a subroutine called after `LD I`, aren't
+/// found.
+fn find(program: &[u8]) -> Vec<Sprite> {
+    use Instruction::*;
+
+    let end = PROGRAM_START + program.len();
+    let mut sprites: BTreeMap<usize, Sprite> = BTreeMap::new();
+    let mut i = None;
+    let mut previous = None;
+    for (pc, reached) in disasm::reachable(program) {
+        // I could be anything when entered from elsewhere
+        if reached != Reached::Flow || previous != Some(pc - 2) {
+            i = None;
+        }
+        previous = Some(pc);
+
+        let offset = pc - PROGRAM_START;
+        let opcode = u16::from_be_bytes([program[offset], program[offset + 1]]);
+        match Instruction::decode(opcode) {
+            SetI { addr } => i = Some(addr as usize),
+            Draw { n, .. } => {
+                let Some(addr) = i else { continue };
+                let (width, height) = match n {
+                    0 => (16, 16),
+                    n => (8, n as usize),
+                };
+                let sprite = Sprite {
+                    addr,
+                    width,
+                    height,
+                };
+                if addr < PROGRAM_START || addr + sprite.len() > end {
+                    continue;
+                }
+                let known = sprites.get(&addr).map_or(0, Sprite::len);
+                if sprite.len() > known {
+                    sprites.insert(addr, sprite);
+                }
+            }
+            Jump { .. } | Call { .. } | Return | Unknown(_) => i = None,
+            _ => {}
+        }
+    }
+    sprites.into_values().collect()
+}
+
+/// Writes every sprite the program draws to `dir` as a PNG named after its
+/// address, with each sprite pixel `scale` x `scale` image pixels
+pub fn export(program: &[u8], dir: &Path, scale: usize) -> Result<()> {
+    let sprites = find(program);
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Couldn't create directory `{}`", dir.display()))?;
+
+    for sprite in &sprites {
+        let path = dir.join(format!("sprite_{:04X}.png", sprite.addr));
+        save_png(&path, program, sprite, scale)
+            .with_context(|| format!("Couldn't write `{}`", path.display()))?;
+        println!(
+            "  {:04X}  {:>2}x{:<2}  {}",
+            sprite.addr,
+            sprite.width,
+            sprite.height,
+            path.display()
+        );
+    }
+    println!("{} sprites written to {}", sprites.len(), dir.display());
+    Ok(())
+}
+
+/// The sprite as a grayscale PNG, white for lit pixels
+fn save_png(path: &Path, program: &[u8], sprite: &Sprite, scale: usize) -> Result<()> {
+    let width = sprite.width * scale;
+    let height = sprite.height * scale;
+    let data: Vec<u8> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (y / scale, x / scale)))
+        .map(|(row, col)| sprite.pixel(program, row, col) as u8 * 0xFF)
+        .collect();
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        width as u32,
+        height as u32,
+    );
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_drawn_sprites() {
+        let program = [
+            0xA2, 0x10, // 200: LD I, 210
+            0xD0, 0x12, // 202: DRW V0, V1, 2
+            0xD0, 0x13, // 204: DRW V0, V1, 3
+            0xA2, 0x13, // 206: LD I, 213
+            0x22, 0x0E, // 208: CALL 20E
+            0xD0, 0x11, // 20A: DRW V0, V1, 1 (I changed by the call)
+            0x12, 0x0C, // 20C: JP 20C
+            0x00, 0xEE, // 20E: RET
+            0xF0, 0x90, 0xF0, 0x18,
+        ];
+        let sprites = find(&program);
+        assert_eq!(
+            sprites,
+            [Sprite {
+                addr: 0x210,
+                width: 8,
+                height: 3
+            }]
+        );
+        assert!(sprites[0].pixel(&program, 1, 0));
+        assert!(!sprites[0].pixel(&program, 1, 1));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::Display;
+use crate::palette::Palette;
+use crate::screenshot;
+use anyhow::{Context, Result};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// The page at `/`, the stream scaled up to the window with sharp pixels
+const PAGE: &str = r#"<!DOCTYPE html>
+<title>CHIP8</title>
+<style>
+  body { margin: 0; background: #000; }
+  img { width: 100vw; height: 100vh; object-fit: contain; image-rendering: pixelated; }
+</style>
+<img src="/stream">
+"#;
+
+/// What the clients show, replaced by every frame that looks different.
+/// Clients copy it and let go of the lock before encoding, so that
+/// publishing a frame doesn't wait for them.
+#[derive(Clone)]
+struct Screen {
+    display: Display,
+    colors: Palette,
+    /// Counts changes, so that clients know when there's a new one
+    version: u64,
+}
+
+struct Shared {
+    screen: Mutex<Screen>,
+    changed: Condvar,
+    scale: usize,
+}
+
+/// Serves the display over HTTP on all interfaces, so that an emulator on
+/// a server can be watched from a browser: `/` is a page showing it,
+/// `/stream` a stream of PNGs (`multipart/x-mixed-replace`, like MJPEG)
+/// and `/frame.png` the display right now. Clients encode the frames on
+/// their own threads.
+pub struct StreamServer {
+    shared: Arc<Shared>,
+}
+
+impl StreamServer {
+    pub fn start(port: u16, colors: Palette, scale: usize) -> Result<Self> {
+        Ok(Self::bind(SocketAddr::from(([0, 0, 0, 0], port)), colors, scale)?.0)
+    }
+
+    fn bind(addr: SocketAddr, colors: Palette, scale: usize) -> Result<(Self, SocketAddr)> {
+        let listener = TcpListener::bind(addr)
+            .with_context(|| format!("Couldn't listen for stream viewers on {}", addr))?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            screen: Mutex::new(Screen {
+                display: Display::new(),
+                colors,
+                version: 0,
+            }),
+            changed: Condvar::new(),
+            scale,
+        });
+        let server = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let shared = server.clone();
+                thread::spawn(move || serve(stream, &shared));
+            }
+        });
+        Ok((Self { shared }, addr))
+    }
+
+    /// Shows `display` in `colors` to the clients, if that's a change
+    pub fn publish(&self, display: &Display, colors: &Palette) {
+        let mut screen = self.shared.screen.lock().unwrap();
+        if screen.display == *display && screen.colors.pixels == colors.pixels {
+            return;
+        }
+        screen.display = display.clone();
+        screen.colors = *colors;
+        screen.version += 1;
+        self.shared.changed.notify_all();
+    }
+}
+
+fn serve(stream: TcpStream, shared: &Shared) {
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    if reader.read_line(&mut request).is_err() {
+        return;
+    }
+    // the headers don't matter
+    let mut line = String::new();
+    while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+        line.clear();
+    }
+
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let _ = match path {
+        "/" => respond(&stream, "text/html; charset=utf-8", PAGE.as_bytes()),
+        "/frame.png" => {
+            let screen = shared.screen.lock().unwrap().clone();
+            respond(&stream, "image/png", &encode(&screen, shared.scale))
+        }
+        "/stream" => stream_frames(&stream, shared),
+        _ => write!(
+            &stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        ),
+    };
+}
+
+fn encode(screen: &Screen, scale: usize) -> Vec<u8> {
+    screenshot::encode_png(&screen.display, &screen.colors, scale)
+}
+
+fn respond(mut stream: &TcpStream, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)
+}
+
+/// Sends a frame whenever the display changes, until the client leaves
+fn stream_frames(mut stream: &TcpStream, shared: &Shared) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary=frame\r\n\
+         Cache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    let mut seen = None;
+    loop {
+        let screen = {
+            let screen = shared
+                .changed
+                .wait_while(shared.screen.lock().unwrap(), |screen| {
+                    seen == Some(screen.version)
+                })
+                .unwrap();
+            seen = Some(screen.version);
+            screen.clone()
+        };
+        let png = encode(&screen, shared.scale);
+        write!(
+            stream,
+            "--frame\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\r\n",
+            png.len()
+        )?;
+        stream.write_all(&png)?;
+        stream.write_all(b"\r\n")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::PALETTES;
+    use std::io::Read;
+
+    #[test]
+    fn streams_a_frame_per_change() {
+        let (server, addr) =
+            StreamServer::bind(SocketAddr::from(([127, 0, 0, 1], 0)), PALETTES[0], 1).unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET /stream HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+
+        let mut display = Display::new();
+        display.rows[0] = 1;
+        // the first frame is whatever was shown when the client came, so
+        // keep changing the display until a second one arrives
+        let mut received = Vec::new();
+        let mut buffer = [0; 4096];
+        client
+            .set_read_timeout(Some(std::time::Duration::from_millis(50)))
+            .unwrap();
+        for _ in 0..100 {
+            server.publish(&display, &PALETTES[0]);
+            display.rows[0] += 1;
+            if let Ok(n) = client.read(&mut buffer) {
+                received.extend_from_slice(&buffer[..n]);
+            }
+            let text = String::from_utf8_lossy(&received);
+            if text.matches("Content-Type: image/png").count() >= 2 {
+                assert!(text.starts_with("HTTP/1.1 200 OK"));
+                assert!(text.contains("multipart/x-mixed-replace"));
+                return;
+            }
+        }
+        panic!("no frames: {}", String::from_utf8_lossy(&received));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::instruction::Instruction;
+use anyhow::{Context, Result, anyhow};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// Names for addresses, read from and written to `.sym` files with a hex
+/// address and a name per line, like `0208 draw_paddle`. `#` starts a
+/// comment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    names: BTreeMap<usize, String>,
+}
+
+impl Symbols {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read symbols `{}`", path.display()))?;
+        Self::parse(&text).with_context(|| format!("In `{}`", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut symbols = Self::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let (Some(addr), Some(name), None) = (words.next(), words.next(), words.next()) else {
+                return Err(anyhow!(
+                    "line {}: expected an address and a name, found `{}`",
+                    n + 1,
+                    line
+                ));
+            };
+            let digits = addr.trim_start_matches("0x");
+            let addr = usize::from_str_radix(digits, 16)
+                .map_err(|_| anyhow!("line {}: bad address `{}`", n + 1, addr))?;
+            symbols.insert(addr, name);
+        }
+        Ok(symbols)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_string())
+            .with_context(|| format!("Couldn't write symbols `{}`", path.display()))
+    }
+
+    pub fn insert(&mut self, addr: usize, name: impl Into<String>) {
+        self.names.insert(addr, name.into());
+    }
+
+    pub fn name(&self, addr: usize) -> Option<&str> {
+        self.names.get(&addr).map(String::as_str)
+    }
+
+    pub fn addr(&self, name: &str) -> Option<usize> {
+        self.names
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(&addr, _)| addr)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.names.iter().map(|(&addr, name)| (addr, name.as_str()))
+    }
+
+    /// Cowgod's mnemonics with the address of `JP`, `CALL` and `LD I`
+    /// replaced by its name, e.g. `CALL draw_paddle`
+    pub fn disassemble(&self, instruction: Instruction) -> String {
+        let (mnemonic, addr) = match instruction {
+            Instruction::Jump { addr } => ("JP", addr),
+            Instruction::Call { addr } => ("CALL", addr),
+            Instruction::SetI { addr } => ("LD I,", addr),
+            _ => return instruction.to_string(),
+        };
+        match self.name(addr as usize) {
+            Some(name) => format!("{} {}", mnemonic, name),
+            None => instruction.to_string(),
+        }
+    }
+}
+
+/// The `.sym` file
+impl fmt::Display for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (addr, name) in &self.names {
+            writeln!(f, "{:04X} {}", addr, name)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use crate::platform::Platform;
+use crate::{Cpu, Display, Fault, INSTRUCTIONS_PER_FRAME, Quirks};
+use anyhow::{Context, Result, anyhow};
+use std::path::PathBuf;
+
+/// Quirk profiles every test ROM is run with, and the number the quirks
+/// test of the combined suite knows the platform by
+const PROFILES: [(&str, Quirks, u8); 2] = [
+    ("chip8", Platform::Chip8.quirks(), 1),
+    ("schip", Platform::Schip.quirks(), 2),
+];
+
+/// A result mark the Timendus test ROMs draw, one row of pixels per entry
+/// with the leftmost pixel in the highest of `width` bits
+struct Mark {
+    width: usize,
+    rows: &'static [u8],
+    passed: bool,
+}
+
+const MARKS: [Mark; 4] = [
+    // check mark (flags and quirks tests)
+    Mark {
+        width: 3,
+        rows: &[0b101, 0b110, 0b100],
+        passed: true,
+    },
+    // cross
+    Mark {
+        width: 3,
+        rows: &[0b101, 0b010, 0b101],
+        passed: false,
+    },
+    // "oK" (corax test)
+    Mark {
+        width: 7,
+        rows: &[0b1110101, 0b1010110, 0b1010101, 0b1110101],
+        passed: true,
+    },
+    // "NO"
+    Mark {
+        width: 7,
+        rows: &[0b1100111, 0b1010101, 0b1010101, 0b1010111],
+        passed: false,
+    },
+];
+
+/// What a test ROM showed after running
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    /// Passed and failed marks on the screen
+    Results { passed: usize, failed: usize },
+    /// The emulator stopped, usually on an opcode it doesn't implement
+    Faulted(Fault),
+}
+
+/// Runs each ROM (or every .ch8 file in a directory) headlessly under each
+/// quirk profile for `frames` frames and reports the passed and failed
+/// marks the Timendus test ROMs draw. `select` is written to 0x1FF, where
+/// the combined test suite reads which test to run, and the profile's
+/// platform to 0x1FE, so that the quirks test skips its menu.
+pub fn run(paths: &[PathBuf], frames: u64, select: Option<u8>) -> Result<()> {
+    let roms = collect_roms(paths)?;
+    if roms.is_empty() {
+        return Err(anyhow!("No test ROMs found"));
+    }
+
+    let mut failures = 0;
+    for rom in &roms {
+        let program =
+            std::fs::read(rom).with_context(|| format!("Couldn't read `{}`", rom.display()))?;
+        let name = rom.file_name().unwrap_or(rom.as_os_str()).to_string_lossy();
+
+        for (profile, quirks, platform) in PROFILES {
+            let select = select.map(|test| (test, platform));
+            let outcome = run_rom(&program, quirks, frames, select);
+            let report = match &outcome {
+                Outcome::Results {
+                    passed: 0,
+                    failed: 0,
+                } => "no results on screen".to_string(),
+                Outcome::Results { passed, failed: 0 } => format!("ok, {} passed", passed),
+                Outcome::Results { passed, failed } => {
+                    format!("FAILED, {} passed, {} failed", passed, failed)
+                }
+                Outcome::Faulted(fault) => format!("FAULT: {}", fault),
+            };
+            if !matches!(outcome, Outcome::Results { failed: 0, .. }) {
+                failures += 1;
+            }
+            println!("{} [{}]: {}", name, profile, report);
+        }
+    }
+
+    match failures {
+        0 => Ok(()),
+        n => Err(anyhow!("{} test ROM runs failed or faulted", n)),
+    }
+}
+
+/// `paths`, with directories replaced by the .ch8 files in them
+fn collect_roms(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut roms = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            roms.push(path.clone());
+            continue;
+        }
+        let entries = std::fs::read_dir(path)
+            .with_context(|| format!("Couldn't read directory `{}`", path.display()))?;
+        let mut found = Vec::new();
+        for entry in entries {
+            let entry = entry?.path();
+            let is_rom = entry
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("ch8"));
+            if is_rom {
+                found.push(entry);
+            }
+        }
+        found.sort();
+        roms.extend(found);
+    }
+    Ok(roms)
+}
+
+/// `select` is the test and the platform to pick in the combined suite
+fn run_rom(program: &[u8], quirks: Quirks, frames: u64, select: Option<(u8, u8)>) -> Outcome {
+    let mut cpu = Cpu::new(program, 0);
+    cpu.quirks = quirks;
+    cpu.speed = INSTRUCTIONS_PER_FRAME;
+    if let Some((test, platform)) = select {
+        cpu.memory[0x1FF] = test;
+        cpu.memory[0x1FE] = platform;
+    }
+
+    for _ in 0..frames {
+        if let Err(fault) = cpu.run_frame() {
+            return Outcome::Faulted(fault);
+        }
+    }
+
+    let (passed, failed) = count_marks(&cpu.display);
+    Outcome::Results { passed, failed }
+}
+
+/// Passed and failed marks on the screen. A mark only counts with blank
+/// pixels all around it, so that parts of letters aren't mistaken for one.
+fn count_marks(display: &Display) -> (usize, usize) {
+    let (mut passed, mut failed) = (0, 0);
+    for row in 0..32 {
+        for col in 0..64 {
+            for mark in MARKS.iter().filter(|m| shows(display, row, col, m)) {
+                match mark.passed {
+                    true => passed += 1,
+                    false => failed += 1,
+                }
+            }
+        }
+    }
+    (passed, failed)
+}
+
+/// Whether `mark` is drawn with its top left corner at `row`, `col`
+fn shows(display: &Display, row: isize, col: isize, mark: &Mark) -> bool {
+    let height = mark.rows.len() as isize;
+    let width = mark.width as isize;
+    if row + height > 32 || col + width > 64 {
+        return false;
+    }
+
+    (row - 1..=row + height).all(|r| {
+        (col - 1..=col + width).all(|c| {
+            let inside = (row..row + height).contains(&r) && (col..col + width).contains(&c);
+            let expected =
+                inside && mark.rows[(r - row) as usize] >> (col + width - 1 - c) & 1 == 1;
+            let lit = (0..32).contains(&r)
+                && (0..64).contains(&c)
+                && display.pixel(r as usize, c as usize);
+            lit == expected
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A display with `marks` drawn at (row, col)
+    fn display_with(marks: &[(usize, usize, &Mark)]) -> Display {
+        let mut display = Display::new();
+        for &(row, col, mark) in marks {
+            for (r, &bits) in mark.rows.iter().enumerate() {
+                display.rows[row + r] |= (bits as u64) << (64 - mark.width) >> col;
+            }
+        }
+        display
+    }
+
+    #[test]
+    fn counts_marks() {
+        let [check, cross, ok, no] = &MARKS;
+        let display = display_with(&[
+            (1, 31, check),
+            (1, 35, check),
+            (6, 57, cross),
+            (29, 0, check),
+            (11, 10, ok),
+            (27, 57, no),
+        ]);
+        assert_eq!(count_marks(&display), (4, 2));
+    }
+
+    #[test]
+    fn ignores_marks_touching_other_pixels() {
+        let display = display_with(&[(1, 31, &MARKS[0]), (4, 31, &MARKS[0])]);
+        assert_eq!(count_marks(&display), (0, 0));
+    }
+}
@@ -0,0 +1,557 @@
+use super::*;
+
+mod lockstep;
+mod properties;
+
+/// A CPU with `opcode` at the start of the program, changed by `setup`,
+/// after executing the opcode
+fn run(opcode: u16, setup: impl FnOnce(&mut Cpu)) -> Cpu {
+    let mut cpu = Cpu::new(&opcode.to_be_bytes(), 0);
+    setup(&mut cpu);
+    cpu.step().unwrap();
+    cpu
+}
+
+/// The fault `opcode` raises at the start of the program, with the CPU
+/// changed by `setup`
+fn fault(opcode: u16, setup: impl FnOnce(&mut Cpu)) -> Fault {
+    let mut cpu = Cpu::new(&opcode.to_be_bytes(), 0);
+    setup(&mut cpu);
+    let fault = cpu.step().unwrap_err();
+    assert_eq!(cpu.position_in_memory, PROGRAM_START);
+    fault
+}
+
+/// The CPU after `8xy?` with Vx and Vy set
+fn alu(opcode: u16, vx: u8, vy: u8) -> Cpu {
+    let x = (opcode >> 8 & 0xF) as usize;
+    let y = (opcode >> 4 & 0xF) as usize;
+    run(opcode, |cpu| {
+        cpu.registers[x] = vx;
+        cpu.registers[y] = vy;
+    })
+}
+
+const NEXT: usize = PROGRAM_START + 2;
+const SKIPPED: usize = PROGRAM_START + 4;
+
+#[test]
+fn halt() {
+    let cpu = run(0x0000, |_| {});
+    assert!(cpu.halted);
+    assert!(!cpu.can_run());
+}
+
+#[test]
+fn halt_on_jump_to_self() {
+    assert!(!run(0x1200, |_| {}).halted);
+    let cpu = run(0x1200, |cpu| cpu.halt_on_self_jump = true);
+    assert!(cpu.halted);
+    assert_eq!(cpu.position_in_memory, PROGRAM_START);
+    assert!(!run(0x1202, |cpu| cpu.halt_on_self_jump = true).halted);
+}
+
+#[test]
+fn clear() {
+    let cpu = run(0x00E0, |cpu| cpu.display.rows = [u64::MAX; 32]);
+    assert_eq!(cpu.display.rows, [0; 32]);
+}
+
+#[test]
+fn call_and_return() {
+    let mut cpu = run(0x2300, |cpu| {
+        cpu.memory[0x300..0x302].copy_from_slice(&[0x00, 0xEE])
+    });
+    assert_eq!(cpu.position_in_memory, 0x300);
+    assert_eq!(cpu.stack_pointer, 1);
+    assert_eq!(cpu.stack[0], NEXT as u16);
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.position_in_memory, NEXT);
+    assert_eq!(cpu.stack_pointer, 0);
+}
+
+#[test]
+fn call_with_a_full_stack() {
+    let pc = PROGRAM_START;
+    assert_eq!(
+        fault(0x2300, |cpu| cpu.stack_pointer = 16),
+        Fault::StackOverflow { pc }
+    );
+}
+
+#[test]
+fn return_with_an_empty_stack() {
+    let pc = PROGRAM_START;
+    assert_eq!(fault(0x00EE, |_| {}), Fault::StackUnderflow { pc });
+}
+
+#[test]
+fn jump() {
+    assert_eq!(run(0x1ABC, |_| {}).position_in_memory, 0xABC);
+}
+
+#[test]
+fn skip_if_equal() {
+    assert_eq!(
+        run(0x3A42, |cpu| cpu.registers[0xA] = 0x42).position_in_memory,
+        SKIPPED
+    );
+    assert_eq!(
+        run(0x3A42, |cpu| cpu.registers[0xA] = 0x41).position_in_memory,
+        NEXT
+    );
+}
+
+#[test]
+fn skip_if_not_equal() {
+    assert_eq!(
+        run(0x4A42, |cpu| cpu.registers[0xA] = 0x42).position_in_memory,
+        NEXT
+    );
+    assert_eq!(
+        run(0x4A42, |cpu| cpu.registers[0xA] = 0x41).position_in_memory,
+        SKIPPED
+    );
+}
+
+#[test]
+fn skip_if_registers_equal() {
+    assert_eq!(alu(0x5120, 7, 7).position_in_memory, SKIPPED);
+    assert_eq!(alu(0x5120, 7, 8).position_in_memory, NEXT);
+}
+
+#[test]
+fn skip_if_registers_not_equal() {
+    assert_eq!(alu(0x9120, 7, 7).position_in_memory, NEXT);
+    assert_eq!(alu(0x9120, 7, 8).position_in_memory, SKIPPED);
+}
+
+#[test]
+fn load() {
+    assert_eq!(run(0x6B42, |_| {}).registers[0xB], 0x42);
+}
+
+#[test]
+fn add_wraps_without_touching_vf() {
+    let cpu = run(0x7BFF, |cpu| {
+        cpu.registers[0xB] = 2;
+        cpu.registers[0xF] = 0x55;
+    });
+    assert_eq!(cpu.registers[0xB], 1);
+    assert_eq!(cpu.registers[0xF], 0x55);
+}
+
+#[test]
+fn set() {
+    assert_eq!(alu(0x8120, 1, 2).registers[1], 2);
+}
+
+#[test]
+fn logic() {
+    assert_eq!(alu(0x8121, 0b1100, 0b1010).registers[1], 0b1110);
+    assert_eq!(alu(0x8122, 0b1100, 0b1010).registers[1], 0b1000);
+    assert_eq!(alu(0x8123, 0b1100, 0b1010).registers[1], 0b0110);
+}
+
+#[test]
+fn logic_resets_vf_with_the_quirk() {
+    for opcode in [0x8121, 0x8122, 0x8123] {
+        let cpu = run(opcode, |cpu| cpu.registers[0xF] = 1);
+        assert_eq!(cpu.registers[0xF], 1);
+
+        let cpu = run(opcode, |cpu| {
+            cpu.quirks.vf_reset = true;
+            cpu.registers[0xF] = 1;
+        });
+        assert_eq!(cpu.registers[0xF], 0);
+    }
+}
+
+#[test]
+fn add_registers() {
+    let cpu = alu(0x8124, 0x10, 0x20);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0x30, 0));
+
+    let cpu = alu(0x8124, 0xFF, 0x02);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0x01, 1));
+}
+
+#[test]
+fn sub() {
+    let cpu = alu(0x8125, 0x30, 0x10);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0x20, 1));
+
+    let cpu = alu(0x8125, 0x10, 0x30);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0xE0, 0));
+
+    // no borrow when equal
+    let cpu = alu(0x8125, 0x10, 0x10);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0, 1));
+}
+
+#[test]
+fn sub_reversed() {
+    let cpu = alu(0x8127, 0x10, 0x30);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0x20, 1));
+
+    let cpu = alu(0x8127, 0x30, 0x10);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0xE0, 0));
+
+    let cpu = alu(0x8127, 0x10, 0x10);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0, 1));
+}
+
+#[test]
+fn shifts_vx_in_place_by_default() {
+    let cpu = alu(0x8126, 0b0000_0101, 0xFF);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0b0000_0010, 1));
+
+    let cpu = alu(0x812E, 0b1000_0010, 0x00);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0b0000_0100, 1));
+
+    let cpu = alu(0x812E, 0b0100_0000, 0x00);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0b1000_0000, 0));
+}
+
+#[test]
+fn shifts_vy_without_the_quirk() {
+    let setup = |opcode: u16| {
+        run(opcode, |cpu| {
+            cpu.quirks.shift = false;
+            cpu.registers[1] = 0xFF;
+            cpu.registers[2] = 0b0000_0110;
+        })
+    };
+
+    let cpu = setup(0x8126);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0b0000_0011, 0));
+
+    let cpu = setup(0x812E);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0b0000_1100, 0));
+}
+
+/// The flag is written after the result, so with VF as Vx the result is lost
+#[test]
+fn vf_as_vx_keeps_the_flag() {
+    assert_eq!(alu(0x8F14, 0xFF, 0x02).registers[0xF], 1);
+    assert_eq!(alu(0x8F15, 0x01, 0x02).registers[0xF], 0);
+    assert_eq!(alu(0x8F17, 0x01, 0x02).registers[0xF], 1);
+    assert_eq!(alu(0x8F16, 0x02, 0x00).registers[0xF], 0);
+    assert_eq!(alu(0x8F1E, 0x80, 0x00).registers[0xF], 1);
+}
+
+#[test]
+fn vf_as_vy() {
+    let cpu = alu(0x81F4, 0xFF, 0x01);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0x00, 1));
+
+    let cpu = alu(0x81F5, 0x01, 0x02);
+    assert_eq!((cpu.registers[1], cpu.registers[0xF]), (0xFF, 0));
+}
+
+#[test]
+fn set_i() {
+    assert_eq!(run(0xA123, |_| {}).register_i, 0x123);
+}
+
+#[test]
+fn jump_with_offset() {
+    // xnn + Vx with the quirk, here V3
+    let cpu = run(0xB340, |cpu| {
+        cpu.registers[0] = 0x01;
+        cpu.registers[3] = 0x10;
+    });
+    assert_eq!(cpu.position_in_memory, 0x350);
+
+    let cpu = run(0xB340, |cpu| {
+        cpu.quirks.jump = false;
+        cpu.registers[0] = 0x01;
+        cpu.registers[3] = 0x10;
+    });
+    assert_eq!(cpu.position_in_memory, 0x341);
+}
+
+#[test]
+fn random_is_masked_and_seeded() {
+    assert_eq!(run(0xC500, |cpu| cpu.registers[5] = 0xFF).registers[5], 0);
+    assert_eq!(run(0xC50F, |_| {}).registers[5] & 0xF0, 0);
+
+    let a = Cpu::new(&[], 42).rng.next_u8();
+    let b = Cpu::new(&[], 42).rng.next_u8();
+    assert_eq!(a, b);
+}
+
+#[test]
+fn skip_if_key() {
+    let pressed = |opcode| {
+        run(opcode, |cpu| {
+            cpu.registers[4] = 0xB;
+            cpu.keypad[0xB] = true;
+        })
+    };
+    assert_eq!(pressed(0xE49E).position_in_memory, SKIPPED);
+    assert_eq!(pressed(0xE4A1).position_in_memory, NEXT);
+    assert_eq!(run(0xE49E, |_| {}).position_in_memory, NEXT);
+    assert_eq!(run(0xE4A1, |_| {}).position_in_memory, SKIPPED);
+}
+
+#[test]
+fn key_uses_the_low_nibble() {
+    let cpu = run(0xE49E, |cpu| {
+        cpu.registers[4] = 0xFB;
+        cpu.keypad[0xB] = true;
+    });
+    assert_eq!(cpu.position_in_memory, SKIPPED);
+}
+
+#[test]
+fn wait_for_key() {
+    let cpu = run(0xF30A, |_| {});
+    assert_eq!(cpu.position_in_memory, PROGRAM_START);
+
+    let cpu = run(0xF30A, |cpu| cpu.keypad[7] = true);
+    assert_eq!(cpu.position_in_memory, NEXT);
+    assert_eq!(cpu.registers[3], 7);
+}
+
+#[test]
+fn timers() {
+    assert_eq!(run(0xF307, |cpu| cpu.delay_timer = 9).registers[3], 9);
+    assert_eq!(run(0xF315, |cpu| cpu.registers[3] = 9).delay_timer, 9);
+    assert_eq!(run(0xF318, |cpu| cpu.registers[3] = 9).sound_timer, 9);
+}
+
+#[test]
+fn add_to_i() {
+    let cpu = run(0xF31E, |cpu| {
+        cpu.registers[3] = 0x20;
+        cpu.register_i = 0x300;
+    });
+    assert_eq!(cpu.register_i, 0x320);
+    // VF is left alone, unlike on the Amiga interpreter
+    let cpu = run(0xF31E, |cpu| {
+        cpu.registers[3] = 0xFF;
+        cpu.register_i = 0xFFF;
+    });
+    assert_eq!((cpu.register_i, cpu.registers[0xF]), (0x10FE, 0));
+}
+
+#[test]
+fn font_digits() {
+    let cpu = run(0xF329, |cpu| cpu.registers[3] = 0x1A);
+    assert_eq!(cpu.register_i as usize, FONT_START + 0xA * 5);
+    let digit = &cpu.memory[cpu.register_i as usize..][..5];
+    assert_eq!(digit, [0xF0, 0x90, 0xF0, 0x90, 0x90]);
+
+    // the program draws 0 with the sprite the font has for it
+    let mut cpu = Cpu::new(&[0xF0, 0x29, 0xD0, 0x05], 0);
+    cpu.run_frame().unwrap();
+    assert!(cpu.display.pixel(0, 0));
+    assert!(!cpu.display.pixel(1, 1));
+    assert!(cpu.display.pixel(4, 3));
+}
+
+#[test]
+fn bcd() {
+    let cpu = run(0xF333, |cpu| {
+        cpu.registers[3] = 254;
+        cpu.register_i = 0x300;
+    });
+    assert_eq!(cpu.memory[0x300..0x303], [2, 5, 4]);
+    assert_eq!(cpu.register_i, 0x300);
+    assert_eq!(cpu.writes, [(0x300, 0), (0x301, 0), (0x302, 0)]);
+}
+
+#[test]
+fn store_and_load_registers() {
+    let cpu = run(0xF255, |cpu| {
+        cpu.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+        cpu.register_i = 0x300;
+    });
+    assert_eq!(cpu.memory[0x300..0x304], [1, 2, 3, 0]);
+    assert_eq!(cpu.register_i, 0x300);
+
+    let cpu = run(0xF265, |cpu| {
+        cpu.memory[0x300..0x304].copy_from_slice(&[1, 2, 3, 4]);
+        cpu.register_i = 0x300;
+    });
+    assert_eq!(cpu.registers[..4], [1, 2, 3, 0]);
+    assert_eq!(cpu.register_i, 0x300);
+}
+
+#[test]
+fn store_registers_wraps_at_the_end_of_memory() {
+    let cpu = run(0xF155, |cpu| {
+        cpu.registers[..2].copy_from_slice(&[1, 2]);
+        cpu.register_i = 0xFFF;
+    });
+    assert_eq!((cpu.memory[0xFFF], cpu.memory[0]), (1, 2));
+}
+
+/// Draws `sprite` at (`x`, `y`) on `cpu`, from 0x300
+fn draw(cpu: &mut Cpu, x: u8, y: u8, sprite: &[u8]) {
+    cpu.memory[0x300..0x300 + sprite.len()].copy_from_slice(sprite);
+    cpu.memory[cpu.position_in_memory..][..2]
+        .copy_from_slice(&(0xD010 | sprite.len() as u16).to_be_bytes());
+    cpu.register_i = 0x300;
+    cpu.registers[0] = x;
+    cpu.registers[1] = y;
+    cpu.step().unwrap();
+}
+
+#[test]
+fn draw_sprite() {
+    let mut cpu = Cpu::new(&[], 0);
+    draw(&mut cpu, 8, 2, &[0b1010_0000, 0b0101_0000]);
+    assert_eq!(cpu.display.rows[2], 0b1010_0000 << 48);
+    assert_eq!(cpu.display.rows[3], 0b0101_0000 << 48);
+    assert!(cpu.display.pixel(2, 8));
+    assert!(!cpu.display.pixel(2, 9));
+    assert_eq!(cpu.registers[0xF], 0);
+}
+
+#[test]
+fn draw_collision_erases() {
+    let mut cpu = Cpu::new(&[], 0);
+    draw(&mut cpu, 0, 0, &[0b1100_0000]);
+    cpu.position_in_memory = PROGRAM_START;
+    draw(&mut cpu, 0, 0, &[0b0110_0000]);
+    assert_eq!(cpu.display.rows[0], 0b1010_0000 << 56);
+    assert_eq!(cpu.registers[0xF], 1);
+}
+
+#[test]
+fn draw_clips_at_the_edges() {
+    let mut cpu = Cpu::new(&[], 0);
+    draw(&mut cpu, 60, 30, &[0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(cpu.display.rows[30], 0xF);
+    assert_eq!(cpu.display.rows[31], 0xF);
+    assert_eq!(cpu.display.rows[0], 0);
+    assert_eq!(cpu.display.rows[1], 0);
+}
+
+#[test]
+fn draw_wraps_the_start_position() {
+    let mut cpu = Cpu::new(&[], 0);
+    draw(&mut cpu, 64 + 1, 32 + 3, &[0x80]);
+    assert!(cpu.display.pixel(3, 1));
+}
+
+#[test]
+fn draw_reads_the_sprite_across_the_end_of_memory() {
+    let mut cpu = Cpu::new(&[0xD0, 0x12], 0);
+    cpu.register_i = 0xFFF;
+    cpu.memory[0xFFF] = 0x80;
+    cpu.memory[0] = 0x40;
+    cpu.step().unwrap();
+    assert!(cpu.display.pixel(0, 0));
+    assert!(cpu.display.pixel(1, 1));
+}
+
+#[test]
+fn draw_sets_vf_after_clearing_it() {
+    let mut cpu = Cpu::new(&[], 0);
+    cpu.registers[0xF] = 1;
+    draw(&mut cpu, 0, 0, &[0x80]);
+    assert_eq!(cpu.registers[0xF], 0);
+}
+
+#[test]
+fn unknown_opcode() {
+    let (pc, opcode) = (PROGRAM_START, 0x5121);
+    assert_eq!(fault(opcode, |_| {}), Fault::UnknownOpcode { pc, opcode });
+}
+
+#[test]
+fn pc_outside_of_memory() {
+    // Bnnn to 0xFFF + V0
+    let cpu = run(0xBFFF, |cpu| {
+        cpu.quirks.jump = false;
+        cpu.registers[0] = 0x10;
+    });
+    let mut faulted = cpu.clone();
+    assert_eq!(faulted.step(), Err(Fault::PcOutOfBounds { pc: 0x100F }));
+    assert_eq!(faulted.position_in_memory, 0x100F);
+    assert_eq!(cpu.read_opcode(), 0);
+
+    // the last byte of memory is only half an instruction
+    let mut cpu = Cpu::new(&[], 0);
+    cpu.position_in_memory = 0xFFF;
+    assert_eq!(cpu.step(), Err(Fault::PcOutOfBounds { pc: 0xFFF }));
+}
+
+#[test]
+fn faults_stop_the_frame() {
+    let mut cpu = Cpu::new(&[0x60, 0x05, 0x51, 0x21], 0);
+    cpu.delay_timer = 2;
+    let pc = PROGRAM_START + 2;
+    assert_eq!(
+        cpu.run_frame(),
+        Err(Fault::UnknownOpcode { pc, opcode: 0x5121 })
+    );
+    assert_eq!((cpu.registers[0], cpu.delay_timer), (5, 2));
+}
+
+#[test]
+fn frame_runs_speed_instructions_and_ticks_timers() {
+    // ADD V0, 1 over and over
+    let mut cpu = Cpu::new(&[0x70, 0x01].repeat(20), 0);
+    cpu.delay_timer = 5;
+    cpu.sound_timer = 1;
+    cpu.run_frame().unwrap();
+    assert_eq!(cpu.registers[0], INSTRUCTIONS_PER_FRAME as u8);
+    assert_eq!(cpu.cycles, INSTRUCTIONS_PER_FRAME);
+    assert_eq!((cpu.delay_timer, cpu.sound_timer), (4, 0));
+}
+
+#[test]
+fn stops_at_max_cycles() {
+    // JP 200
+    let mut cpu = Cpu::new(&[0x12, 0x00], 0);
+    cpu.max_cycles = 25;
+    for _ in 0..10 {
+        cpu.run_frame().unwrap();
+    }
+    assert_eq!(cpu.cycles, 25);
+}
+
+#[test]
+fn executed_records_registers() {
+    let mut cpu = Cpu::new(&[0x60, 0x05], 0);
+    cpu.executed = Some(Vec::new());
+    cpu.step().unwrap();
+    let executed = cpu.take_executed();
+    assert_eq!(executed.len(), 1);
+    assert_eq!(
+        (executed[0].pc, executed[0].opcode),
+        (PROGRAM_START, 0x6005)
+    );
+    assert_eq!(executed[0].before.v[0], 0);
+    assert_eq!(executed[0].after.v[0], 5);
+}
+
+#[test]
+fn runs_through_the_c_interface() {
+    use ffi::*;
+    // LD I, 20A; DRW V0, V0, 1; SKP V1; JP 204; HALT; sprite 80
+    let rom = [
+        0xA2, 0x0A, 0xD0, 0x01, 0xE1, 0x9E, 0x12, 0x04, 0x00, 0x00, 0x80,
+    ];
+    let mut pixels = [0; 64 * 32];
+    unsafe {
+        let chip8 = chip8_create(0);
+        assert_eq!(chip8_load_rom(chip8, rom.as_ptr(), rom.len()), CHIP8_OK);
+        assert_eq!(chip8_run_frame(chip8), CHIP8_OK);
+        chip8_framebuffer(chip8, pixels.as_mut_ptr());
+        assert_eq!(pixels[..2], [1, 0]);
+        chip8_set_keys(chip8, 0b1);
+        assert_eq!(chip8_run_frame(chip8), CHIP8_HALTED);
+        assert_eq!(chip8_step(chip8), CHIP8_HALTED);
+        assert_eq!(
+            chip8_load_rom(chip8, rom.as_ptr(), MAX_PROGRAM_SIZE + 1),
+            CHIP8_ROM_TOO_LARGE
+        );
+        chip8_destroy(chip8);
+    }
+}
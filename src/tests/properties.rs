@@ -0,0 +1,101 @@
+//! Properties of the 8xy? instructions over all registers and values,
+//! including VF as Vx or Vy
+
+use super::super::*;
+use proptest::prelude::*;
+
+/// The CPU after `8xy?` with `registers` loaded
+fn alu(n: u16, x: usize, y: usize, registers: [u8; 16], quirks: Quirks) -> Cpu {
+    let opcode = 0x8000 | (x as u16) << 8 | (y as u16) << 4 | n;
+    let mut cpu = Cpu::new(&opcode.to_be_bytes(), 0);
+    cpu.registers = registers;
+    cpu.quirks = quirks;
+    cpu.step().unwrap();
+    cpu
+}
+
+/// `registers` with Vx set to `result` and then VF to `flag`, the order the
+/// instructions write them in
+fn expected(registers: [u8; 16], x: usize, result: u8, flag: Option<u8>) -> [u8; 16] {
+    let mut expected = registers;
+    expected[x] = result;
+    if let Some(flag) = flag {
+        expected[0xF] = flag;
+    }
+    expected
+}
+
+fn quirks() -> impl Strategy<Value = Quirks> {
+    (any::<bool>(), any::<bool>(), any::<bool>()).prop_map(|(shift, jump, vf_reset)| Quirks {
+        shift,
+        jump,
+        vf_reset,
+    })
+}
+
+proptest! {
+    #[test]
+    fn set(x in 0..16usize, y in 0..16usize, registers: [u8; 16], quirks in quirks()) {
+        let cpu = alu(0x0, x, y, registers, quirks);
+        prop_assert_eq!(cpu.registers, expected(registers, x, registers[y], None));
+    }
+
+    #[test]
+    fn logic(n in 1..4u16, x in 0..16usize, y in 0..16usize, registers: [u8; 16], quirks in quirks()) {
+        let cpu = alu(n, x, y, registers, quirks);
+        let (vx, vy) = (registers[x], registers[y]);
+        let result = match n {
+            1 => vx | vy,
+            2 => vx & vy,
+            _ => vx ^ vy,
+        };
+        let flag = quirks.vf_reset.then_some(0);
+        prop_assert_eq!(cpu.registers, expected(registers, x, result, flag));
+    }
+
+    #[test]
+    fn add_sets_the_carry(x in 0..16usize, y in 0..16usize, registers: [u8; 16], quirks in quirks()) {
+        let cpu = alu(0x4, x, y, registers, quirks);
+        let (sum, carry) = registers[x].overflowing_add(registers[y]);
+        prop_assert_eq!(cpu.registers, expected(registers, x, sum, Some(carry as u8)));
+    }
+
+    #[test]
+    fn sub_sets_no_borrow(x in 0..16usize, y in 0..16usize, registers: [u8; 16], quirks in quirks()) {
+        let cpu = alu(0x5, x, y, registers, quirks);
+        let (vx, vy) = (registers[x], registers[y]);
+        let flag = (vx >= vy) as u8;
+        prop_assert_eq!(cpu.registers, expected(registers, x, vx.wrapping_sub(vy), Some(flag)));
+    }
+
+    #[test]
+    fn subn_sets_no_borrow(x in 0..16usize, y in 0..16usize, registers: [u8; 16], quirks in quirks()) {
+        let cpu = alu(0x7, x, y, registers, quirks);
+        let (vx, vy) = (registers[x], registers[y]);
+        let flag = (vy >= vx) as u8;
+        prop_assert_eq!(cpu.registers, expected(registers, x, vy.wrapping_sub(vx), Some(flag)));
+    }
+
+    #[test]
+    fn shr_shifts_out_the_low_bit(x in 0..16usize, y in 0..16usize, registers: [u8; 16], quirks in quirks()) {
+        let cpu = alu(0x6, x, y, registers, quirks);
+        let value = registers[if quirks.shift { x } else { y }];
+        prop_assert_eq!(cpu.registers, expected(registers, x, value >> 1, Some(value & 1)));
+    }
+
+    #[test]
+    fn shl_shifts_out_the_high_bit(x in 0..16usize, y in 0..16usize, registers: [u8; 16], quirks in quirks()) {
+        let cpu = alu(0xE, x, y, registers, quirks);
+        let value = registers[if quirks.shift { x } else { y }];
+        prop_assert_eq!(cpu.registers, expected(registers, x, value << 1, Some(value >> 7)));
+    }
+
+    #[test]
+    fn alu_only_changes_registers_and_pc(n in prop::sample::select(vec![0, 1, 2, 3, 4, 5, 6, 7, 0xE]), x in 0..16usize, y in 0..16usize, registers: [u8; 16], quirks in quirks()) {
+        let cpu = alu(n, x, y, registers, quirks);
+        prop_assert_eq!(cpu.position_in_memory, PROGRAM_START + 2);
+        prop_assert_eq!(cpu.register_i, 0);
+        prop_assert_eq!(cpu.stack_pointer, 0);
+        prop_assert!(cpu.writes.is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+use crate::instruction::Instruction;
+use crate::symbols::Symbols;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// Registers that an instruction can change, besides the PC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: usize,
+    pub dt: u8,
+    pub st: u8,
+}
+
+/// An executed instruction with the registers before and after it
+#[derive(Debug, Clone)]
+pub struct Executed {
+    pub pc: usize,
+    pub opcode: u16,
+    pub before: Registers,
+    pub after: Registers,
+    /// Addresses the instruction wrote and their previous values
+    pub writes: Vec<(usize, u8)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TraceFormat {
+    /// The PC, opcode, disassembly and the registers the instruction changed
+    Text,
+    /// A JSON object per line with the PC, opcode, disassembly and all the
+    /// registers after the instruction
+    Json,
+}
+
+/// A line of the JSON trace
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    pc: usize,
+    opcode: u16,
+    mnemonic: &'a str,
+    v: [u8; 16],
+    i: u16,
+    sp: usize,
+    dt: u8,
+    st: u8,
+}
+
+/// Writes executed instructions to a file, one per line
+pub struct Tracer {
+    out: BufWriter<File>,
+    format: TraceFormat,
+    range: Option<Range<usize>>,
+    symbols: Symbols,
+}
+
+impl Tracer {
+    /// Only instructions with the PC in `range` are written, when given.
+    /// Jump, call and `LD I` addresses with a symbol are shown by name.
+    pub fn create(
+        path: &Path,
+        format: TraceFormat,
+        range: Option<Range<usize>>,
+        symbols: Symbols,
+    ) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Couldn't create trace `{}`", path.display()))?;
+        Ok(Self {
+            out: BufWriter::new(file),
+            format,
+            range,
+            symbols,
+        })
+    }
+
+    pub fn write(&mut self, executed: &[Executed]) -> Result<()> {
+        for e in executed {
+            if self.range.as_ref().is_some_and(|r| !r.contains(&e.pc)) {
+                continue;
+            }
+            let instruction = self.symbols.disassemble(Instruction::decode(e.opcode));
+            let line = match self.format {
+                TraceFormat::Text => format!(
+                    "{:04X}  {:04X}  {:<16}{}",
+                    e.pc,
+                    e.opcode,
+                    instruction,
+                    changes(&e.before, &e.after)
+                )
+                .trim_end()
+                .to_string(),
+                TraceFormat::Json => serde_json::to_string(&JsonLine {
+                    pc: e.pc,
+                    opcode: e.opcode,
+                    mnemonic: &instruction,
+                    v: e.after.v,
+                    i: e.after.i,
+                    sp: e.after.sp,
+                    dt: e.after.dt,
+                    st: e.after.st,
+                })?,
+            };
+            writeln!(self.out, "{}", line).context("Couldn't write the trace")?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.out.flush().context("Couldn't write the trace")
+    }
+}
+
+/// `V0=01 I=0300`
+fn changes(before: &Registers, after: &Registers) -> String {
+    let mut changes = String::new();
+    for (r, (old, new)) in before.v.iter().zip(&after.v).enumerate() {
+        if old != new {
+            let _ = write!(changes, " V{:X}={:02X}", r, new);
+        }
+    }
+    if before.i != after.i {
+        let _ = write!(changes, " I={:04X}", after.i);
+    }
+    if before.sp != after.sp {
+        let _ = write!(changes, " SP={:X}", after.sp);
+    }
+    if before.dt != after.dt {
+        let _ = write!(changes, " DT={:02X}", after.dt);
+    }
+    if before.st != after.st {
+        let _ = write!(changes, " ST={:02X}", after.st);
+    }
+    changes.trim_start().to_string()
+}
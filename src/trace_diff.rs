@@ -0,0 +1,176 @@
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Matching instructions shown before the first difference
+const CONTEXT: usize = 3;
+
+/// A line of a JSON trace, as written by `--trace-format json`. Only the
+/// PC is required, so that traces of other emulators can leave out what
+/// they don't track.
+#[derive(Debug, Default, Deserialize)]
+struct Line {
+    pc: usize,
+    opcode: Option<u16>,
+    mnemonic: Option<String>,
+    v: Option<[u8; 16]>,
+    i: Option<u16>,
+    sp: Option<usize>,
+    dt: Option<u8>,
+    st: Option<u8>,
+}
+
+impl Line {
+    /// What differs from `other` among what both traces have, like `VA 05 != 06`
+    fn differences(&self, other: &Line) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |name: String, a: Option<String>, b: Option<String>| {
+            if let (Some(a), Some(b)) = (a, b)
+                && a != b
+            {
+                differences.push(format!("{} {} != {}", name, a, b));
+            }
+        };
+        let hex2 = |value: u8| format!("{:02X}", value);
+        let hex4 = |value: usize| format!("{:04X}", value);
+
+        compare("PC".into(), Some(hex4(self.pc)), Some(hex4(other.pc)));
+        compare(
+            "opcode".into(),
+            self.opcode.map(|o| hex4(o as usize)),
+            other.opcode.map(|o| hex4(o as usize)),
+        );
+        if let (Some(a), Some(b)) = (self.v, other.v) {
+            for r in 0..16 {
+                compare(format!("V{:X}", r), Some(hex2(a[r])), Some(hex2(b[r])));
+            }
+        }
+        compare(
+            "I".into(),
+            self.i.map(|i| hex4(i as usize)),
+            other.i.map(|i| hex4(i as usize)),
+        );
+        compare(
+            "SP".into(),
+            self.sp.map(|sp| sp.to_string()),
+            other.sp.map(|sp| sp.to_string()),
+        );
+        compare("DT".into(), self.dt.map(hex2), other.dt.map(hex2));
+        compare("ST".into(), self.st.map(hex2), other.st.map(hex2));
+        differences
+    }
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:04X}", self.pc)?;
+        if let Some(opcode) = self.opcode {
+            write!(f, "  {:04X}", opcode)?;
+        }
+        if let Some(mnemonic) = &self.mnemonic {
+            write!(f, "  {}", mnemonic)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a JSON trace a line at a time
+struct Trace {
+    name: String,
+    lines: std::io::Lines<BufReader<File>>,
+    number: usize,
+}
+
+impl Trace {
+    fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Couldn't open trace `{}`", path.display()))?;
+        Ok(Self {
+            name: path.display().to_string(),
+            lines: BufReader::new(file).lines(),
+            number: 0,
+        })
+    }
+
+    fn next(&mut self) -> Result<Option<Line>> {
+        let Some(text) = self.lines.next() else {
+            return Ok(None);
+        };
+        self.number += 1;
+        let text = text.with_context(|| format!("Couldn't read `{}`", self.name))?;
+        let line = serde_json::from_str(&text)
+            .with_context(|| format!("{}:{}: not a JSON trace line", self.name, self.number))?;
+        Ok(Some(line))
+    }
+}
+
+/// Compares two JSON traces instruction by instruction and reports the
+/// first one where the PC, the opcode or the registers after it differ.
+/// Fails if the traces diverge.
+pub fn run(a: &Path, b: &Path) -> Result<()> {
+    let mut a = Trace::open(a)?;
+    let mut b = Trace::open(b)?;
+    let mut recent = VecDeque::with_capacity(CONTEXT);
+
+    loop {
+        let (line_a, line_b) = match (a.next()?, b.next()?) {
+            (Some(line_a), Some(line_b)) => (line_a, line_b),
+            (None, None) => {
+                println!("Traces match for all {} instructions", a.number);
+                return Ok(());
+            }
+            (None, Some(_)) | (Some(_), None) => {
+                let (ended, longer) = if a.number < b.number {
+                    (&a, &b)
+                } else {
+                    (&b, &a)
+                };
+                return Err(anyhow!(
+                    "{} ends after {} instructions, {} goes on",
+                    ended.name,
+                    ended.number,
+                    longer.name
+                ));
+            }
+        };
+
+        let differences = line_a.differences(&line_b);
+        if differences.is_empty() {
+            if recent.len() == CONTEXT {
+                recent.pop_front();
+            }
+            recent.push_back(line_a);
+            continue;
+        }
+
+        println!("Traces diverge at instruction {}:", a.number);
+        for line in &recent {
+            println!("    {}", line);
+        }
+        println!("  a {}", line_a);
+        println!("  b {}", line_b);
+        println!("{}", differences.join(", "));
+        return Err(anyhow!("{} and {} diverge", a.name, b.name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_what_both_traces_have() {
+        let ours: Line = serde_json::from_str(
+            r#"{"pc":520,"opcode":24581,"mnemonic":"LD V0, 05","v":[5,0,0,0,0,0,0,0,0,0,0,2,0,0,0,0],"i":0,"sp":0,"dt":0,"st":0}"#,
+        )
+        .unwrap();
+        let reference: Line =
+            serde_json::from_str(r#"{"pc":520,"v":[5,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0],"i":0}"#)
+                .unwrap();
+        assert_eq!(ours.differences(&reference), ["VB 02 != 03"]);
+        assert!(ours.differences(&ours).is_empty());
+    }
+}
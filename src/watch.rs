@@ -0,0 +1,49 @@
+use anyhow::{Context, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+/// Notices when a file changes on disk. The directory is watched rather
+/// than the file, since editors often save by replacing the file.
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+    changes: Receiver<()>,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> Result<Self> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Couldn't watch `{}`", path.display()))?;
+        let dir = path.parent().map(PathBuf::from).unwrap_or_default();
+
+        let (tx, changes) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else { return };
+                let ours = event.paths.contains(&path);
+                if ours && (event.kind.is_create() || event.kind.is_modify()) {
+                    let _ = tx.send(());
+                }
+            })
+            .context("Couldn't start watching files")?;
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("Couldn't watch `{}`", dir.display()))?;
+
+        Ok(Self {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// Whether the file changed since the last call. A save usually comes
+    /// as several events, which count as one change.
+    pub fn changed(&self) -> bool {
+        let mut changed = false;
+        while self.changes.try_recv().is_ok() {
+            changed = true;
+        }
+        changed
+    }
+}
@@ -0,0 +1,198 @@
+//! Runs the emulator binary the way scripts do, without a window
+
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A file in the temporary directory that's removed when dropped
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("chip8-cli-{}-{}", std::process::id(), name)))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn fault_still_writes_the_dumps() {
+    let rom = TempFile::new("fault.ch8");
+    let state = TempFile::new("state.json");
+    let display = TempFile::new("display.txt");
+    let screenshot = TempFile::new("screenshot.png");
+    // CLS, then 00EE returns with nothing on the stack
+    std::fs::write(&rom.0, [0x00, 0xE0, 0x00, 0xEE]).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cpu"))
+        .arg("--file")
+        .arg(&rom.0)
+        .args(["--screenshot-at-frame", "10", "--seed", "1"])
+        .arg("--screenshot-file")
+        .arg(&screenshot.0)
+        .arg("--dump-state")
+        .arg(&state.0)
+        .arg("--dump-display")
+        .arg(&display.0)
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("underflow"));
+    let state = std::fs::read_to_string(&state.0).unwrap();
+    assert!(state.contains("\"frames\":0"), "{}", state);
+    assert!(display.0.exists());
+}
+
+#[test]
+fn dump_to_stdout_is_all_that_goes_there() {
+    let rom = TempFile::new("halt.ch8");
+    // CLS, then a jump to itself
+    std::fs::write(&rom.0, [0x00, 0xE0, 0x12, 0x02]).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cpu"))
+        .arg("--file")
+        .arg(&rom.0)
+        .args(["--exit-on-halt", "--dump-state", "-"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.trim().starts_with('{') && stdout.trim().ends_with('}'),
+        "{}",
+        stdout
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Program halted"));
+}
+
+#[test]
+fn max_frames_runs_without_a_window() {
+    let rom = TempFile::new("loop.ch8");
+    let state = TempFile::new("loop.json");
+    // a jump to itself
+    std::fs::write(&rom.0, [0x12, 0x00]).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cpu"))
+        .arg("--file")
+        .arg(&rom.0)
+        .args(["--max-frames", "5"])
+        .arg("--dump-state")
+        .arg(&state.0)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let state = std::fs::read_to_string(&state.0).unwrap();
+    assert!(state.contains("\"frames\":5"), "{}", state);
+}
+
+#[test]
+fn movie_recorded_without_a_window_verifies() {
+    let rom = TempFile::new("movie.ch8");
+    let movie = TempFile::new("movie.txt");
+    // draws the top row of the font's 0 at random places, so the screen
+    // depends on the seed written in the movie
+    let program = [0xA0, 0x50, 0xC0, 0x3F, 0xC1, 0x1F, 0xD0, 0x11, 0x12, 0x02];
+    std::fs::write(&rom.0, program).unwrap();
+
+    let record = Command::new(env!("CARGO_BIN_EXE_cpu"))
+        .arg("--file")
+        .arg(&rom.0)
+        .args(["--max-frames", "30", "--record-input"])
+        .arg(&movie.0)
+        .output()
+        .unwrap();
+    assert!(record.status.success());
+
+    let verify = Command::new(env!("CARGO_BIN_EXE_cpu"))
+        .arg("verify")
+        .arg("--file")
+        .arg(&rom.0)
+        .arg(&movie.0)
+        .output()
+        .unwrap();
+    assert!(
+        verify.status.success(),
+        "{}",
+        String::from_utf8_lossy(&verify.stderr)
+    );
+    assert!(String::from_utf8_lossy(&verify.stdout).contains("30 frames match"));
+}
+
+#[test]
+fn cheats_stay_off_while_recording() {
+    let rom = TempFile::new("cheat.ch8");
+    let cht = TempFile::new("cheat.cht");
+    let movie = TempFile::new("cheat.movie");
+    let state = TempFile::new("cheat.json");
+    // loads the byte at 300 into V0, then jumps to itself
+    std::fs::write(&rom.0, [0xA3, 0x00, 0xF0, 0x65, 0x12, 0x04]).unwrap();
+    std::fs::write(&cht.0, "300=07\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cpu"))
+        .arg("--file")
+        .arg(&rom.0)
+        .args(["--max-frames", "2", "--record-input"])
+        .arg(&movie.0)
+        .arg("--dump-state")
+        .arg(&state.0)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let state = std::fs::read_to_string(&state.0).unwrap();
+    assert!(state.contains("\"v\":[0,"), "{}", state);
+}
+
+#[test]
+fn verify_applies_the_patches() {
+    let rom = TempFile::new("patched.ch8");
+    let patch = TempFile::new("patched.ips");
+    let movie = TempFile::new("patched.movie");
+    // draws the top row of the font's 0 at a random column, which the patch
+    // moves down a row
+    std::fs::write(
+        &rom.0,
+        [0xA0, 0x50, 0xC0, 0x3F, 0x61, 0x00, 0xD0, 0x11, 0x12, 0x02],
+    )
+    .unwrap();
+    let mut ips = b"PATCH".to_vec();
+    ips.extend([0x00, 0x00, 0x05, 0x00, 0x01, 0x01]);
+    ips.extend(b"EOF");
+    std::fs::write(&patch.0, ips).unwrap();
+
+    let cpu = || {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_cpu"));
+        command.arg("--patch").arg(&patch.0);
+        command
+    };
+    let record = cpu()
+        .arg("--file")
+        .arg(&rom.0)
+        .args(["--max-frames", "10", "--record-input"])
+        .arg(&movie.0)
+        .output()
+        .unwrap();
+    assert!(record.status.success());
+
+    let verify = |patched: bool| {
+        let mut command = if patched {
+            cpu()
+        } else {
+            Command::new(env!("CARGO_BIN_EXE_cpu"))
+        };
+        command
+            .arg("verify")
+            .arg("--file")
+            .arg(&rom.0)
+            .arg(&movie.0);
+        command.output().unwrap().status.success()
+    };
+    assert!(verify(true));
+    assert!(!verify(false));
+}
@@ -0,0 +1,112 @@
+//! Golden framebuffer snapshots. Each test runs a ROM for a number of frames
+//! and compares the display with `tests/snapshots/<name>.png`. Run with
+//! `BLESS=1 cargo test --test snapshots` to write the snapshots from the
+//! current output instead.
+
+use cpu::{Cpu, Display, Quirks};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+#[test]
+fn ibm_logo() {
+    check("ibm_logo", "IBM Logo.ch8", 30, Quirks::default());
+}
+
+/// Clipping at the right and bottom edges, wrapping the start position,
+/// 15 row sprites and XOR with what is already on the screen
+#[test]
+fn draw() {
+    check("draw", "tests/roms/draw.ch8", 10, Quirks::default());
+}
+
+/// Runs `rom` (relative to the crate root) for `frames` frames and compares
+/// the display with the snapshot called `name`
+fn check(name: &str, rom: &str, frames: usize, quirks: Quirks) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let program = std::fs::read(root.join(rom)).unwrap();
+    let mut cpu = Cpu::new(&program, 0);
+    cpu.quirks = quirks;
+    for _ in 0..frames {
+        cpu.run_frame().unwrap();
+    }
+
+    let path = root.join("tests/snapshots").join(format!("{}.png", name));
+    let actual = path.with_extension("actual.png");
+    if std::env::var_os("BLESS").is_some() {
+        write_png(&path, &cpu.display);
+        return;
+    }
+
+    let expected = read_png(&path).unwrap_or_else(|| {
+        panic!(
+            "No snapshot at {}, run with BLESS=1 to create it",
+            path.display()
+        )
+    });
+    if expected != cpu.display {
+        write_png(&actual, &cpu.display);
+        panic!(
+            "{} differs from the snapshot, the display is saved as {} \
+             (+ lit only now, - lit only in the snapshot):\n{}",
+            name,
+            actual.display(),
+            diff(&expected, &cpu.display)
+        );
+    }
+    let _ = std::fs::remove_file(actual);
+}
+
+/// The two displays side by side in text, `#` for pixels lit in both
+fn diff(expected: &Display, actual: &Display) -> String {
+    let mut text = String::new();
+    for row in 0..32 {
+        for col in 0..64 {
+            text.push(match (expected.pixel(row, col), actual.pixel(row, col)) {
+                (true, true) => '#',
+                (false, true) => '+',
+                (true, false) => '-',
+                (false, false) => '.',
+            });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// The display as a 64x32 grayscale PNG, white for lit pixels
+fn write_png(path: &Path, display: &Display) {
+    let data: Vec<u8> = (0..32)
+        .flat_map(|row| (0..64).map(move |col| display.pixel(row, col) as u8 * 0xFF))
+        .collect();
+
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path).unwrap()), 64, 32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&data).unwrap();
+}
+
+/// A display read back from `write_png`, if the file exists
+fn read_png(path: &Path) -> Option<Display> {
+    let file = File::open(path).ok()?;
+    let mut reader = png::Decoder::new(std::io::BufReader::new(file))
+        .read_info()
+        .unwrap();
+    let mut data = vec![0; reader.output_buffer_size().unwrap()];
+    let info = reader.next_frame(&mut data).unwrap();
+    assert_eq!(
+        (info.width, info.height, info.color_type),
+        (64, 32, png::ColorType::Grayscale),
+        "{} isn't a 64x32 grayscale snapshot",
+        path.display()
+    );
+
+    let mut display = Display::new();
+    for (i, &value) in data.iter().enumerate() {
+        if value >= 0x80 {
+            display.rows[i / 64] |= 1 << 63 >> (i % 64);
+        }
+    }
+    Some(display)
+}
@@ -0,0 +1,131 @@
+//! The core for the web, built with `wasm-pack build --release` into an npm
+//! package whose `Chip8` class a page drives 60 times a second:
+//!
+//! ```js
+//! import { Chip8 } from "chip8-wasm";
+//! const chip8 = new Chip8();
+//! chip8.loadRom(new Uint8Array(await (await fetch("pong.ch8")).arrayBuffer()));
+//! chip8.keyDown(1);
+//! chip8.tick();
+//! chip8.getDisplay(); // Uint8Array of 64 * 32 pixels
+//! ```
+
+use cpu::{Cpu, Fault, MAX_PROGRAM_SIZE};
+use wasm_bindgen::prelude::*;
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+/// A CHIP8 machine with empty memory until `loadRom`
+#[wasm_bindgen]
+pub struct Chip8 {
+    cpu: Cpu,
+    seed: u32,
+}
+
+fn run(result: Result<(), Fault>, cpu: &Cpu) -> Result<bool, JsError> {
+    result.map_err(|fault| JsError::new(&fault.to_string()))?;
+    Ok(cpu.can_run())
+}
+
+#[wasm_bindgen]
+impl Chip8 {
+    /// `seed` decides the random numbers, so that a game can be replayed
+    #[wasm_bindgen(constructor)]
+    pub fn new(seed: Option<u32>) -> Self {
+        let seed = seed.unwrap_or(0);
+        Self {
+            cpu: Cpu::new(&[], seed as u64),
+            seed,
+        }
+    }
+
+    /// Starts over with `rom` as the program, keeping the speed
+    #[wasm_bindgen(js_name = loadRom)]
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), JsError> {
+        if rom.len() > MAX_PROGRAM_SIZE {
+            return Err(JsError::new(&format!(
+                "The ROM is {} bytes, at most {} fit in memory",
+                rom.len(),
+                MAX_PROGRAM_SIZE
+            )));
+        }
+        let mut cpu = Cpu::new(rom, self.seed as u64);
+        cpu.speed = self.cpu.speed;
+        cpu.quirks = self.cpu.quirks;
+        self.cpu = cpu;
+        Ok(())
+    }
+
+    /// Executes a frame's worth of instructions and ticks the timers,
+    /// returning whether the program can go on. Throws if the program does
+    /// something the machine can't carry out.
+    pub fn tick(&mut self) -> Result<bool, JsError> {
+        let result = self.cpu.run_frame();
+        run(result, &self.cpu)
+    }
+
+    /// Executes one instruction, without ticking the timers
+    pub fn step(&mut self) -> Result<bool, JsError> {
+        if !self.cpu.can_run() {
+            return Ok(false);
+        }
+        let result = self.cpu.step();
+        run(result, &self.cpu)
+    }
+
+    /// The 64x32 screen, a byte per pixel, 1 for on and 0 for off, row by
+    /// row from the top left
+    #[wasm_bindgen(js_name = getDisplay)]
+    pub fn get_display(&self) -> Vec<u8> {
+        let display = &self.cpu.display;
+        (0..WIDTH * HEIGHT)
+            .map(|n| display.pixel(n / WIDTH, n % WIDTH) as u8)
+            .collect()
+    }
+
+    /// Holds key `key` (0 to 15) down, other keys are ignored
+    #[wasm_bindgen(js_name = keyDown)]
+    pub fn key_down(&mut self, key: usize) {
+        self.set_key(key, true);
+    }
+
+    #[wasm_bindgen(js_name = keyUp)]
+    pub fn key_up(&mut self, key: usize) {
+        self.set_key(key, false);
+    }
+
+    fn set_key(&mut self, key: usize, down: bool) {
+        if let Some(held) = self.cpu.keypad.get_mut(key) {
+            *held = down;
+        }
+    }
+
+    /// Instructions per frame, 10 by default
+    #[wasm_bindgen(getter)]
+    pub fn speed(&self) -> usize {
+        self.cpu.speed
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_speed(&mut self, speed: usize) {
+        self.cpu.speed = speed;
+    }
+
+    /// Whether the buzzer sounds
+    #[wasm_bindgen(getter)]
+    pub fn sound(&self) -> bool {
+        self.cpu.sound_timer > 0
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn halted(&self) -> bool {
+        !self.cpu.can_run()
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}